@@ -0,0 +1,114 @@
+use crate::parser::{Expression, ExpressionKind};
+
+/// A function that evaluates some of its arguments with the columns of a data frame in scope, which is called data masking.
+///
+/// In `filter(kbd, Speed > 100)`, `Speed` refers to the column of `kbd`, not to a variable named `Speed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataMasking {
+    pub package: &'static str,
+    pub function: &'static str,
+    /// The name of the parameter that takes the data frame, which is the first parameter.
+    /// `None` if the data frame is given elsewhere, e. g. `aes()` is evaluated with the data of `ggplot()`.
+    pub data: Option<&'static str>,
+}
+
+const fn masking(
+    package: &'static str,
+    function: &'static str,
+    data: Option<&'static str>,
+) -> DataMasking {
+    DataMasking {
+        package,
+        function,
+        data,
+    }
+}
+
+/// The data-masking functions of base R and the tidyverse that Tractus knows about.
+pub const DATA_MASKING_FUNCTIONS: &[DataMasking] = &[
+    masking("base", "with", Some("data")),
+    masking("base", "within", Some("data")),
+    masking("base", "subset", Some("x")),
+    masking("base", "transform", Some("_data")),
+    masking("dplyr", "filter", Some(".data")),
+    masking("dplyr", "select", Some(".data")),
+    masking("dplyr", "mutate", Some(".data")),
+    masking("dplyr", "transmute", Some(".data")),
+    masking("dplyr", "summarise", Some(".data")),
+    masking("dplyr", "summarize", Some(".data")),
+    masking("dplyr", "reframe", Some(".data")),
+    masking("dplyr", "arrange", Some(".data")),
+    masking("dplyr", "group_by", Some(".data")),
+    masking("dplyr", "rename", Some(".data")),
+    masking("dplyr", "relocate", Some(".data")),
+    masking("dplyr", "distinct", Some(".data")),
+    masking("dplyr", "count", Some("x")),
+    masking("dplyr", "pull", Some(".data")),
+    masking("dplyr", "slice_min", Some(".data")),
+    masking("dplyr", "slice_max", Some(".data")),
+    masking("tidyr", "pivot_longer", Some("data")),
+    masking("tidyr", "pivot_wider", Some("data")),
+    masking("tidyr", "separate", Some("data")),
+    masking("tidyr", "unite", Some("data")),
+    masking("tidyr", "drop_na", Some("data")),
+    masking("tidyr", "fill", Some("data")),
+    masking("tidyr", "nest", Some(".data")),
+    masking("tidyr", "unnest", Some("data")),
+    masking("ggplot2", "aes", None),
+    masking("ggplot2", "vars", None),
+];
+
+/// Looks up the called `function`, e. g. `filter` or `dplyr::filter`, in the `DATA_MASKING_FUNCTIONS`.
+///
+/// A function named with its package only matches that package, so `stats::filter` does not mask its arguments.
+pub fn data_masking(function: &Expression) -> Option<&'static DataMasking> {
+    let (package, name) = match &function.kind {
+        ExpressionKind::Variable(name) => (None, name),
+        ExpressionKind::Namespaced { package, name, .. } => (Some(package), name),
+        _ => return None,
+    };
+    DATA_MASKING_FUNCTIONS.iter().find(|masking| {
+        masking.function == name && package.is_none_or(|package| package == masking.package)
+    })
+}
+
+impl DataMasking {
+    /// Returns the index of the argument that is the data frame, which is matched by name or else is the first unnamed argument.
+    ///
+    /// Pipes need to be desugared first, such that the data frame of `kbd %>% filter(Speed > 100)` is an argument.
+    pub fn data_argument<T>(&self, args: &[(Option<String>, T)]) -> Option<usize> {
+        let data = self.data?;
+        args.iter()
+            .position(|(name, _)| name.as_deref() == Some(data))
+            .or_else(|| args.iter().position(|(name, _)| name.is_none()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::{namespaced, variable};
+
+    #[test]
+    fn matches_packages() {
+        assert_eq!(
+            Some(".data"),
+            data_masking(&variable!("filter")).unwrap().data
+        );
+        assert!(data_masking(&namespaced!("dplyr", "filter", false)).is_some());
+        assert_eq!(None, data_masking(&namespaced!("stats", "filter", false)));
+        assert_eq!(None, data_masking(&variable!("lm")));
+    }
+
+    #[test]
+    fn finds_data_argument() {
+        let filter = data_masking(&variable!("filter")).unwrap();
+        let args = vec![(None, 1), (Some(".data".to_string()), 2), (None, 3)];
+        assert_eq!(Some(1), filter.data_argument(&args));
+        assert_eq!(Some(0), filter.data_argument(&args[2..]));
+        let aes = data_masking(&variable!("aes")).unwrap();
+        assert_eq!(None, aes.data_argument(&args));
+    }
+}
@@ -1,16 +1,36 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, VecDeque};
 
 use petgraph;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use crate::parser::{Expression, RIdentifier, Statement, StatementId, Statements};
+use crate::data_masking::data_masking;
+use crate::parser::{
+    desugar_magrittr, desugar_pipe, fold_expression, pipe_assignment_target, walk_expression,
+    Expression, ExpressionKind, Fold, RIdentifier, Statement, StatementId, StatementKind,
+    Statements, Visit,
+};
 
 /// A graph modelling dependencies between statement as a graph of `StatementId`s with the variable names as edges.
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct DependencyGraph {
     graph: Graph,
     variables: VariableMap,
+    /// The definitions in `variables` that only happen on some paths through the statement, e. g. in a loop body.
+    #[serde(default)]
+    conditional: VariableMap,
+    /// The variables that the functions defined by a statement use from outside, by that statement.
+    #[serde(default)]
+    functions: HashMap<StatementId, Vec<Dependency>>,
+    /// The columns of data frames that a statement refers to, see `data_masking`.
+    #[serde(default)]
+    columns: HashMap<StatementId, Vec<RIdentifier>>,
+    /// The variables a statement uses from earlier statements, which are linked to their definitions by `uses`.
+    #[serde(default)]
+    uses: HashMap<StatementId, Vec<Dependency>>,
+    /// The largest id inserted so far, such that statements inserted before it are linked to later statements as well.
+    #[serde(default)]
+    last: Option<StatementId>,
 }
 
 // Helper type definitions
@@ -18,10 +38,42 @@ type NodeIndexType = petgraph::graph::DefaultIx;
 pub type NodeIndex = petgraph::graph::NodeIndex<NodeIndexType>;
 #[derive(Serialize, Default, Deserialize, Debug)]
 struct Graph {
-    graph: petgraph::Graph<StatementId, Variable, petgraph::Directed, NodeIndexType>,
+    graph: petgraph::Graph<StatementId, Dependency, petgraph::Directed, NodeIndexType>,
     ids: HashMap<StatementId, NodeIndex>,
 }
 type Variable = String;
+/// A parameter of a function with its default value.
+type Parameter = (RIdentifier, Option<Expression>);
+
+/// The variable one statement uses from another.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct Dependency {
+    variable: Variable,
+    /// The part of the variable that the using statement changes, e. g. `kbd$logSpeed` or `levels(kbd$Layout)`.
+    changed: Option<String>,
+    /// Whether the variable is only called as a function, e. g. `analyze` in `analyze(kbd)`.
+    #[serde(default)]
+    called: bool,
+    /// Whether the name might refer to a column of a data frame instead, e. g. `threshold` in `subset(kbd, Speed > threshold)`.
+    /// Such a name only uses the variable if the code defines it before.
+    #[serde(default)]
+    masked: bool,
+}
+
+/// A variable used by a statement, linked to the statements whose definitions of it may reach the use.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Use {
+    pub variable: RIdentifier,
+    /// The part of the variable that the statement changes, e. g. `kbd$logSpeed` in `kbd$logSpeed <- log(kbd$Speed)`.
+    pub changed: Option<String>,
+    /// Whether the variable is only called as a function, e. g. `analyze` in `analyze(kbd)`.
+    pub called: bool,
+    /// Whether the name might refer to a column of a data frame instead, see `data_masking`.
+    pub masked: bool,
+    /// The statements that may have defined the value that is used, see `reaching_definitions`.
+    /// Empty if the code does not define the variable before, e. g. because it comes from a package.
+    pub definitions: Vec<StatementId>,
+}
 
 // We use a custom Graph type, since petgraph::GraphMap is not serializable out of the box.
 // This structure manages the converting between `StatementId`s and internal `NodexIndex`.
@@ -33,17 +85,26 @@ impl Graph {
         }
     }
 
+    /// Adds a node for the `StatementId`, unless it already exists, e. g. when an `if` statement is merged with its `else`.
     fn add_node(&mut self, content: StatementId) {
-        let node_id = self.graph.add_node(content);
-        self.ids.insert(content, node_id);
+        if !self.ids.contains_key(&content) {
+            let node_id = self.graph.add_node(content);
+            self.ids.insert(content, node_id);
+        }
     }
 
-    fn add_edge(&mut self, from: StatementId, to: StatementId, content: Variable) {
+    fn add_edge(&mut self, from: StatementId, to: StatementId, content: Dependency) {
         let from_idx = self.ids[&from];
         let to_idx = self.ids[&to];
         self.graph.add_edge(from_idx, to_idx, content);
     }
 
+    fn remove_incoming_edges(&mut self, id: StatementId) {
+        let node_id = self.ids[&id];
+        self.graph
+            .retain_edges(|graph, edge| graph.edge_endpoints(edge).unwrap().1 != node_id);
+    }
+
     fn neighbors_directed(
         &self,
         id: StatementId,
@@ -57,11 +118,25 @@ impl Graph {
             .collect()
     }
 
+    /// Returns the nodes that can be reached from `id` by following the edges in the `direction`, ordered by id.
+    fn reachable(&self, id: StatementId, direction: petgraph::Direction) -> Vec<StatementId> {
+        let mut reached = BTreeSet::new();
+        let mut pending = vec![id];
+        while let Some(next) = pending.pop() {
+            for neighbor in self.neighbors_directed(next, direction) {
+                if reached.insert(neighbor) {
+                    pending.push(neighbor);
+                }
+            }
+        }
+        reached.into_iter().collect()
+    }
+
     fn serialize_nodes(&self) -> Vec<StatementId> {
         self.graph.raw_nodes().iter().map(|n| n.weight).collect()
     }
 
-    fn serialize_edges(&self) -> Vec<(StatementId, StatementId, Variable)> {
+    fn serialize_edges(&self) -> Vec<(StatementId, StatementId, Variable, Option<String>)> {
         self.graph
             .raw_edges()
             .iter()
@@ -69,7 +144,8 @@ impl Graph {
                 (
                     *self.graph.node_weight(e.source()).unwrap(),
                     *self.graph.node_weight(e.target()).unwrap(),
-                    e.weight.clone(),
+                    e.weight.variable.clone(),
+                    e.weight.changed.clone(),
                 )
             })
             .collect()
@@ -81,6 +157,11 @@ impl DependencyGraph {
         DependencyGraph {
             graph: Graph::new(),
             variables: VariableMap::new(),
+            conditional: VariableMap::new(),
+            functions: HashMap::new(),
+            columns: HashMap::new(),
+            uses: HashMap::new(),
+            last: None,
         }
     }
 
@@ -109,53 +190,193 @@ impl DependencyGraph {
 
     /// Inserts a single `StatementId` into the graph.
     ///
+    /// Control flow statements are inserted as a whole, using and defining the variables of their conditions and bodies.
+    /// A statement that uses a function defined by the code also uses the variables of the function's body.
+    /// The statements are linked by their ids, so statements can be inserted in any order.
     /// Requires that the statment corresponding to the id can be looked up in `stmts`.
     pub fn insert(&mut self, id: StatementId, statement: &Statement) {
-        use Statement::*;
-        match statement {
-            Expression(expression) => {
-                self.register_dependencies(id, expression);
-            }
-            Assignment(left, additional, right) => {
-                let first = [left.clone()];
-                let rest = additional.iter();
-                let assigned = first.iter().chain(rest);
-                self.register_dependencies(id, right);
-                for variable in assigned {
-                    match variable.extract_variable_name() {
-                        Some(name) => self.variables.push(name, id),
-                        None => panic!(
-                        "Could not find a variable in {}, in the left side of the assignment {}.",
-                        variable, statement
-                    ),
-                    };
+        // An `if` statement is inserted again when it is merged with its `else`, which might change its dataflow.
+        let mut defined: Vec<RIdentifier> = Vec::new();
+        if self.graph.ids.contains_key(&id) {
+            defined.extend(
+                self.variables
+                    .0
+                    .iter()
+                    .filter(|(_, ids)| ids.contains(&id))
+                    .map(|(variable, _)| variable.clone()),
+            );
+            self.graph.remove_incoming_edges(id);
+            self.variables.remove(id);
+            self.conditional.remove(id);
+            self.functions.remove(&id);
+            self.columns.remove(&id);
+            self.uses.remove(&id);
+        }
+
+        let dataflow = dataflow(statement);
+        self.graph.add_node(id);
+        if !dataflow.uses.is_empty() {
+            self.uses.insert(id, dataflow.uses);
+        }
+        self.link(id);
+        if let Some((params, body)) = defined_function(statement) {
+            self.functions.insert(id, free_variables(params, body));
+        }
+        let mut columns: Vec<RIdentifier> = Vec::new();
+        for column in statement
+            .expressions()
+            .into_iter()
+            .flat_map(extract_columns)
+        {
+            if !columns.contains(&column) {
+                columns.push(column);
+            }
+        }
+        if !columns.is_empty() {
+            self.columns.insert(id, columns);
+        }
+        defined.extend(dataflow.definitions.iter().cloned());
+        defined.extend(dataflow.conditional.iter().cloned());
+        for variable in dataflow.definitions {
+            self.variables.push(variable, id);
+        }
+        for variable in dataflow.conditional {
+            self.variables.push(variable.clone(), id);
+            self.conditional.push(variable, id);
+        }
+
+        // The definitions may reach statements that were inserted before, but come after this statement.
+        if self.last.is_some_and(|last| id < last) {
+            let mut later: Vec<StatementId> = self
+                .uses
+                .keys()
+                .filter(|other| **other > id)
+                .filter(|other| {
+                    self.uses(**other)
+                        .iter()
+                        .any(|used| defined.contains(&used.variable))
+                })
+                .copied()
+                .collect();
+            later.sort_unstable();
+            for other in later {
+                self.graph.remove_incoming_edges(other);
+                self.link(other);
+            }
+        }
+        self.last = self.last.max(Some(id));
+    }
+
+    /// Adds the edges from the definitions of the variables that the statement with `id` uses.
+    fn link(&mut self, id: StatementId) {
+        for used in self.uses(id) {
+            let dependency = Dependency {
+                variable: used.variable,
+                changed: used.changed,
+                called: used.called,
+                masked: used.masked,
+            };
+            for definition in used.definitions {
+                self.graph.add_edge(definition, id, dependency.clone());
+            }
+        }
+    }
+
+    /// Returns the variables that the statement with `id` uses, each with the statements that may have defined its value.
+    ///
+    /// A statement that calls a function defined by the code also uses the variables the function uses from outside,
+    /// which follow the variables used by the statement itself.
+    /// Names in data-masked arguments are only returned if a definition of the variable reaches them,
+    /// since they refer to columns otherwise.
+    pub fn uses(&self, id: StatementId) -> Vec<Use> {
+        let mut dependencies = self.uses.get(&id).cloned().unwrap_or_default();
+        let mut uses = Vec::new();
+        let mut index = 0;
+        while let Some(dependency) = dependencies.get(index).cloned() {
+            let definitions = self.reaching_definitions(&dependency.variable, id);
+            // The function's variables are looked up when it is called.
+            for definition in &definitions {
+                for used in self.functions.get(definition).into_iter().flatten() {
+                    if dependencies
+                        .iter()
+                        .all(|other| other.variable != used.variable)
+                    {
+                        dependencies.push(Dependency {
+                            variable: used.variable.clone(),
+                            changed: None,
+                            called: false,
+                            masked: used.masked,
+                        });
+                    }
                 }
             }
-            TailComment(statement, _) => self.insert(id, statement),
-            // The following cannot have dependencies
-            Empty => self.graph.add_node(id),
-            Comment(_) => self.graph.add_node(id),
-            If(_, _, _) => self.graph.add_node(id),
-            While(_, _) => self.graph.add_node(id),
-            For(_, _, _) => self.graph.add_node(id),
-            Library(_) => self.graph.add_node(id),
+            index += 1;
+            if dependency.masked && definitions.is_empty() {
+                continue;
+            }
+            uses.push(Use {
+                variable: dependency.variable,
+                changed: dependency.changed,
+                called: dependency.called,
+                masked: dependency.masked,
+                definitions,
+            });
         }
+        uses
     }
 
-    /// Analyzes the expression for dependencies and adds the `StatementId` into the graph along with edges for the dependencies.
+    /// Returns the statements before the statement with `id` whose values of the `variable` may reach it:
+    /// the last statement that always defines the variable, and the statements after that one that define it conditionally.
+    /// The latest statement comes first.
     ///
-    /// Assumes that `expression` is from a statement with the passed `id`.
-    fn register_dependencies(&mut self, id: StatementId, expression: &Expression) {
-        let dependencies = extract_dependencies(&expression);
-        self.graph.add_node(id);
-        for dependency in dependencies {
-            if let Some(parent) = self.variables.get(&dependency) {
-                self.graph.add_edge(*parent, id, dependency);
+    /// If no definition is known, the variable might still be valid,
+    /// e. g. if it is a library function that wasn't explicitly declared in the code.
+    pub fn reaching_definitions(&self, variable: &str, id: StatementId) -> Vec<StatementId> {
+        let definitions = self
+            .variables
+            .get_all(variable)
+            .map_or(&[][..], Vec::as_slice);
+        let mut reaching = Vec::new();
+        for definition in definitions.iter().rev().filter(|other| **other < id) {
+            reaching.push(*definition);
+            if !self.defines_conditionally(*definition, variable) {
+                break;
             }
-            // Else, we do not know the variable. But this might still be valid,
-            // e. g. if it is a library function that wasn't explicitly declared in the code.
-            // Therefore, we simply ignore this in the dependency graph.
         }
+        reaching
+    }
+
+    /// Returns the statements that define the `variable`, always or conditionally, in the order of their ids.
+    pub fn defining_statements(&self, variable: &str) -> &[StatementId] {
+        self.variables.get_all(variable).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the variables that the statement with `id` always defines, sorted by name.
+    pub fn definitions(&self, id: StatementId) -> Vec<&RIdentifier> {
+        let mut definitions: Vec<&RIdentifier> = self
+            .variables
+            .0
+            .iter()
+            .filter(|(variable, ids)| {
+                ids.contains(&id) && !self.defines_conditionally(id, variable)
+            })
+            .map(|(variable, _)| variable)
+            .collect();
+        definitions.sort_unstable();
+        definitions
+    }
+
+    /// Whether the statement with `id` defines the `variable` only on some paths, e. g. in the body of a loop.
+    fn defines_conditionally(&self, id: StatementId, variable: &str) -> bool {
+        self.conditional
+            .get_all(variable)
+            .is_some_and(|ids| ids.contains(&id))
+    }
+
+    /// Returns the columns of data frames that the statement with `id` refers to in data-masked arguments,
+    /// e. g. `Layout` and `Speed` in `kbd %>% group_by(Layout) %>% summarise(m = mean(Speed))`.
+    pub fn columns(&self, id: StatementId) -> &[RIdentifier] {
+        self.columns.get(&id).map_or(&[], Vec::as_slice)
     }
 
     /// Returns all `StatementId`s that assign to a variable used by the statement with `id`.
@@ -164,6 +385,76 @@ impl DependencyGraph {
             .neighbors_directed(id, petgraph::Direction::Incoming)
     }
 
+    /// Returns all `StatementId`s that use a variable assigned by the statement with `id`.
+    pub fn children(&self, id: StatementId) -> Vec<StatementId> {
+        self.graph
+            .neighbors_directed(id, petgraph::Direction::Outgoing)
+    }
+
+    /// Returns the statements that the statement with `id` depends on directly or indirectly, ordered by id.
+    /// These are the statements that need to run before it to reproduce its result.
+    pub fn ancestors(&self, id: StatementId) -> Vec<StatementId> {
+        self.graph.reachable(id, petgraph::Direction::Incoming)
+    }
+
+    /// Returns the statements that depend on the statement with `id` directly or indirectly, ordered by id.
+    pub fn descendants(&self, id: StatementId) -> Vec<StatementId> {
+        self.graph.reachable(id, petgraph::Direction::Outgoing)
+    }
+
+    /// Returns a shortest chain of dependencies that leads from the statement `from` to the statement `to`, including both,
+    /// e. g. from loading the data to an analysis of it.
+    /// Returns `None` if `to` does not depend on `from`.
+    pub fn path_between(&self, from: StatementId, to: StatementId) -> Option<Vec<StatementId>> {
+        let mut previous = HashMap::new();
+        let mut pending = VecDeque::from(vec![from]);
+        while let Some(next) = pending.pop_front() {
+            if next == to {
+                let mut path = vec![to];
+                while let Some(before) = previous.get(&path[path.len() - 1]) {
+                    path.push(*before);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for child in self.children(next) {
+                if child != from && !previous.contains_key(&child) {
+                    previous.insert(child, next);
+                    pending.push_back(child);
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns all statements such that each statement comes after the statements it depends on.
+    /// Statements that do not depend on each other keep the order of their ids.
+    pub fn topological_order(&self) -> Vec<StatementId> {
+        let mut missing: HashMap<StatementId, usize> = self
+            .graph
+            .serialize_nodes()
+            .into_iter()
+            .map(|id| (id, self.parents(id).len()))
+            .collect();
+        let mut ready: BTreeSet<StatementId> = missing
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(id, _)| *id)
+            .collect();
+        let mut order = Vec::new();
+        while let Some(id) = ready.pop_first() {
+            order.push(id);
+            for child in self.children(id) {
+                let count = missing.get_mut(&child).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    ready.insert(child);
+                }
+            }
+        }
+        order
+    }
+
     /// If the statement behind `id` contains an expression,
     /// constructs a new `Expression` where all variables used by that expression are inlined.
     /// Otherwise returns `None`.
@@ -176,6 +467,16 @@ impl DependencyGraph {
             .map(|exp| self.inline_exp(exp, id, stmts))
     }
 
+    /// Like `inline_id`, but inlines all expressions evaluated by the statement, see `Statement::expressions`.
+    pub fn inline_expressions<M>(&self, id: StatementId, stmts: &Statements<M>) -> Vec<Expression> {
+        let statement = &stmts[id].0;
+        statement
+            .expressions()
+            .into_iter()
+            .map(|exp| self.inline_exp(exp, id, stmts))
+            .collect()
+    }
+
     /// Constructs a new `Expression` where all variables used are inlined.
     ///
     /// Requires that the expression comes from a statement that can be looked up with `stmt_id` in `stmts`.
@@ -185,116 +486,579 @@ impl DependencyGraph {
         stmt_id: StatementId,
         stmts: &Statements<M>,
     ) -> Expression {
-        use Expression::*;
-        match exp {
-            Constant(constant) => Constant(constant.clone()),
-            Variable(name) => {
-                if let Some(exps) = self.variables.get_all(name) {
-                    if let Some(replacement) = exps.iter().filter(|other| **other < stmt_id).last()
-                    {
-                        return self.inline_id(*replacement, stmts).unwrap();
-                    }
-                }
-                Variable(name.clone())
-            }
-            Call(exp, args) => Call(
-                Box::new(self.inline_exp(exp, stmt_id, stmts)),
-                args.iter()
-                    .map(|(name, exp)| (name.clone(), self.inline_exp(exp, stmt_id, stmts)))
-                    .collect(),
-            ),
-            Column(left, right) => Column(
-                Box::new(self.inline_exp(left, stmt_id, stmts)),
-                Box::new(self.inline_exp(right, stmt_id, stmts)),
-            ),
-            Index(left, right) => Index(
-                Box::new(self.inline_exp(left, stmt_id, stmts)),
-                right
-                    .iter()
-                    .map(|maybe_exp| {
-                        maybe_exp
-                            .as_ref()
-                            .map(|exp| self.inline_exp(exp, stmt_id, stmts))
-                    })
-                    .collect(),
-            ),
-            ListIndex(left, right) => ListIndex(
-                Box::new(self.inline_exp(left, stmt_id, stmts)),
-                right
-                    .iter()
-                    .map(|maybe_exp| {
-                        maybe_exp
-                            .as_ref()
-                            .map(|exp| self.inline_exp(exp, stmt_id, stmts))
-                    })
-                    .collect(),
-            ),
-            OneSidedFormula(formula) => {
-                OneSidedFormula(Box::new(self.inline_exp(formula, stmt_id, stmts)))
-            }
-            TwoSidedFormula(left, right) => TwoSidedFormula(
-                Box::new(self.inline_exp(left, stmt_id, stmts)),
-                Box::new(self.inline_exp(right, stmt_id, stmts)),
-            ),
-            Function(args, body) => Function(
-                args.iter()
-                    .map(|(name, maybe_expression)| {
-                        (
-                            name.clone(),
-                            maybe_expression
-                                .clone()
-                                .map(|expression| self.inline_exp(&expression, stmt_id, stmts)),
-                        )
-                    })
-                    .collect(),
-                body.to_vec(), // TODO: Investigate whether statements can be analyzed.
-            ),
-            Prefix(operator, exp) => Prefix(
-                operator.clone(),
-                Box::new(self.inline_exp(exp, stmt_id, stmts)),
-            ),
-            Infix(operator, left, right) => Infix(
-                operator.clone(),
-                Box::new(self.inline_exp(left, stmt_id, stmts)),
-                Box::new(self.inline_exp(right, stmt_id, stmts)),
-            ),
+        Inliner {
+            graph: self,
+            stmt_id,
+            stmts,
+            functions: Vec::new(),
         }
+        .fold_expression(exp.clone())
     }
 
     pub fn as_json(&self) -> serde_json::Value {
         json!({
             "nodes": self.graph.serialize_nodes(),
             "edges": self.graph.serialize_edges(),
-            "variables": self.variables
+            "variables": self.variables,
+            "columns": self.columns,
+            "ancestors": self
+                .graph
+                .serialize_nodes()
+                .into_iter()
+                .map(|id| (id, self.ancestors(id)))
+                .collect::<HashMap<StatementId, Vec<StatementId>>>(),
         })
     }
 }
 
-/// Returns the names of the variables used in the `expression`.
-fn extract_dependencies(expression: &Expression) -> Vec<RIdentifier> {
-    use Expression::*;
-    match expression {
-        Variable(name) => vec![name.clone()],
-        Call(_, arguments) => arguments
+/// Replaces the variables in an expression with the expressions assigned to them before the statement `stmt_id`,
+/// and calls of simple functions with their bodies.
+struct Inliner<'a, M> {
+    graph: &'a DependencyGraph,
+    stmt_id: StatementId,
+    stmts: &'a Statements<M>,
+    /// The functions whose bodies are being inlined, which are not inlined again to avoid infinite recursion.
+    functions: Vec<RIdentifier>,
+}
+
+impl<M> Inliner<'_, M> {
+    /// If `name` refers to a function whose body is a single expression, e. g. `function(d) t.test(Speed ~ Layout, data = d)`,
+    /// returns the function's parameters and that expression.
+    fn simple_function(&self, name: &str) -> Option<(Vec<Parameter>, Expression)> {
+        let id = match self.graph.reaching_definitions(name, self.stmt_id)[..] {
+            [id] => id,
+            // Functions that are defined conditionally might have different bodies.
+            _ => return None,
+        };
+        let (params, body) = defined_function(&self.stmts[id].0)?;
+        let mut body = body
             .iter()
-            .flat_map(|(_, exp)| extract_dependencies(exp))
-            .collect(),
-        Column(left, _) => extract_dependencies(left),
-        Index(left, _) => extract_dependencies(left),
-        ListIndex(left, _) => extract_dependencies(left),
-        Prefix(_, exp) => extract_dependencies(exp),
-        Infix(_, left, right) => {
-            let mut deps = extract_dependencies(left);
-            deps.append(&mut extract_dependencies(right));
-            deps
-        }
-        OneSidedFormula(exp) => extract_dependencies(exp),
-        TwoSidedFormula(left, right) => {
-            let mut deps = extract_dependencies(left);
-            deps.append(&mut extract_dependencies(right));
-            deps
-        }
-        Function(_, _) | Constant(_) => Vec::new(),
+            .filter(|stmt| !matches!(stmt.kind, StatementKind::Empty | StatementKind::Comment(_)));
+        match (body.next(), body.next()) {
+            // The arguments passed on with `...` cannot be matched.
+            (Some(stmt), None) if params.iter().all(|(param, _)| param != "...") => {
+                Some((params.to_vec(), stmt.expression()?.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the latest statement before `stmt_id` that assigns the whole `variable`.
+    ///
+    /// A modification like `x$a <- 1` only assigns part of the variable, which is otherwise defined before.
+    fn assignment(&self, variable: &str) -> Option<StatementId> {
+        let mut id = self.stmt_id;
+        loop {
+            id = *self.graph.reaching_definitions(variable, id).first()?;
+            if !modifies(&self.stmts[id].0, variable) {
+                return Some(id);
+            }
+        }
+    }
+
+    /// Replaces the call of a simple function with the function's body, where the parameters are replaced by the arguments.
+    fn inline_call(
+        &mut self,
+        name: &str,
+        args: &[(Option<RIdentifier>, Expression)],
+    ) -> Option<Expression> {
+        if self.functions.iter().any(|other| other == name) {
+            return None;
+        }
+        let (params, body) = self.simple_function(name)?;
+        let arguments = match_arguments(&params, args)?;
+        let body = Substitution(arguments).fold_expression(body);
+        self.functions.push(name.to_string());
+        let inlined = self.fold_expression(body);
+        self.functions.pop();
+        Some(inlined)
+    }
+}
+
+impl<M> Fold for Inliner<'_, M> {
+    fn fold_expression(&mut self, exp: Expression) -> Expression {
+        // Piped calls are inlined like the nested calls they stand for.
+        match &exp.kind {
+            ExpressionKind::Pipe(left, right) => {
+                return self.fold_expression(desugar_pipe(left, right))
+            }
+            ExpressionKind::Infix(op, left, right) => {
+                if let Some(call) = desugar_magrittr(op, left, right) {
+                    return self.fold_expression(call);
+                }
+            }
+            _ => {}
+        }
+        if let ExpressionKind::Call(function, args) = &exp.kind {
+            if let ExpressionKind::Variable(name) = &function.kind {
+                if let Some(inlined) = self.inline_call(name, args) {
+                    return inlined;
+                }
+                // Other functions are called by their name.
+                let args = args
+                    .iter()
+                    .map(|(name, arg)| (name.clone(), self.fold_expression(arg.clone())))
+                    .collect();
+                return Expression::new(ExpressionKind::Call(function.clone(), args), exp.span);
+            }
+        }
+        if let ExpressionKind::Variable(name) = &exp.kind {
+            // Control flow statements define variables without a single expression that could be inlined.
+            if let Some(inlined) = self
+                .assignment(name)
+                .and_then(|id| self.graph.inline_id(id, self.stmts))
+            {
+                return inlined;
+            }
+        }
+        fold_expression(self, exp)
+    }
+
+    // TODO: Investigate whether the statements in function bodies can be analyzed.
+    fn fold_statement(&mut self, stmt: Statement) -> Statement {
+        stmt
+    }
+}
+
+/// Matches the arguments of a call to the parameters of a function, first by name and then by position.
+/// Parameters without an argument take their default value.
+///
+/// Returns `None` if there are arguments that do not match any parameter.
+fn match_arguments(
+    params: &[Parameter],
+    args: &[(Option<RIdentifier>, Expression)],
+) -> Option<HashMap<RIdentifier, Expression>> {
+    let mut arguments = HashMap::new();
+    for (name, arg) in args {
+        if let Some(name) = name {
+            params.iter().find(|(param, _)| param == name)?;
+            arguments.insert(name.clone(), arg.clone());
+        }
+    }
+    let mut unnamed = args.iter().filter(|(name, _)| name.is_none());
+    for (param, _) in params {
+        if !arguments.contains_key(param) {
+            if let Some((_, arg)) = unnamed.next() {
+                arguments.insert(param.clone(), arg.clone());
+            }
+        }
+    }
+    if unnamed.next().is_some() {
+        return None;
+    }
+    // Default values may refer to the other parameters.
+    let defaults: Vec<(RIdentifier, Expression)> = params
+        .iter()
+        .filter(|(param, _)| !arguments.contains_key(param))
+        .filter_map(|(param, default)| Some((param.clone(), default.clone()?)))
+        .collect();
+    for (param, default) in defaults {
+        let default = Substitution(arguments.clone()).fold_expression(default);
+        arguments.insert(param, default);
+    }
+    Some(arguments)
+}
+
+/// Replaces the variables in an expression with the given expressions, e. g. the parameters of a function with its arguments.
+struct Substitution(HashMap<RIdentifier, Expression>);
+
+impl Fold for Substitution {
+    fn fold_expression(&mut self, exp: Expression) -> Expression {
+        match &exp.kind {
+            ExpressionKind::Variable(name) => self.0.get(name).cloned().unwrap_or(exp),
+            // The parameters of nested functions shadow the variables.
+            ExpressionKind::Function(_, _, _) => exp,
+            _ => fold_expression(self, exp),
+        }
+    }
+}
+
+/// The variables a statement uses and defines.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Dataflow {
+    /// The variables used before the statement defines them, such that their values come from earlier statements.
+    uses: Vec<Dependency>,
+    /// The variables the statement defines whenever it runs.
+    definitions: Vec<RIdentifier>,
+    /// The variables the statement defines only on some paths, e. g. in one branch of an `if` or in the body of a loop.
+    conditional: Vec<RIdentifier>,
+}
+
+impl Dataflow {
+    /// Adds the variables used in the `expression`, followed by the functions it calls by name
+    /// and the names in data-masked arguments, unless they have been defined or used before.
+    /// The variables assigned in arguments, e. g. `y` in `f(x -> y)`, are defined afterwards.
+    fn use_expression(&mut self, expression: &Expression) {
+        let mut dependencies = Dependencies::default();
+        dependencies.visit_expression(expression);
+        let variables = dependencies
+            .variables
+            .into_iter()
+            .map(|v| (v, false, false));
+        let functions = dependencies.functions.into_iter().map(|f| (f, true, false));
+        let masked = dependencies
+            .masked_variables
+            .into_iter()
+            .map(|v| (v, false, true));
+        for (variable, called, masked) in variables.chain(functions).chain(masked) {
+            self.add_use(Dependency {
+                variable,
+                changed: None,
+                called,
+                masked,
+            });
+        }
+        for variable in dependencies.assigned {
+            self.define(variable);
+        }
+    }
+
+    /// Adds the use of a variable, unless it has been defined before.
+    /// A variable is used once, but if one of its uses changes part of it, that change is kept,
+    /// and it is only considered called or masked if all of its uses are.
+    fn add_use(&mut self, dependency: Dependency) {
+        if self.definitions.contains(&dependency.variable) {
+            return;
+        }
+        match self
+            .uses
+            .iter_mut()
+            .find(|other| other.variable == dependency.variable)
+        {
+            Some(other) => {
+                if other.changed.is_none() {
+                    other.changed = dependency.changed;
+                }
+                other.called &= dependency.called;
+                other.masked &= dependency.masked;
+            }
+            None => self.uses.push(dependency),
+        }
+    }
+
+    /// Changing part of a variable, e. g. `kbd$logSpeed <- ...` or `levels(kbd$Layout) <- ...`,
+    /// reads the variable, modifies it and assigns the result to the variable again.
+    fn modify(&mut self, target: &Expression) {
+        if !is_modification(target) {
+            return;
+        }
+        if let Some(variable) = target.extract_variable_name() {
+            self.add_use(Dependency {
+                variable,
+                changed: Some(target.to_string()),
+                called: false,
+                masked: false,
+            });
+        }
+        for argument in target_arguments(target) {
+            self.use_expression(argument);
+        }
+    }
+
+    fn define(&mut self, variable: RIdentifier) {
+        self.conditional.retain(|other| *other != variable);
+        if !self.definitions.contains(&variable) {
+            self.definitions.push(variable);
+        }
+    }
+
+    fn define_conditionally(&mut self, variable: RIdentifier) {
+        if !self.definitions.contains(&variable) && !self.conditional.contains(&variable) {
+            self.conditional.push(variable);
+        }
+    }
+
+    /// Continues with the dataflow of the code that runs afterwards.
+    fn then(&mut self, next: Dataflow) {
+        next.uses.into_iter().for_each(|d| self.add_use(d));
+        next.definitions.into_iter().for_each(|v| self.define(v));
+        next.conditional
+            .into_iter()
+            .for_each(|v| self.define_conditionally(v));
+    }
+
+    /// Makes all definitions conditional, e. g. for the body of a loop, which might not run at all.
+    fn conditionally(self) -> Dataflow {
+        let mut conditional = self.definitions;
+        conditional.extend(self.conditional);
+        Dataflow {
+            uses: self.uses,
+            definitions: Vec::new(),
+            conditional,
+        }
+    }
+}
+
+/// Analyzes which variables the statement uses and defines, descending into the bodies of control flow.
+fn dataflow(statement: &Statement) -> Dataflow {
+    use StatementKind::*;
+    let mut dataflow = Dataflow::default();
+    match &statement.kind {
+        Expression(expression) => {
+            dataflow.use_expression(expression);
+            // The assignment pipe `kbd %<>% filter(Speed > 0)` assigns its result to the variable it starts with.
+            if let Some(target) = pipe_assignment_target(expression) {
+                dataflow.modify(target);
+                if let Some(name) = target.extract_variable_name() {
+                    dataflow.define(name);
+                }
+            }
+        }
+        Return(Some(expression)) => dataflow.use_expression(expression),
+        Assignment(left, additional, right, _) => {
+            dataflow.use_expression(right);
+            let targets: Vec<_> = std::iter::once(left).chain(additional).collect();
+            for target in &targets {
+                dataflow.modify(target);
+            }
+            // Targets without a variable, e. g. `f() <- 1`, are reported by the parser and define nothing.
+            for name in targets
+                .into_iter()
+                .filter_map(|t| t.assigned_variable_name())
+            {
+                dataflow.define(name);
+            }
+        }
+        TailComment(statement, _) => return self::dataflow(statement),
+        If(condition, then, otherwise) => {
+            dataflow.use_expression(condition);
+            let then = body_dataflow(then);
+            let otherwise = otherwise.as_deref().map(body_dataflow).unwrap_or_default();
+            for dependency in then.uses.into_iter().chain(otherwise.uses) {
+                dataflow.add_use(dependency);
+            }
+            // Only the variables defined in both branches are always defined.
+            for variable in then.definitions.iter().chain(&otherwise.definitions) {
+                if then.definitions.contains(variable) && otherwise.definitions.contains(variable) {
+                    dataflow.define(variable.clone());
+                } else {
+                    dataflow.define_conditionally(variable.clone());
+                }
+            }
+            for variable in then.conditional.into_iter().chain(otherwise.conditional) {
+                dataflow.define_conditionally(variable);
+            }
+        }
+        While(condition, body) => {
+            dataflow.use_expression(condition);
+            dataflow.then(body_dataflow(body).conditionally());
+        }
+        For(variable, range, body) => {
+            dataflow.use_expression(range);
+            // R sets the variable even if the range is empty.
+            if let Some(name) = variable.extract_variable_name() {
+                dataflow.define(name);
+            }
+            dataflow.then(body_dataflow(body).conditionally());
+        }
+        // A `break` may end the loop before all statements of the body have run.
+        Repeat(body) => dataflow.then(body_dataflow(body).conditionally()),
+        Empty | Comment(_) | Break | Next | Return(None) | Library(_) | Unparsed(_) => {}
+    }
+    dataflow
+}
+
+/// Whether the target of an assignment changes only part of a variable, e. g. a column, an element or an attribute.
+fn is_modification(target: &Expression) -> bool {
+    !matches!(
+        target.kind,
+        ExpressionKind::Variable(_) | ExpressionKind::Constant(_)
+    )
+}
+
+/// Returns the expressions in the target of an assignment that are only read, i. e. the indices and the further arguments of replacement functions,
+/// e. g. `i` in `x[i] <- 5` or `n` in `substr(s, 1, n) <- "A"`.
+fn target_arguments(target: &Expression) -> Vec<&Expression> {
+    use ExpressionKind::*;
+    match &target.kind {
+        Column(left, _) | Slot(left, _) => target_arguments(left),
+        Index(left, indices) | ListIndex(left, indices) => {
+            let mut arguments = target_arguments(left);
+            arguments.extend(indices.iter().flatten());
+            arguments
+        }
+        Call(_, args) => match args.split_first() {
+            Some(((_, first), rest)) => {
+                let mut arguments = target_arguments(first);
+                arguments.extend(rest.iter().map(|(_, arg)| arg));
+                arguments
+            }
+            None => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// Whether the statement is an assignment that changes only part of the `variable`.
+fn modifies(statement: &Statement, variable: &str) -> bool {
+    let modifies_variable = |target: &Expression| {
+        is_modification(target) && target.extract_variable_name().as_deref() == Some(variable)
+    };
+    match &statement.kind {
+        StatementKind::Assignment(left, additional, _, _) => std::iter::once(left)
+            .chain(additional)
+            .any(modifies_variable),
+        StatementKind::Expression(expression) => {
+            pipe_assignment_target(expression).is_some_and(modifies_variable)
+        }
+        StatementKind::TailComment(statement, _) => modifies(statement, variable),
+        _ => false,
+    }
+}
+
+/// Analyzes the statements of a body, which run one after another.
+fn body_dataflow(body: &[Statement]) -> Dataflow {
+    body.iter().fold(Dataflow::default(), |mut dataflow, stmt| {
+        dataflow.then(self::dataflow(stmt));
+        dataflow
+    })
+}
+
+/// If the statement assigns a function, e. g. `analyze <- function(d) ...`, returns the function's parameters and body.
+fn defined_function(statement: &Statement) -> Option<(&[Parameter], &[Statement])> {
+    match &statement.kind {
+        StatementKind::Assignment(_, _, right, _) => match &right.kind {
+            ExpressionKind::Function(params, body, _) => Some((params, body)),
+            _ => None,
+        },
+        StatementKind::TailComment(statement, _) => defined_function(statement),
+        _ => None,
+    }
+}
+
+/// Returns the variables that a function uses from outside, which are looked up when the function is called.
+fn free_variables(params: &[Parameter], body: &[Statement]) -> Vec<Dependency> {
+    let mut dataflow = Dataflow::default();
+    for (param, _) in params {
+        dataflow.define(param.clone());
+    }
+    // Default values are evaluated inside the function, where the other parameters are defined.
+    for default in params.iter().filter_map(|(_, default)| default.as_ref()) {
+        dataflow.use_expression(default);
+    }
+    dataflow.then(body_dataflow(body));
+    dataflow
+        .uses
+        .into_iter()
+        .map(|dependency| Dependency {
+            changed: None,
+            called: false,
+            ..dependency
+        })
+        .collect()
+}
+
+/// Returns the names of the columns referred to in the data-masked arguments of the `expression`, e. g. `Speed` in `filter(kbd, Speed > 100)`.
+fn extract_columns(expression: &Expression) -> Vec<RIdentifier> {
+    let mut dependencies = Dependencies::default();
+    dependencies.visit_expression(expression);
+    dependencies.columns
+}
+
+/// Collects the names of the variables used in the visited expressions, and of the functions called by name.
+#[derive(Default)]
+struct Dependencies {
+    variables: Vec<RIdentifier>,
+    functions: Vec<RIdentifier>,
+    /// The names that refer to columns of a data frame, see `data_masking`.
+    columns: Vec<RIdentifier>,
+    /// The names in data-masked arguments that might refer to variables as well, if there is no such column.
+    masked_variables: Vec<RIdentifier>,
+    /// The variables assigned in arguments, e. g. `y` in `f(x -> y)`.
+    assigned: Vec<RIdentifier>,
+    /// Whether the visited expression is evaluated with the columns of a data frame in scope.
+    masked: bool,
+}
+
+impl Dependencies {
+    /// Visits the arguments of a call, where the arguments of data-masking functions refer to columns.
+    fn visit_call(
+        &mut self,
+        function: &Expression,
+        arguments: &[(Option<RIdentifier>, Expression)],
+    ) {
+        if let ExpressionKind::Variable(name) = &function.kind {
+            self.functions.push(name.clone());
+        }
+        let masking = data_masking(function);
+        let data = masking.and_then(|masking| masking.data_argument(arguments));
+        for (index, (_, arg)) in arguments.iter().enumerate() {
+            if masking.is_some() && data != Some(index) {
+                self.visit_with_masking(arg, true);
+            } else {
+                self.visit_expression(arg);
+            }
+        }
+    }
+
+    fn visit_with_masking(&mut self, exp: &Expression, masked: bool) {
+        let outer = std::mem::replace(&mut self.masked, masked);
+        self.visit_expression(exp);
+        self.masked = outer;
+    }
+}
+
+impl Visit for Dependencies {
+    fn visit_expression(&mut self, exp: &Expression) {
+        use ExpressionKind::*;
+        match &exp.kind {
+            Variable(name) if self.masked => {
+                self.columns.push(name.clone());
+                self.masked_variables.push(name.clone());
+            }
+            Variable(name) => self.variables.push(name.clone()),
+            // Functions are usually defined by packages, but might be defined by the code.
+            Call(function, arguments) => self.visit_call(function, arguments),
+            // The pronouns `.data$column` and `.env$variable` tell columns and variables apart explicitly.
+            Column(left, right)
+                if self.masked && matches!(&left.kind, Variable(name) if name == ".data") =>
+            {
+                match &right.kind {
+                    Variable(name) => self.columns.push(name.clone()),
+                    _ => self.visit_expression(right),
+                }
+            }
+            Column(left, right)
+                if self.masked && matches!(&left.kind, Variable(name) if name == ".env") =>
+            {
+                self.visit_with_masking(right, false)
+            }
+            // `!!variable` injects the value of a variable.
+            Prefix(op, operand) if self.masked && op == "!" => match &operand.kind {
+                Prefix(op, variable) if op == "!" => self.visit_with_masking(variable, false),
+                _ => self.visit_expression(operand),
+            },
+            Column(left, _) | Slot(left, _) | Index(left, _) | ListIndex(left, _) => {
+                self.visit_expression(left)
+            }
+            Pipe(left, right) => self.visit_expression(&desugar_pipe(left, right)),
+            Infix(op, left, right) if ["<-", "<<-", "->", "->>"].contains(&op.as_str()) => {
+                let (target, value) = if op.starts_with('-') {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+                self.visit_expression(value);
+                // Changing part of a variable in an argument only uses the variable.
+                match target.assigned_variable_name() {
+                    Some(name) if !is_modification(target) => self.assigned.push(name),
+                    _ => self.visit_expression(target),
+                }
+            }
+            Infix(op, left, right) => match desugar_magrittr(op, left, right) {
+                Some(call) => {
+                    // The call of a tee is left out when the pipe continues, but runs nonetheless.
+                    if matches!(&left.kind, Infix(op, _, _) if op == "%T>%") {
+                        self.visit_expression(left);
+                    }
+                    self.visit_expression(&call)
+                }
+                None => walk_expression(self, exp),
+            },
+            // The variables in a function body are only used when the function is called.
+            Function(_, _, _) => {}
+            _ => walk_expression(self, exp),
+        }
     }
 }
 
@@ -308,14 +1072,19 @@ impl VariableMap {
         VariableMap(HashMap::new())
     }
 
-    /// Add a new `StatementId` for the `variable`.
+    /// Add a new `StatementId` for the `variable`, keeping the ids in order.
     fn push(&mut self, variable: String, index: StatementId) {
-        self.0.entry(variable).or_insert_with(|| vec![]).push(index);
+        let ids = self.0.entry(variable).or_default();
+        let position = ids.partition_point(|id| *id < index);
+        ids.insert(position, index);
     }
 
-    /// Returns the last `StatementId` that defined the `variable`, or returns `None` if the variable is undefined.
-    fn get(&self, variable: &str) -> Option<&StatementId> {
-        self.get_all(variable).and_then(|v| v.last())
+    /// Removes all definitions by the statement with `index`.
+    fn remove(&mut self, index: StatementId) {
+        for ids in self.0.values_mut() {
+            ids.retain(|id| *id != index);
+        }
+        self.0.retain(|_, ids| !ids.is_empty());
     }
 
     /// Returns all `StatementId`s that defined the `variable`.
@@ -334,7 +1103,9 @@ mod tests {
 
     use super::*;
     use crate::parser::{Expression, Statement};
-    use crate::{assignment, call, column, constant, expression, infix, tail_comment, variable};
+    use crate::{
+        assignment, call, column, constant, expression, infix, return_stmt, tail_comment, variable,
+    };
 
     impl Graph {
         fn from_ids(ids: impl Iterator<Item = StatementId>) -> Self {
@@ -353,8 +1124,16 @@ mod tests {
             &mut self,
             edges: impl Iterator<Item = (StatementId, StatementId, Variable)>,
         ) {
-            let edges: Vec<(NodeIndex, NodeIndex, Variable)> = edges
-                .map(|(from, to, v)| (self.ids[&from], self.ids[&to], v))
+            let edges: Vec<(NodeIndex, NodeIndex, Dependency)> = edges
+                .map(|(from, to, variable)| {
+                    let dependency = Dependency {
+                        variable,
+                        changed: None,
+                        called: false,
+                        masked: false,
+                    };
+                    (self.ids[&from], self.ids[&to], dependency)
+                })
                 .collect();
             self.graph.extend_with_edges(edges);
         }
@@ -405,9 +1184,8 @@ mod tests {
         let mut actual = DependencyGraph::new();
         actual.batch_insert(ids.iter().cloned(), &input);
 
-        let variables: HashMap<String, Vec<StatementId>> = HashMap::from_iter(
-            vec![("x".into(), vec![ids[0]]), ("y".into(), vec![ids[1]])].into_iter(),
-        );
+        let variables: HashMap<String, Vec<StatementId>> =
+            HashMap::from_iter(vec![("x".into(), vec![ids[0]]), ("y".into(), vec![ids[1]])]);
         let mut graph = Graph::from_ids(ids.iter().cloned());
         graph.extend_with_edges(
             vec![
@@ -420,6 +1198,11 @@ mod tests {
         let expected = DependencyGraph {
             graph,
             variables: VariableMap(variables),
+            conditional: VariableMap::new(),
+            functions: HashMap::new(),
+            columns: HashMap::new(),
+            uses: HashMap::new(),
+            last: None,
         };
 
         compare_graphs(expected, actual);
@@ -428,13 +1211,13 @@ mod tests {
     #[test]
     fn detects_mutations() {
         let input = Statements::from_iter(vec![
-            assignment!(variable!("x"), vec![], constant!("data frame")),
+            assignment!(variable!("x"), vec![], constant!("\"data frame\"")),
             assignment!(
-                column!(variable!("x"), constant!("column")),
+                column!(variable!("x"), constant!("\"column\"")),
                 vec![],
                 call!(
                     variable!("factor"),
-                    vec![(None, column!(variable!("x"), constant!("column")))]
+                    vec![(None, column!(variable!("x"), constant!("\"column\"")))]
                 )
             ),
             expression!(call!(variable!("summary"), vec![(None, variable!("x"))])),
@@ -444,7 +1227,7 @@ mod tests {
         actual.batch_insert(ids.iter().cloned(), &input);
 
         let variables: HashMap<String, Vec<StatementId>> =
-            HashMap::from_iter(vec![("x".into(), vec![ids[0], ids[1]])].into_iter());
+            HashMap::from_iter(vec![("x".into(), vec![ids[0], ids[1]])]);
         let mut graph = Graph::from_ids(ids.iter().cloned());
         graph.extend_with_edges(
             vec![(ids[0], ids[1], "x".into()), (ids[1], ids[2], "x".into())].into_iter(),
@@ -452,18 +1235,312 @@ mod tests {
         let expected = DependencyGraph {
             graph,
             variables: VariableMap(variables),
+            conditional: VariableMap::new(),
+            functions: HashMap::new(),
+            columns: HashMap::new(),
+            uses: HashMap::new(),
+            last: None,
         };
 
         compare_graphs(expected, actual);
     }
 
+    #[test]
+    fn detects_modifications_of_parts() {
+        let input = crate::parser::parse_statements(
+            "\
+kbd <- read.csv(\"kbd.csv\")
+levels(kbd$Layout) <- c(\"QWERTY\", \"Dvorak\")
+threshold <- 200
+kbd[kbd$Speed > threshold, ]$Speed <- NA
+summary(kbd)",
+        )
+        .unwrap();
+        let actual = DependencyGraph::from_input(&input);
+
+        assert_eq!(
+            serde_json::json!([
+                [0, 1, "kbd", "levels(kbd$Layout)"],
+                [1, 3, "kbd", "kbd[kbd$Speed > threshold, ]$Speed"],
+                [2, 3, "threshold", null],
+                [3, 4, "kbd", null],
+            ]),
+            actual.as_json()["edges"]
+        );
+    }
+
+    #[test]
+    fn detects_replacement_functions_and_string_targets() {
+        let input = crate::parser::parse_statements(
+            "\
+x <- 1:3
+attr(x, \"units\") <- \"cm\"
+\"s\" <- \"abc\"
+substr(s, 1, 1) <- \"A\"
+f() <- 1
+print(x, s)",
+        )
+        .unwrap();
+        let actual = DependencyGraph::from_input(&input);
+
+        assert_eq!(
+            serde_json::json!([
+                [0, 1, "x", "attr(x, \"units\")"],
+                [2, 3, "s", "substr(s, 1, 1)"],
+                [1, 5, "x", null],
+                [3, 5, "s", null],
+            ]),
+            actual.as_json()["edges"]
+        );
+    }
+
+    #[test]
+    fn queries_related_statements() {
+        let input = crate::parser::parse_statements(
+            "\
+kbd <- read.csv(\"kbd.csv\")
+threshold <- 100
+fast <- head(kbd, threshold)
+summary(fast)
+summary(kbd)",
+        )
+        .unwrap();
+        let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+        let actual = DependencyGraph::from_input(&input);
+
+        let mut children = actual.children(ids[0]);
+        children.sort_unstable();
+        assert_eq!(vec![ids[2], ids[4]], children);
+        assert_eq!(vec![ids[0], ids[1], ids[2]], actual.ancestors(ids[3]));
+        assert_eq!(vec![ids[2], ids[3], ids[4]], actual.descendants(ids[0]));
+        assert_eq!(
+            Some(vec![ids[1], ids[2], ids[3]]),
+            actual.path_between(ids[1], ids[3])
+        );
+        assert_eq!(None, actual.path_between(ids[1], ids[4]));
+        assert_eq!(ids, actual.topological_order());
+        assert_eq!(
+            serde_json::json!([0, 1, 2]),
+            actual.as_json()["ancestors"]["3"]
+        );
+    }
+
+    #[test]
+    fn links_uses_to_reaching_definitions() {
+        let input = crate::parser::parse_statements(
+            "\
+x <- 1
+if (check) x <- 2
+y <- x",
+        )
+        .unwrap();
+        let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+        let actual = DependencyGraph::from_input(&input);
+
+        let uses = |variable: &str, definitions: Vec<StatementId>| Use {
+            variable: variable.to_string(),
+            changed: None,
+            called: false,
+            masked: false,
+            definitions,
+        };
+        assert_eq!(vec![uses("check", vec![])], actual.uses(ids[1]));
+        assert_eq!(vec![uses("x", vec![ids[1], ids[0]])], actual.uses(ids[2]));
+        assert_eq!(vec![&"y".to_string()], actual.definitions(ids[2]));
+        assert!(actual.definitions(ids[1]).is_empty());
+    }
+
+    #[test]
+    fn links_statements_inserted_out_of_order() {
+        let input = crate::parser::parse_statements(
+            "\
+x <- 1
+x <- 2
+y <- x",
+        )
+        .unwrap();
+        let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+        let mut actual = DependencyGraph::new();
+        actual.batch_insert(vec![ids[0], ids[2], ids[1]].into_iter(), &input);
+
+        assert_eq!(vec![ids[1]], actual.parents(ids[2]));
+        assert_eq!(vec![ids[1]], actual.uses(ids[2])[0].definitions);
+        assert_eq!(vec![ids[0]], actual.reaching_definitions("x", ids[1]));
+        assert_eq!(
+            Some("2".to_string()),
+            actual.inline_id(ids[2], &input).map(|exp| exp.to_string())
+        );
+    }
+
+    #[test]
+    fn links_calls_to_variables_inserted_out_of_order() {
+        let input = crate::parser::parse_statements(
+            "\
+x <- 1
+f <- function() x
+x <- 2
+f()",
+        )
+        .unwrap();
+        let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+        let mut actual = DependencyGraph::new();
+        actual.batch_insert(vec![ids[0], ids[1], ids[3], ids[2]].into_iter(), &input);
+
+        let mut parents = actual.parents(ids[3]);
+        parents.sort_unstable();
+        assert_eq!(vec![ids[1], ids[2]], parents);
+    }
+
+    #[test]
+    fn detects_assignment_pipes() {
+        let input = crate::parser::parse_statements(
+            "\
+kbd <- read.csv(\"kbd.csv\")
+Speed <- 3
+kbd %<>% filter(Speed > 0)
+kbd$Speed %<>% log()
+summary(kbd)",
+        )
+        .unwrap();
+        let actual = DependencyGraph::from_input(&input);
+
+        assert_eq!(
+            serde_json::json!([
+                [0, 2, "kbd", null],
+                [1, 2, "Speed", null],
+                [2, 3, "kbd", "kbd$Speed"],
+                [3, 4, "kbd", null],
+            ]),
+            actual.as_json()["edges"]
+        );
+    }
+
+    #[test]
+    fn detects_variables_of_tees() {
+        let input = crate::parser::parse_statements(
+            "\
+title <- \"Speed\"
+kbd <- read.csv(\"kbd.csv\")
+kbd %T>% plot(main = title) %>% summary()",
+        )
+        .unwrap();
+        let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+        let actual = DependencyGraph::from_input(&input);
+
+        let mut parents = actual.parents(ids[2]);
+        parents.sort_unstable();
+        assert_eq!(vec![ids[0], ids[1]], parents);
+    }
+
+    #[test]
+    fn detects_function_calls() {
+        let input = crate::parser::parse_statements(
+            "\
+threshold <- 100
+analyze <- function(d) {
+    t.test(Speed ~ Layout, data = subset(d, Speed < threshold))
+}
+kbd <- read.csv(\"kbd.csv\")
+threshold <- 200
+analyze(kbd)
+report <- function() analyze(kbd)
+report()",
+        )
+        .unwrap();
+        let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+        let actual = DependencyGraph::from_input(&input);
+
+        let mut parents = actual.parents(ids[4]);
+        parents.sort_unstable();
+        assert_eq!(vec![ids[1], ids[2], ids[3]], parents);
+        // The variables of called functions are looked up transitively.
+        let mut parents = actual.parents(ids[6]);
+        parents.sort_unstable();
+        assert_eq!(vec![ids[1], ids[2], ids[3], ids[5]], parents);
+    }
+
+    #[test]
+    fn analyzes_functions_with_any_targets() {
+        let input = crate::parser::parse_statements(
+            "\
+b <- 2
+f <- function() {
+    \"a\" <- 1
+    names(a, b) <- \"x\"
+    g() <- a
+    a
+}
+f()",
+        )
+        .unwrap();
+        let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+        let actual = DependencyGraph::from_input(&input);
+
+        let mut parents = actual.parents(ids[2]);
+        parents.sort_unstable();
+        assert_eq!(vec![ids[0], ids[1]], parents);
+    }
+
+    #[test]
+    fn links_masked_names_to_defined_variables() {
+        let input = crate::parser::parse_statements(
+            "\
+Speed <- 3
+kbd <- read.csv(\"kbd.csv\")
+fast <- kbd %>% filter(Speed > 100)
+ggplot(fast, aes(x = Layout, y = Speed))",
+        )
+        .unwrap();
+        let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+        let actual = DependencyGraph::from_input(&input);
+
+        let parents = |id: StatementId| {
+            let mut parents = actual.parents(id);
+            parents.sort_unstable();
+            parents
+        };
+        assert_eq!(vec![ids[0], ids[1]], parents(ids[2]));
+        assert_eq!(vec![ids[0], ids[2]], parents(ids[3]));
+        // Undefined names in data-masked arguments are columns only.
+        assert_eq!(
+            vec!["fast", "ggplot", "aes", "Speed"],
+            actual
+                .uses(ids[3])
+                .iter()
+                .map(|used| used.variable.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(&["Speed".to_string()], actual.columns(ids[2]));
+        assert_eq!(
+            &["Layout".to_string(), "Speed".to_string()],
+            actual.columns(ids[3])
+        );
+    }
+
+    #[test]
+    fn detects_assignments_in_arguments() {
+        let input = crate::parser::parse_statements(
+            "\
+x <- c(1, 2)
+print(mean(x) -> m)
+summary(m)",
+        )
+        .unwrap();
+        let actual = DependencyGraph::from_input(&input);
+
+        assert_eq!(
+            serde_json::json!([[0, 1, "x", null], [1, 2, "m", null]]),
+            actual.as_json()["edges"]
+        );
+    }
+
     #[test]
     fn detects_sibling_dependencies() {
         let input = Statements::from_iter(vec![
-            assignment!(variable!("x"), vec![], constant!("data frame")),
+            assignment!(variable!("x"), vec![], constant!("\"data frame\"")),
             expression!(call!(
                 variable!("factor"),
-                vec![(None, column!(variable!("x"), constant!("column")))]
+                vec![(None, column!(variable!("x"), constant!("\"column\"")))]
             )),
             expression!(call!(variable!("summary"), vec![(None, variable!("x"))])),
         ]);
@@ -472,7 +1549,7 @@ mod tests {
         actual.batch_insert(ids.iter().cloned(), &input);
 
         let variables: HashMap<String, Vec<StatementId>> =
-            HashMap::from_iter(vec![("x".into(), vec![ids[0]])].into_iter());
+            HashMap::from_iter(vec![("x".into(), vec![ids[0]])]);
         let mut graph = Graph::from_ids(ids.iter().cloned());
         graph.extend_with_edges(
             vec![(ids[0], ids[1], "x".into()), (ids[0], ids[2], "x".into())].into_iter(),
@@ -480,16 +1557,177 @@ mod tests {
         let expected = DependencyGraph {
             graph,
             variables: VariableMap(variables),
+            conditional: VariableMap::new(),
+            functions: HashMap::new(),
+            columns: HashMap::new(),
+            uses: HashMap::new(),
+            last: None,
         };
 
         compare_graphs(expected, actual);
     }
 
+    #[test]
+    fn detects_right_assignments() {
+        let input = Statements::from_iter(vec![
+            assignment!(
+                variable!("x"),
+                vec![],
+                constant!("1"),
+                vec![crate::parser::AssignmentDirection::Right]
+            ),
+            expression!(call!(variable!("summary"), vec![(None, variable!("x"))])),
+        ]);
+        let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+        let mut actual = DependencyGraph::new();
+        actual.batch_insert(ids.iter().cloned(), &input);
+
+        let variables: HashMap<String, Vec<StatementId>> =
+            HashMap::from_iter(vec![("x".into(), vec![ids[0]])]);
+        let mut graph = Graph::from_ids(ids.iter().cloned());
+        graph.extend_with_edges(vec![(ids[0], ids[1], "x".into())].into_iter());
+        let expected = DependencyGraph {
+            graph,
+            variables: VariableMap(variables),
+            conditional: VariableMap::new(),
+            functions: HashMap::new(),
+            columns: HashMap::new(),
+            uses: HashMap::new(),
+            last: None,
+        };
+
+        compare_graphs(expected, actual);
+    }
+
+    #[test]
+    fn detects_return_dependencies() {
+        let input = Statements::from_iter(vec![
+            assignment!(variable!("x"), vec![], constant!("1")),
+            return_stmt!(variable!("x")),
+        ]);
+        let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+        let actual = DependencyGraph::from_input(&input);
+
+        assert_eq!(vec![ids[0]], actual.parents(ids[1]));
+    }
+
+    #[test]
+    fn resolves_backtick_identifiers() {
+        let input =
+            crate::parser::parse_statements("`my var` <- 1\nmy_copy <- `my var`\ny <- `my_copy`")
+                .unwrap();
+        let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+        let actual = DependencyGraph::from_input(&input);
+
+        assert_eq!(vec![ids[0]], actual.parents(ids[1]));
+        assert_eq!(vec![ids[1]], actual.parents(ids[2]));
+    }
+
+    mod control_flow {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        fn parents(code: &str) -> Vec<Vec<usize>> {
+            let input = crate::parser::parse_statements(code).unwrap();
+            let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+            let graph = DependencyGraph::from_input(&input);
+            ids.iter()
+                .map(|id| {
+                    let mut parents: Vec<usize> = graph
+                        .parents(*id)
+                        .iter()
+                        .map(|parent| ids.iter().position(|other| other == parent).unwrap())
+                        .collect();
+                    parents.sort_unstable();
+                    parents
+                })
+                .collect()
+        }
+
+        #[test]
+        fn connects_loops() {
+            let code = "\
+kbd <- read.csv(\"kbd.csv\")
+res <- list()
+for (l in levels(kbd$Layout)) {
+    res[[l]] <- t.test(Speed ~ Posture, data = kbd[kbd$Layout == l, ])
+}
+print(res)
+print(l)";
+            assert_eq!(
+                vec![vec![], vec![], vec![0, 1], vec![1, 2], vec![2]],
+                parents(code)
+            );
+        }
+
+        #[test]
+        fn carries_values_into_next_iterations() {
+            let code = "\
+total <- 0
+i <- 1
+while (i <= 10) {
+    total <- total + i
+    i <- i + 1
+}
+total";
+            assert_eq!(vec![vec![], vec![], vec![0, 1], vec![0, 2]], parents(code));
+        }
+
+        #[test]
+        fn keeps_definitions_of_skipped_branches() {
+            let code = "\
+x <- 1
+y <- 1
+if (x > 0) {
+    x <- 2
+    y <- 2
+} else y <- 3
+repeat break
+f(x, y)";
+            assert_eq!(
+                vec![vec![], vec![], vec![0], vec![], vec![0, 2, 2]],
+                parents(code)
+            );
+        }
+
+        #[test]
+        fn modifies_variables_in_loops() {
+            let code = "\
+x <- 1:3
+for (i in 1:3) {
+    attr(x, \"n\") <- i
+}
+while (FALSE) f() <- 1
+print(x)";
+            assert_eq!(vec![vec![], vec![0], vec![], vec![0, 1]], parents(code));
+        }
+
+        #[test]
+        fn updates_if_merged_with_else() {
+            let mut parsed = crate::Parsed::new();
+            let mut graph = DependencyGraph::new();
+            for line in &["x <- 1", "if (a) x <- 2", "else x <- 3", "f(x)"] {
+                let ids = parsed.append(vec![line]);
+                graph.batch_insert(ids.into_iter(), parsed.statements());
+            }
+            let ids: Vec<StatementId> = parsed.statements().iter().map(|(id, _, _)| id).collect();
+            assert_eq!(vec![ids[1]], graph.parents(ids[2]));
+            assert_eq!(&vec![ids[0], ids[1]], graph.variables.get_all("x").unwrap());
+        }
+    }
+
     mod dependencies {
         use super::*;
-        use crate::{column, index};
+        use crate::{column, index, namespaced, pipe, slot, switch};
         use pretty_assertions::assert_eq;
 
+        /// Returns the names of the variables used in the `expression`.
+        fn extract_dependencies(expression: &Expression) -> Vec<RIdentifier> {
+            let mut dependencies = Dependencies::default();
+            dependencies.visit_expression(expression);
+            dependencies.variables
+        }
+
         #[test]
         fn finds_variables() {
             let expression = variable!("x");
@@ -513,7 +1751,7 @@ mod tests {
 
         #[test]
         fn finds_column() {
-            let expression = column!(variable!("x"), constant!("test"));
+            let expression = column!(variable!("x"), constant!("\"test\""));
             let result = extract_dependencies(&expression);
             assert_eq!(vec!["x".to_string()], result);
         }
@@ -522,7 +1760,7 @@ mod tests {
         fn finds_column_in_call() {
             let expression = call!(
                 variable!("factor"),
-                vec![(None, column!(variable!("x"), constant!("column")),)]
+                vec![(None, column!(variable!("x"), constant!("\"column\"")),)]
             );
             let result = extract_dependencies(&expression);
             assert_eq!(vec!["x".to_string()], result);
@@ -535,6 +1773,116 @@ mod tests {
             assert_eq!(vec!["x".to_string()], result);
         }
 
+        #[test]
+        fn finds_switch_selector_and_arms() {
+            let expression = switch!(
+                variable!("x"),
+                vec![
+                    (Some("a".to_string()), None),
+                    (Some("b".to_string()), Some(variable!("y"))),
+                    (None, Some(constant!("1"))),
+                ]
+            );
+            let result = extract_dependencies(&expression);
+            assert_eq!(vec!["x".to_string(), "y".to_string()], result);
+        }
+
+        #[test]
+        fn finds_native_pipe() {
+            let expression = pipe!(
+                variable!("x"),
+                call!(
+                    variable!("f"),
+                    vec![
+                        (None, variable!("y")),
+                        (Some("data".into()), variable!("_"))
+                    ]
+                )
+            );
+            let result = extract_dependencies(&expression);
+            assert_eq!(vec!["y".to_string(), "x".to_string()], result);
+        }
+
+        #[test]
+        fn ignores_typed_constants() {
+            let expression = call!(
+                variable!("c"),
+                vec![
+                    (None, constant!("NA_integer_")),
+                    (None, constant!("NULL")),
+                    (None, constant!("Inf")),
+                    (None, constant!("NaN")),
+                    (None, constant!("r\"(C:\\path)\"")),
+                    (None, variable!("x")),
+                ]
+            );
+            let result = extract_dependencies(&expression);
+            assert_eq!(vec!["x".to_string()], result);
+        }
+
+        #[test]
+        fn ignores_namespaced_objects() {
+            let expression = call!(
+                namespaced!("stats", "lm", false),
+                vec![(None, slot!(variable!("model"), variable!("data")))]
+            );
+            let result = extract_dependencies(&expression);
+            assert_eq!(vec!["model".to_string()], result);
+        }
+
+        #[test]
+        fn separates_masked_columns() {
+            let parsed = crate::parser::parse_statements(
+                "kbd %>% filter(Layout == \"QWERTY\") %>% summarise(m = mean(Speed))",
+            )
+            .unwrap();
+            let stmt = parsed.into_iter().next().unwrap().0;
+            let expression = stmt.expression().unwrap();
+            assert_eq!(vec!["kbd".to_string()], extract_dependencies(expression));
+            assert_eq!(
+                vec!["Layout".to_string(), "Speed".to_string()],
+                extract_columns(expression)
+            );
+        }
+
+        #[test]
+        fn finds_injected_variables() {
+            let parsed = crate::parser::parse_statements(
+                "subset(kbd, Speed > .env$threshold & Layout == !!layout & .data$Posture == \"sitting\")",
+            )
+            .unwrap();
+            let stmt = parsed.into_iter().next().unwrap().0;
+            let expression = stmt.expression().unwrap();
+            assert_eq!(
+                vec![
+                    "kbd".to_string(),
+                    "threshold".to_string(),
+                    "layout".to_string()
+                ],
+                extract_dependencies(expression)
+            );
+            assert_eq!(
+                vec![
+                    "Speed".to_string(),
+                    "Layout".to_string(),
+                    "Posture".to_string()
+                ],
+                extract_columns(expression)
+            );
+        }
+
+        #[test]
+        fn respects_packages_of_masking_functions() {
+            let expression = call!(
+                namespaced!("stats", "filter", false),
+                vec![(None, variable!("x")), (None, variable!("weights"))]
+            );
+            assert_eq!(
+                vec!["x".to_string(), "weights".to_string()],
+                extract_dependencies(&expression)
+            );
+        }
+
         #[test]
         fn finds_infix() {
             let expression = infix!("+", variable!("x"), constant!("10"));
@@ -550,9 +1898,9 @@ mod tests {
         #[test]
         fn inlines_simple_shadowing() {
             let input = Statements::from_iter(vec![
-                assignment!(variable!("x"), vec![], constant!("old value")),
+                assignment!(variable!("x"), vec![], constant!("\"old value\"")),
                 expression!(call!(variable!("print"), vec![(None, variable!("x"))])),
-                assignment!(variable!("x"), vec![], constant!("new value")),
+                assignment!(variable!("x"), vec![], constant!("\"new value\"")),
                 expression!(call!(variable!("print"), vec![(None, variable!("x"))])),
             ]);
 
@@ -562,12 +1910,53 @@ mod tests {
 
             let result = graph.inline_id(ids[1], &input).unwrap();
             let expected: Expression =
-                call!(variable!("print"), vec![(None, constant!("old value"))]);
+                call!(variable!("print"), vec![(None, constant!("\"old value\""))]);
             assert_eq!(expected, result);
             let result = graph.inline_id(ids[3], &input).unwrap();
             let expected: Expression =
-                call!(variable!("print"), vec![(None, constant!("new value"))]);
+                call!(variable!("print"), vec![(None, constant!("\"new value\""))]);
             assert_eq!(expected, result);
         }
+
+        #[test]
+        fn inlines_simple_functions() {
+            let input = crate::parser::parse_statements(
+                "\
+analyze <- function(d, alpha = 0.05) t.test(Speed ~ Layout, data = d, conf.level = 1 - alpha)
+kbd <- read.csv(\"kbd.csv\")
+analyze(kbd)
+analyze(alpha = 0.01, d = kbd)
+countdown <- function(n) countdown(n - 1)
+countdown(3)",
+            )
+            .unwrap();
+            let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+            let graph = DependencyGraph::from_input(&input);
+
+            let inline = |id: StatementId| graph.inline_id(id, &input).unwrap().to_string();
+            assert_eq!(
+                "t.test(Speed ~ Layout, data = read.csv(\"kbd.csv\"), conf.level = 1 - 0.05)",
+                inline(ids[2])
+            );
+            assert_eq!(
+                "t.test(Speed ~ Layout, data = read.csv(\"kbd.csv\"), conf.level = 1 - 0.01)",
+                inline(ids[3])
+            );
+            // Recursive functions are inlined once.
+            assert_eq!("countdown(3 - 1)", inline(ids[5]));
+        }
+
+        #[test]
+        fn inlines_variables_before_modifications() {
+            let input = crate::parser::parse_statements(
+                "x <- read.csv(\"data.csv\")\nx$a <- log(x$b)\nsummary(x)",
+            )
+            .unwrap();
+            let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+            let graph = DependencyGraph::from_input(&input);
+
+            let result = graph.inline_id(ids[2], &input).unwrap();
+            assert_eq!("summary(read.csv(\"data.csv\"))", result.to_string());
+        }
     }
 }
@@ -0,0 +1,421 @@
+use std::ops::Range;
+use std::path::Path;
+
+use serde::Deserialize;
+
+/// A code chunk of an R Markdown or Quarto document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// The language the chunk is written in, e. g. `r` or `python`.
+    pub engine: String,
+    /// The name of the chunk, e. g. `load-data` in `{r load-data}`.
+    pub label: Option<String>,
+    /// Whether the chunk is run when the document is rendered, which `eval=FALSE` prevents.
+    pub eval: bool,
+    /// The text of the last heading before the chunk.
+    pub heading: Option<String>,
+    /// The indices of the chunk's code lines in the document, without the fences and chunk options.
+    pub lines: Range<usize>,
+}
+
+impl Chunk {
+    /// Reads the chunk from the `header` in the braces of its opening fence and its `lines`.
+    fn new(header: &str, lines: &[&str], code: Range<usize>, heading: Option<String>) -> Self {
+        let header = header.trim();
+        let engine_end = header.find([' ', ',']).unwrap_or(header.len());
+        let mut chunk = Chunk {
+            engine: header[..engine_end].to_string(),
+            label: None,
+            eval: true,
+            heading,
+            lines: code,
+        };
+        for (index, option) in split_options(&header[engine_end..]).into_iter().enumerate() {
+            match option.split_once('=') {
+                Some((key, value)) => chunk.set_option(key.trim(), value.trim()),
+                // The label can be given without a name as the first option.
+                None if index == 0 => chunk.label = Some(unquote(option)),
+                None => {}
+            }
+        }
+        // Quarto puts the options in comments starting with `#|` at the start of the chunk.
+        while chunk.lines.start < chunk.lines.end {
+            let line = lines[chunk.lines.start].trim_start();
+            let option = match line.strip_prefix("#|") {
+                Some(option) => option,
+                None => break,
+            };
+            if let Some((key, value)) = option.split_once(':') {
+                chunk.set_option(key.trim(), value.trim());
+            }
+            chunk.lines.start += 1;
+        }
+        chunk
+    }
+
+    fn set_option(&mut self, key: &str, value: &str) {
+        match key {
+            "label" => self.label = Some(unquote(value)),
+            "eval" => self.eval = !matches!(value, "FALSE" | "F" | "false"),
+            _ => {}
+        }
+    }
+
+    /// Whether the chunk contains R code that is run when rendering the document.
+    pub fn is_evaluated_r(&self) -> bool {
+        self.eval && self.engine.eq_ignore_ascii_case("r")
+    }
+
+    /// Describes the chunk by its heading and label, e. g. `Cleaning (remove-outliers)`.
+    pub fn title(&self) -> Option<String> {
+        match (&self.heading, &self.label) {
+            (Some(heading), Some(label)) => Some(format!("{} ({})", heading, label)),
+            (Some(heading), None) => Some(heading.clone()),
+            (None, Some(label)) => Some(label.clone()),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Whether the file at `path` is an R Markdown (`.Rmd`) or Quarto (`.qmd`) document, judging by its extension.
+pub fn is_rmarkdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("rmd") || extension.eq_ignore_ascii_case("qmd")
+        })
+}
+
+/// Finds the code chunks in an R Markdown or Quarto document.
+///
+/// Chunks are fenced by at least three backticks and name their engine in braces, e. g. ```` ```{r label, eval=FALSE} ````.
+/// Fenced code without braces is displayed, but not run, so it is not a chunk.
+pub fn rmarkdown_chunks(document: &str) -> Vec<Chunk> {
+    let lines: Vec<&str> = document.lines().collect();
+    let mut chunks = Vec::new();
+    let mut heading = None;
+    let mut index = front_matter_end(&lines);
+    while index < lines.len() {
+        let line = lines[index].trim_start();
+        index += 1;
+        let fence = line.chars().take_while(|c| *c == '`').count();
+        if fence >= 3 {
+            let start = index;
+            // The closing fence has at least as many backticks and nothing else.
+            let end = (start..lines.len())
+                .find(|i| {
+                    let line = lines[*i].trim();
+                    line.len() >= fence && line.chars().all(|c| c == '`')
+                })
+                .unwrap_or(lines.len());
+            index = end + 1;
+            let header = line[fence..].trim();
+            if let Some(header) = header.strip_prefix('{').and_then(|h| h.strip_suffix('}')) {
+                chunks.push(Chunk::new(header, &lines, start..end, heading.clone()));
+            }
+        } else if let Some(text) = markdown_heading(line) {
+            heading = Some(text);
+        }
+    }
+    chunks
+}
+
+/// A code cell of a Jupyter notebook.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cell {
+    /// The position of the cell in the notebook, starting at zero.
+    pub index: usize,
+    /// The number of the cell's last run, e. g. `3` for `In [3]`.
+    pub execution_count: u64,
+    pub code: String,
+    /// The text the cell printed when it was last run, including its value and errors.
+    pub output: String,
+    /// The text of the last heading in the Markdown cells before the cell.
+    pub heading: Option<String>,
+}
+
+impl Cell {
+    /// Describes the cell by its heading and execution count, e. g. `Cleaning (In [3])`.
+    pub fn title(&self) -> String {
+        let label = format!("In [{}]", self.execution_count);
+        match &self.heading {
+            Some(heading) => format!("{} ({})", heading, label),
+            None => label,
+        }
+    }
+}
+
+/// Whether the file at `path` is a Jupyter notebook, judging by its `.ipynb` extension.
+pub fn is_notebook(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("ipynb"))
+}
+
+/// Reads the code cells of a Jupyter notebook in the order they were last run.
+///
+/// This is the order in which the cells changed the state of the session, which may differ from their order in the notebook.
+/// Cells that have not been run since the kernel started are left out.
+/// Notebooks whose kernel runs another language than R are rejected.
+pub fn notebook_cells(notebook: &str) -> serde_json::Result<Vec<Cell>> {
+    let notebook: Notebook = serde_json::from_str(notebook)?;
+    if let Some(language) = notebook
+        .metadata
+        .kernelspec
+        .and_then(|kernel| kernel.language)
+    {
+        if !language.eq_ignore_ascii_case("R") {
+            return Err(serde::de::Error::custom(format!(
+                "the notebook runs {}, not R",
+                language
+            )));
+        }
+    }
+    let mut cells = Vec::new();
+    let mut heading = None;
+    for (index, cell) in notebook.cells.into_iter().enumerate() {
+        let code = cell.source.into_string();
+        if cell.cell_type == "markdown" {
+            heading = code.lines().rev().find_map(markdown_heading).or(heading);
+        }
+        if let (Some(execution_count), "code") = (cell.execution_count, cell.cell_type.as_str()) {
+            let output = cell
+                .outputs
+                .into_iter()
+                .filter_map(NotebookOutput::into_text)
+                .map(|text| text.trim_end_matches('\n').to_string())
+                .collect::<Vec<_>>()
+                .join("\n");
+            cells.push(Cell {
+                index,
+                execution_count,
+                code,
+                output,
+                heading: heading.clone(),
+            });
+        }
+    }
+    cells.sort_by_key(|cell| cell.execution_count);
+    Ok(cells)
+}
+
+/// The parts of the `.ipynb` format that are needed, see https://nbformat.readthedocs.io/en/latest/format_description.html.
+#[derive(Deserialize)]
+struct Notebook {
+    cells: Vec<NotebookCell>,
+    #[serde(default)]
+    metadata: NotebookMetadata,
+}
+
+#[derive(Deserialize, Default)]
+struct NotebookMetadata {
+    #[serde(default)]
+    kernelspec: Option<KernelSpec>,
+}
+
+#[derive(Deserialize)]
+struct KernelSpec {
+    #[serde(default)]
+    language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NotebookCell {
+    cell_type: String,
+    source: NotebookText,
+    #[serde(default)]
+    execution_count: Option<u64>,
+    #[serde(default)]
+    outputs: Vec<NotebookOutput>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "output_type", rename_all = "snake_case")]
+enum NotebookOutput {
+    Stream {
+        text: NotebookText,
+    },
+    ExecuteResult {
+        data: std::collections::HashMap<String, serde_json::Value>,
+    },
+    DisplayData {
+        data: std::collections::HashMap<String, serde_json::Value>,
+    },
+    Error {
+        evalue: String,
+    },
+}
+
+impl NotebookOutput {
+    /// Returns the output as text, if it has a textual representation.
+    fn into_text(self) -> Option<String> {
+        match self {
+            NotebookOutput::Stream { text } => Some(text.into_string()),
+            NotebookOutput::ExecuteResult { mut data }
+            | NotebookOutput::DisplayData { mut data } => {
+                let text = data.remove("text/plain")?;
+                serde_json::from_value::<NotebookText>(text)
+                    .ok()
+                    .map(NotebookText::into_string)
+            }
+            NotebookOutput::Error { evalue } => Some(evalue),
+        }
+    }
+}
+
+/// Text in a notebook is stored either as a single string or as a list of lines.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NotebookText {
+    Single(String),
+    Lines(Vec<String>),
+}
+
+impl NotebookText {
+    fn into_string(self) -> String {
+        match self {
+            NotebookText::Single(text) => text,
+            NotebookText::Lines(lines) => lines.concat(),
+        }
+    }
+}
+
+/// Returns the index of the first line after the YAML front matter, which is enclosed in `---` lines.
+fn front_matter_end(lines: &[&str]) -> usize {
+    if lines.first().map(|line| line.trim_end()) != Some("---") {
+        return 0;
+    }
+    lines
+        .iter()
+        .skip(1)
+        .position(|line| matches!(line.trim_end(), "---" | "..."))
+        .map_or(0, |end| end + 2)
+}
+
+/// Returns the text of a heading like `## Results ##`.
+fn markdown_heading(line: &str) -> Option<String> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    let text = &line[level..];
+    if level == 0 || level > 6 || !(text.is_empty() || text.starts_with([' ', '\t'])) {
+        return None;
+    }
+    let text = text.trim().trim_end_matches('#').trim_end();
+    Some(text.to_string())
+}
+
+/// Splits chunk options at the commas that are not inside quotes or parentheses, e. g. in `fig.cap = "a, b"`.
+fn split_options(options: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (index, c) in options.char_indices() {
+        match (quote, c) {
+            (Some(open), _) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                result.push(&options[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    result.push(&options[start..]);
+    result
+        .into_iter()
+        .map(|option| option.trim())
+        .filter(|option| !option.is_empty())
+        .collect()
+}
+
+fn unquote(value: &str) -> String {
+    value.trim_matches(|c| c == '"' || c == '\'').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    const DOCUMENT: &str = "\
+---
+title: Keyboards
+# Not a heading
+---
+
+# Setup
+
+```{r setup, include=FALSE}
+library(dplyr)
+```
+
+## Data cleaning
+
+```{r}
+#| label: remove-outliers
+data <- subset(data, speed < 200)
+```
+
+```{r, eval = FALSE, fig.cap = \"a, b\"}
+install.packages(\"dplyr\")
+```
+
+```{python}
+print(1)
+```
+
+```
+not <- a_chunk
+```
+";
+
+    #[test]
+    fn finds_chunks_with_their_headings() {
+        let chunks = rmarkdown_chunks(DOCUMENT);
+        assert_eq!(
+            vec![
+                (Some("Setup (setup)".to_string()), 8..9),
+                (Some("Data cleaning (remove-outliers)".to_string()), 15..16),
+            ],
+            chunks
+                .iter()
+                .filter(|chunk| chunk.is_evaluated_r())
+                .map(|chunk| (chunk.title(), chunk.lines.clone()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn skips_unevaluated_chunks_and_other_engines() {
+        let chunks = rmarkdown_chunks(DOCUMENT);
+        assert_eq!(4, chunks.len());
+        assert!(!chunks[2].eval);
+        assert_eq!(None, chunks[2].label);
+        assert_eq!("python", chunks[3].engine);
+    }
+
+    #[test]
+    fn rejects_notebooks_in_other_languages() {
+        let notebook = |language: &str| {
+            format!(
+                r#"{{"cells": [], "metadata": {{"kernelspec": {{"language": "{}"}}}}}}"#,
+                language
+            )
+        };
+        assert!(notebook_cells(&notebook("R")).is_ok());
+        assert!(notebook_cells(r#"{"cells": []}"#).is_ok());
+        let error = notebook_cells(&notebook("python")).unwrap_err();
+        assert_eq!("the notebook runs python, not R", error.to_string());
+    }
+
+    #[test]
+    fn recognizes_documents_by_extension() {
+        assert!(is_rmarkdown(Path::new("analysis.Rmd")));
+        assert!(is_rmarkdown(Path::new("report.qmd")));
+        assert!(!is_rmarkdown(Path::new("main.R")));
+    }
+}
@@ -0,0 +1,581 @@
+use std::iter;
+use std::str::FromStr;
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::parser::{
+    parse_statements, parse_statements_recovering, quote_identifier, AssignmentDirection,
+    Expression, ExpressionKind, FunctionSyntax, Statement, StatementKind,
+};
+
+/// The style the `formatter` prints code in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Style {
+    /// The number of spaces per indentation level.
+    pub indent: usize,
+    /// The width after which argument lists and operator chains are broken into multiple lines.
+    pub line_width: usize,
+    /// The operator used for assignments to the left. Superassignments always keep `<<-`.
+    pub assignment: AssignmentOperator,
+    /// Whether binary operators are surrounded by spaces. `^` and `:` never are.
+    pub spaces_around_operators: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            indent: 2,
+            line_width: 80,
+            assignment: AssignmentOperator::Arrow,
+            spaces_around_operators: true,
+        }
+    }
+}
+
+/// The operator for assignments to the left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AssignmentOperator {
+    /// `a <- 1`
+    Arrow,
+    /// `a = 1`
+    Equals,
+}
+
+impl AssignmentOperator {
+    fn as_str(self) -> &'static str {
+        match self {
+            AssignmentOperator::Arrow => "<-",
+            AssignmentOperator::Equals => "=",
+        }
+    }
+}
+
+impl FromStr for AssignmentOperator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "arrow" | "<-" => Ok(AssignmentOperator::Arrow),
+            "equals" | "=" => Ok(AssignmentOperator::Equals),
+            other => Err(format!(
+                "Unknown assignment operator `{}`, expected `arrow` or `equals`.",
+                other
+            )),
+        }
+    }
+}
+
+/// Format R code, one statement per line.
+///
+/// Code that cannot be parsed is kept as it is.
+pub fn format_code(code: &str, style: &Style) -> String {
+    let (statements, _) = parse_statements_recovering(code);
+    statements
+        .iter()
+        .map(|(_, stmt, _)| format_statement(stmt, style) + "\n")
+        .collect()
+}
+
+/// Format a single statement.
+///
+/// A parsed statement keeps its original code if formatting would lose comments within it
+/// or the formatted code would not parse back into the same statement.
+pub fn format_statement(stmt: &Statement, style: &Style) -> String {
+    let formatted = Printer { style }.statement(stmt, 0);
+    match &stmt.source {
+        Some(source) if !is_faithful(stmt, source, &formatted) => source.clone(),
+        _ => formatted,
+    }
+}
+
+/// Check that the formatted code has the same meaning and comments as the source.
+fn is_faithful(stmt: &Statement, source: &str, formatted: &str) -> bool {
+    if let StatementKind::Unparsed(_) = stmt.kind {
+        return false;
+    }
+    if count_comments(source) != count_comments(formatted) {
+        return false;
+    }
+    match parse_statements(formatted) {
+        Ok(parsed) => {
+            let mut statements = parsed.iter();
+            match (statements.next(), statements.next()) {
+                (Some((_, reparsed, _)), None) => reparsed == stmt,
+                _ => false,
+            }
+        }
+        Err(_) => false,
+    }
+}
+
+/// Count the comments in the code, skipping `#` in strings.
+fn count_comments(code: &str) -> usize {
+    let mut count = 0;
+    let mut quote = None;
+    let mut chars = code.chars();
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) => {
+                if c == '\\' {
+                    chars.next();
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '"' | '\'' | '`' => quote = Some(c),
+                '#' => {
+                    count += 1;
+                    chars.by_ref().find(|&c| c == '\n');
+                }
+                _ => {}
+            },
+        }
+    }
+    count
+}
+
+/// The column after the text, if the text starts at column `start`.
+fn end_column(start: usize, text: &str) -> usize {
+    match text.rfind('\n') {
+        Some(i) => text[i + 1..].chars().count(),
+        None => start + text.chars().count(),
+    }
+}
+
+/// Whether the text fits into the width, if it starts at `column`.
+fn fits(column: usize, text: &str, width: usize) -> bool {
+    text.lines().enumerate().all(|(i, line)| {
+        let start = if i == 0 { column } else { 0 };
+        start + line.chars().count() <= width
+    })
+}
+
+/// Collect the operands of a chain of the same left-associative operator, e. g. `a + b + c`.
+fn chain<'e>(exp: &'e Expression, operator: &str) -> Vec<&'e Expression> {
+    use ExpressionKind::*;
+    match &exp.kind {
+        Infix(op, left, right) if op == operator => {
+            let mut operands = chain(left, operator);
+            operands.push(right);
+            operands
+        }
+        Pipe(left, right) if operator == "|>" => {
+            let mut operands = chain(left, operator);
+            operands.push(right);
+            operands
+        }
+        _ => vec![exp],
+    }
+}
+
+/// Prints statements and expressions according to a `Style`.
+///
+/// Every method returns code without the indentation of its first line, which depends on what precedes it.
+/// All following lines are indented completely.
+struct Printer<'a> {
+    style: &'a Style,
+}
+
+impl Printer<'_> {
+    fn indentation(&self, level: usize) -> String {
+        " ".repeat(level * self.style.indent)
+    }
+
+    fn statement(&self, stmt: &Statement, level: usize) -> String {
+        use StatementKind::*;
+        let column = level * self.style.indent;
+        match &stmt.kind {
+            Empty => String::new(),
+            Comment(text) => text.clone(),
+            TailComment(inner, text) => format!("{} {}", self.statement(inner, level), text),
+            Assignment(left, additional, value, directions) => {
+                let operator = |direction: &AssignmentDirection| match direction {
+                    AssignmentDirection::Left => self.style.assignment.as_str(),
+                    _ => direction.operator(),
+                };
+                let targets: Vec<_> = iter::once(left).chain(additional).zip(directions).collect();
+                let mut s = String::new();
+                if directions.iter().any(|direction| direction.is_right()) {
+                    s += &self.expression(value, level, column);
+                    for (target, direction) in targets.into_iter().rev() {
+                        s += &format!(" {} ", operator(direction));
+                        s += &self.expression(target, level, end_column(column, &s));
+                    }
+                } else {
+                    for (target, direction) in targets {
+                        s += &self.expression(target, level, end_column(column, &s));
+                        s += &format!(" {} ", operator(direction));
+                    }
+                    s += &self.expression(value, level, end_column(column, &s));
+                }
+                s
+            }
+            If(condition, body, maybe_else_body) => {
+                let mut s = "if (".to_string();
+                s += &self.expression(condition, level, end_column(column, &s));
+                s += ") ";
+                s += &self.block(body, level);
+                if let Some(else_body) = maybe_else_body {
+                    s += " else ";
+                    match else_body.as_slice() {
+                        [else_if @ Statement {
+                            kind: If(_, _, _), ..
+                        }] => s += &self.statement(else_if, level),
+                        _ => s += &self.block(else_body, level),
+                    }
+                }
+                s
+            }
+            While(condition, body) => {
+                let mut s = "while (".to_string();
+                s += &self.expression(condition, level, end_column(column, &s));
+                s + ") " + &self.block(body, level)
+            }
+            For(variable, range, body) => {
+                let mut s = format!("for ({} in ", self.flat(variable, level));
+                s += &self.expression(range, level, end_column(column, &s));
+                s + ") " + &self.block(body, level)
+            }
+            Repeat(body) => format!("repeat {}", self.block(body, level)),
+            Break => "break".to_string(),
+            Next => "next".to_string(),
+            Return(maybe_value) => match maybe_value {
+                Some(value) => format!("return({})", self.expression(value, level, column + 7)),
+                None => "return()".to_string(),
+            },
+            Library(name) => format!("library({})", quote_identifier(name)),
+            Expression(exp) => self.expression(exp, level, column),
+            Unparsed(code) => code.clone(),
+        }
+    }
+
+    /// Print a braced block with its statements one level deeper.
+    fn block(&self, body: &[Statement], level: usize) -> String {
+        if body.is_empty() {
+            return "{}".to_string();
+        }
+        let indentation = self.indentation(level + 1);
+        let lines = body
+            .iter()
+            .map(|stmt| match stmt.kind {
+                StatementKind::Empty => String::new(),
+                _ => indentation.clone() + &self.statement(stmt, level + 1),
+            })
+            .join("\n");
+        format!("{{\n{}\n{}}}", lines, self.indentation(level))
+    }
+
+    fn expression(&self, exp: &Expression, level: usize, column: usize) -> String {
+        self.layout(exp, level, column, self.style.line_width)
+    }
+
+    /// Print the expression without breaking any lines outside of function bodies.
+    fn flat(&self, exp: &Expression, level: usize) -> String {
+        self.layout(exp, level, 0, usize::MAX)
+    }
+
+    fn layout(&self, exp: &Expression, level: usize, column: usize, width: usize) -> String {
+        use ExpressionKind::*;
+        match &exp.kind {
+            Constant(constant) => constant.to_string(),
+            Variable(_) | Namespaced { .. } => exp.to_string(),
+            Call(function, args) => {
+                let head = self.layout(function, level, column, width) + "(";
+                let items = args
+                    .iter()
+                    .map(|(maybe_name, arg)| {
+                        let prefix = match maybe_name {
+                            Some(name) => format!("{} = ", quote_identifier(name)),
+                            None => String::new(),
+                        };
+                        (prefix, Some(arg))
+                    })
+                    .collect();
+                self.list(head, items, ")", level, column, width)
+            }
+            Column(left, right) => format!(
+                "{}${}",
+                self.layout(left, level, column, width),
+                self.flat(right, level)
+            ),
+            Slot(left, right) => format!(
+                "{}@{}",
+                self.layout(left, level, column, width),
+                self.flat(right, level)
+            ),
+            Index(left, indices) => format!(
+                "{}[{}]",
+                self.layout(left, level, column, width),
+                self.indices(indices, level)
+            ),
+            ListIndex(left, indices) => format!(
+                "{}[[{}]]",
+                self.layout(left, level, column, width),
+                self.indices(indices, level)
+            ),
+            OneSidedFormula(formula) => {
+                let tight = !self.style.spaces_around_operators
+                    || matches!(formula.kind, Variable(_) | Constant(_));
+                let operator = if tight { "~" } else { "~ " };
+                operator.to_string() + &self.layout(formula, level, column + operator.len(), width)
+            }
+            TwoSidedFormula(left, right) => {
+                let mut s = self.layout(left, level, column, width);
+                s += if self.style.spaces_around_operators {
+                    " ~ "
+                } else {
+                    "~"
+                };
+                s += &self.layout(right, level, end_column(column, &s), width);
+                s
+            }
+            Function(params, body, syntax) => {
+                let parameters = params
+                    .iter()
+                    .map(|(name, maybe_default)| match maybe_default {
+                        Some(default) => {
+                            format!("{} = {}", quote_identifier(name), self.flat(default, level))
+                        }
+                        None => quote_identifier(name).into_owned(),
+                    })
+                    .join(", ");
+                let head = format!("{}({}) ", syntax.keyword(), parameters);
+                match (syntax, body.as_slice()) {
+                    // Short lambdas like `\(x) x + 1` stay on their line.
+                    (
+                        FunctionSyntax::Lambda,
+                        [Statement {
+                            kind: StatementKind::Expression(inner),
+                            ..
+                        }],
+                    ) => {
+                        let column = column + head.len();
+                        head + &self.layout(inner, level, column, width)
+                    }
+                    _ => head + &self.block(body, level),
+                }
+            }
+            Switch(selector, arms) => {
+                let items = iter::once((String::new(), Some(selector.as_ref())))
+                    .chain(arms.iter().map(|(maybe_name, maybe_value)| {
+                        let prefix = match maybe_name {
+                            Some(name) => format!("{} = ", quote_identifier(name)),
+                            None => String::new(),
+                        };
+                        (prefix, maybe_value.as_ref())
+                    }))
+                    .collect();
+                self.list("switch(".to_string(), items, ")", level, column, width)
+            }
+            Prefix(op, operand) => {
+                op.clone() + &self.layout(operand, level, column + op.len(), width)
+            }
+            Infix(op, left, right) if op == "^" || op == ":" => {
+                let mut s = self.layout(left, level, column, width);
+                s += op;
+                s += &self.layout(right, level, end_column(column, &s), width);
+                s
+            }
+            Infix(op, _, _) => self.operation(exp, op, level, column, width),
+            Paren(inner) => format!("({})", self.layout(inner, level, column + 1, width)),
+            Pipe(_, _) => self.operation(exp, "|>", level, column, width),
+        }
+    }
+
+    fn indices(&self, indices: &[Option<Expression>], level: usize) -> String {
+        indices
+            .iter()
+            .map(|maybe_index| match maybe_index {
+                Some(index) => self.flat(index, level),
+                None => String::new(),
+            })
+            .join(", ")
+    }
+
+    /// Print a chain of the same binary operator.
+    /// If it does not fit, every operand after the first goes on its own line.
+    fn operation(
+        &self,
+        exp: &Expression,
+        op: &str,
+        level: usize,
+        column: usize,
+        width: usize,
+    ) -> String {
+        let operands = chain(exp, op);
+        let spaced = self.style.spaces_around_operators || op.starts_with('%') || op == "|>";
+        let flat = self.join_operands(&operands, op, spaced, level, column, usize::MAX);
+        if fits(column, &flat, width) {
+            return self.join_operands(&operands, op, spaced, level, column, width);
+        }
+
+        let indentation = self.indentation(level + 1);
+        let mut s = self.layout(operands[0], level, column, width);
+        for operand in &operands[1..] {
+            let spacing = if spaced { " " } else { "" };
+            s += &format!("{}{}\n{}", spacing, op, indentation);
+            s += &self.layout(operand, level + 1, end_column(column, &s), width);
+        }
+        s
+    }
+
+    fn join_operands(
+        &self,
+        operands: &[&Expression],
+        op: &str,
+        spaced: bool,
+        level: usize,
+        column: usize,
+        width: usize,
+    ) -> String {
+        let mut s = self.layout(operands[0], level, column, width);
+        for operand in &operands[1..] {
+            let right = self.layout(operand, level, 0, usize::MAX);
+            // Without spaces, `a < -b` would turn into the assignment `a<-b`.
+            if spaced || (op == "<" && right.starts_with('-')) {
+                s += &format!(" {} ", op);
+            } else {
+                s += op;
+            }
+            s += &self.layout(operand, level, end_column(column, &s), width);
+        }
+        s
+    }
+
+    /// Print comma separated items in brackets, each on its own line if they do not fit on one.
+    fn list(
+        &self,
+        head: String,
+        items: Vec<(String, Option<&Expression>)>,
+        close: &str,
+        level: usize,
+        column: usize,
+        width: usize,
+    ) -> String {
+        let flat = head.clone()
+            + &items
+                .iter()
+                .map(|(prefix, maybe_item)| match maybe_item {
+                    Some(item) => prefix.clone() + &self.flat(item, level),
+                    None => prefix.clone(),
+                })
+                .join(", ")
+            + close;
+        if items.is_empty() || fits(column, &flat, width) {
+            return flat;
+        }
+
+        let indentation = self.indentation(level + 1);
+        let lines = items
+            .iter()
+            .map(|(prefix, maybe_item)| {
+                let mut line = indentation.clone() + prefix;
+                if let Some(item) = maybe_item {
+                    line += &self.layout(item, level + 1, end_column(0, &line), width);
+                }
+                line
+            })
+            .join(",\n");
+        format!("{}\n{}\n{}{}", head, lines, self.indentation(level), close)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn narrow(line_width: usize) -> Style {
+        Style {
+            line_width,
+            ..Style::default()
+        }
+    }
+
+    #[test]
+    fn normalizes_spacing_and_indentation() {
+        let code =
+            "x<-c(1,2)\nif(x[1]>1){\ny=x^2}else{\n    y <- -x[2]\n}\nf <- function(a,b=2) a:b\n";
+        let expected = "x <- c(1, 2)\nif (x[1] > 1) {\n  y <- x^2\n} else {\n  y <- -x[2]\n}\nf <- function(a, b = 2) {\n  a:b\n}\n";
+        assert_eq!(expected, format_code(code, &Style::default()));
+    }
+
+    #[test]
+    fn keeps_else_if_chains() {
+        let code = "if (a) {\n  1\n} else if (b) {\n  2\n} else {\n  3\n}\n";
+        assert_eq!(code, format_code(code, &Style::default()));
+    }
+
+    #[test]
+    fn applies_style() {
+        let code = "a <- b + 1\nglobal <<- 2\n3 -> c\nd <- global <<- 4\n";
+        let style = Style {
+            indent: 4,
+            assignment: AssignmentOperator::Equals,
+            spaces_around_operators: false,
+            ..Style::default()
+        };
+        assert_eq!(
+            "a = b+1\nglobal <<- 2\n3 -> c\nd = global <<- 4\n",
+            format_code(code, &style)
+        );
+        assert_eq!(
+            "while (a < -1) {\n    a = a+1\n}\n",
+            format_code("while(a < - 1) a <- a + 1", &style)
+        );
+    }
+
+    #[test]
+    fn breaks_long_argument_lists() {
+        let code =
+            "result <- some_function(first_argument, second = another_function(x, y), third = 3)";
+        let expected = "result <- some_function(\n  first_argument,\n  second = another_function(x, y),\n  third = 3\n)\n";
+        assert_eq!(expected, format_code(code, &narrow(40)));
+        assert_eq!(code.to_string() + "\n", format_code(code, &narrow(100)));
+    }
+
+    #[test]
+    fn breaks_operator_chains() {
+        let code = "data |> filter(value > 10) |> summarise(total = sum(value))";
+        let expected = "data |>\n  filter(value > 10) |>\n  summarise(total = sum(value))\n";
+        assert_eq!(expected, format_code(code, &narrow(40)));
+    }
+
+    #[test]
+    fn keeps_function_bodies_inline_in_calls() {
+        let code = "lapply(xs, function(x) {\n  x + 1\n})\n";
+        assert_eq!(code, format_code(code, &Style::default()));
+    }
+
+    #[test]
+    fn keeps_lambdas() {
+        let code = "v <- sapply(xs, \\(x)x+1)\nadd <- \\(x, y = 1) {\nz <- x + y\nz\n}\n";
+        assert_eq!(
+            "v <- sapply(xs, \\(x) x+1)\nadd <- \\(x, y = 1) {\n  z <- x+y\n  z\n}\n",
+            format_code(
+                code,
+                &Style {
+                    spaces_around_operators: false,
+                    ..Style::default()
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn keeps_code_it_cannot_format_faithfully() {
+        let code = "f(a, # first\n  b)\nx <- )\ny<-1\n";
+        assert_eq!(
+            "f(a, # first\n  b)\nx <- )\ny <- 1\n",
+            format_code(code, &Style::default())
+        );
+    }
+
+    #[test]
+    fn counts_comments_outside_strings() {
+        assert_eq!(2, count_comments("a # one \"\n'#' # two"));
+    }
+}
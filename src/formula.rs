@@ -0,0 +1,334 @@
+use std::fmt::Display;
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::parser::{Expression, ExpressionKind, Literal};
+
+/// A model formula like `Speed ~ Layout * Posture + (1 | Subject)`, broken down into its terms.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Formula {
+    /// The left-hand side of a two-sided formula.
+    pub response: Option<Expression>,
+    /// The fixed-effect terms in the order they appear, with interactions expanded and removed terms left out.
+    pub terms: Vec<Term>,
+    /// The terms removed with `-`, e. g. `Age` in `Speed ~ . - Age`.
+    pub removed: Vec<Term>,
+    /// Whether the model has an intercept, which is dropped by `- 1` or `+ 0`.
+    pub intercept: bool,
+    /// Whether the formula contains `.`, which stands for all columns of the data not otherwise in the formula.
+    pub dot: bool,
+    /// The arguments of `offset()` terms, which are included with a fixed coefficient of one.
+    pub offsets: Vec<Expression>,
+    /// The random-effect terms written as `(terms | group)`.
+    pub random_effects: Vec<RandomEffect>,
+}
+
+/// A term of a formula: either the main effect of a single factor, or the interaction of multiple factors.
+///
+/// A factor is usually a variable, but can be any expression like `I(x^2)` or `log(x)`.
+/// The factors are kept sorted, such that `a:b` and `b:a` are the same term.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Term {
+    pub factors: Vec<Expression>,
+}
+
+/// A random-effect term like `(1 + Day | Subject)`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RandomEffect {
+    /// The terms that vary between the groups.
+    pub terms: Vec<Term>,
+    /// Whether the intercept varies between the groups.
+    pub intercept: bool,
+    /// The grouping factor, e. g. `Subject` or the nested `School/Class`.
+    pub group: Expression,
+    /// Whether the effects are correlated. `||` makes them uncorrelated.
+    pub correlated: bool,
+}
+
+impl Formula {
+    /// Breaks down a formula expression into its terms.
+    ///
+    /// Returns `None` if the expression is not a formula.
+    pub fn from_expression(expression: &Expression) -> Option<Self> {
+        use ExpressionKind::*;
+        let (response, right) = match &expression.kind {
+            OneSidedFormula(right) => (None, right),
+            TwoSidedFormula(left, right) => (Some(left.as_ref().clone()), right),
+            _ => return None,
+        };
+        let mut formula = Formula::empty(response);
+        formula.terms = formula.expand(right);
+        Some(formula)
+    }
+
+    fn empty(response: Option<Expression>) -> Self {
+        Formula {
+            response,
+            terms: Vec::new(),
+            removed: Vec::new(),
+            intercept: true,
+            dot: false,
+            offsets: Vec::new(),
+            random_effects: Vec::new(),
+        }
+    }
+
+    /// Returns the terms of the expression, recording intercepts, removals, offsets and random effects along the way.
+    fn expand(&mut self, expression: &Expression) -> Vec<Term> {
+        use ExpressionKind::*;
+        match &expression.kind {
+            Infix(op, left, right) => match op.as_str() {
+                "+" => union(self.expand(left), self.expand(right)),
+                "-" => {
+                    let terms = self.expand(left);
+                    let removed = self.remove(right);
+                    terms
+                        .into_iter()
+                        .filter(|term| !removed.contains(term))
+                        .collect()
+                }
+                ":" | "%in%" => interact(&self.expand(left), &self.expand(right)),
+                "*" => {
+                    let (left, right) = (self.expand(left), self.expand(right));
+                    let interactions = interact(&left, &right);
+                    union(union(left, right), interactions)
+                }
+                // `a / b` nests `b` within `a` and stands for `a + a:b`.
+                "/" => {
+                    let (left, right) = (self.expand(left), self.expand(right));
+                    let outer = Term::new(left.iter().flat_map(|term| term.factors.clone()));
+                    let nested = interact(&[outer], &right);
+                    union(left, nested)
+                }
+                // `(a + b + c)^2` contains all interactions of up to two factors.
+                "^" => {
+                    let terms = self.expand(left);
+                    let order = match &right.kind {
+                        Constant(Literal::Inf) => Some(f64::INFINITY),
+                        Constant(literal) => literal.number_value(),
+                        _ => None,
+                    };
+                    let order = match order {
+                        Some(order) => order,
+                        None => return vec![Term::single(expression)],
+                    };
+                    // Once all factors interact, higher powers add nothing, e. g. in `(a + b)^1e9`.
+                    let mut result = terms.clone();
+                    for _ in 1..order as usize {
+                        let interactions = interact(&result, &terms);
+                        let count = result.len();
+                        result = union(result, interactions);
+                        if result.len() == count {
+                            break;
+                        }
+                    }
+                    result
+                }
+                "|" | "||" => {
+                    self.add_random_effect(left, right, op == "|");
+                    Vec::new()
+                }
+                _ => vec![Term::single(expression)],
+            },
+            Prefix(op, operand) if op == "-" => {
+                self.remove(operand);
+                Vec::new()
+            }
+            Prefix(op, operand) if op == "+" => self.expand(operand),
+            Paren(inner) => self.expand(inner),
+            Variable(name) if name == "." => {
+                self.dot = true;
+                Vec::new()
+            }
+            Constant(literal) if literal.number_value() == Some(0.0) => {
+                self.intercept = false;
+                Vec::new()
+            }
+            Constant(literal) if literal.number_value() == Some(1.0) => {
+                self.intercept = true;
+                Vec::new()
+            }
+            Call(function, args) if function.function_name() == Some("offset") => {
+                self.offsets
+                    .extend(args.iter().map(|(_, arg)| arg.clone()).take(1));
+                Vec::new()
+            }
+            // Anything else is a single factor, including `I(...)` which protects arithmetic from the formula syntax.
+            _ => vec![Term::single(expression)],
+        }
+    }
+
+    /// Records the removal of the expression's terms and returns them.
+    fn remove(&mut self, expression: &Expression) -> Vec<Term> {
+        if let ExpressionKind::Constant(literal) = &expression.kind {
+            if literal.number_value() == Some(1.0) {
+                self.intercept = false;
+                return Vec::new();
+            }
+        }
+        let removed = self.expand(expression);
+        self.removed = union(self.removed.clone(), removed.clone());
+        removed
+    }
+
+    fn add_random_effect(&mut self, terms: &Expression, group: &Expression, correlated: bool) {
+        let mut effect = Formula::empty(None);
+        effect.terms = effect.expand(terms);
+        self.random_effects.push(RandomEffect {
+            terms: effect.terms,
+            intercept: effect.intercept,
+            group: group.clone(),
+            correlated,
+        });
+    }
+}
+
+impl Term {
+    /// Creates a term from its factors, ignoring repeated factors, since `a:a` is just `a`.
+    pub fn new(factors: impl IntoIterator<Item = Expression>) -> Self {
+        let factors = factors
+            .into_iter()
+            .sorted_by_key(|factor| factor.to_string())
+            .dedup()
+            .collect();
+        Term { factors }
+    }
+
+    fn single(factor: &Expression) -> Self {
+        Term::new(vec![factor.clone()])
+    }
+
+    /// Whether the term is an interaction of multiple factors.
+    pub fn is_interaction(&self) -> bool {
+        self.factors.len() > 1
+    }
+}
+
+impl Display for Term {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.factors.iter().join(":"))
+    }
+}
+
+/// Appends the terms from `other` that are not yet in `terms`.
+fn union(mut terms: Vec<Term>, other: Vec<Term>) -> Vec<Term> {
+    for term in other {
+        if !terms.contains(&term) {
+            terms.push(term);
+        }
+    }
+    terms
+}
+
+/// Returns the interactions of every term on the left with every term on the right.
+fn interact(left: &[Term], right: &[Term]) -> Vec<Term> {
+    let interactions = left
+        .iter()
+        .cartesian_product(right)
+        .map(|(l, r)| Term::new(l.factors.iter().chain(&r.factors).cloned()))
+        .collect();
+    union(Vec::new(), interactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::parser::parse_statements;
+
+    fn parse_formula(code: &str) -> Formula {
+        let parsed = parse_statements(code).unwrap();
+        let stmt = parsed.into_iter().next().unwrap().0;
+        Formula::from_expression(stmt.expression().unwrap()).unwrap()
+    }
+
+    fn terms(terms: &[Term]) -> Vec<String> {
+        terms.iter().map(|term| term.to_string()).collect()
+    }
+
+    #[test]
+    fn expands_interactions() {
+        let formula = parse_formula("Speed ~ Layout * Posture + Age:Layout");
+        assert_eq!(
+            Some("Speed".to_string()),
+            formula.response.map(|r| r.to_string())
+        );
+        assert_eq!(
+            vec!["Layout", "Posture", "Layout:Posture", "Age:Layout"],
+            terms(&formula.terms)
+        );
+        assert!(formula.intercept);
+    }
+
+    #[test]
+    fn expands_powers_and_nesting() {
+        assert_eq!(
+            vec!["a", "b", "c", "a:b", "a:c", "b:c"],
+            terms(&parse_formula("y ~ (a + b + c)^2").terms)
+        );
+        assert_eq!(
+            vec!["a", "b", "a:b"],
+            terms(&parse_formula("y ~ (a + b)^1e9").terms)
+        );
+        assert_eq!(
+            vec!["a", "b", "a:b"],
+            terms(&parse_formula("y ~ (a + b)^Inf").terms)
+        );
+        assert_eq!(
+            vec!["School", "Class:School"],
+            terms(&parse_formula("y ~ School / Class").terms)
+        );
+    }
+
+    #[test]
+    fn removes_terms_and_intercept() {
+        let formula = parse_formula("Speed ~ . - Age - 1");
+        assert!(formula.dot);
+        assert!(!formula.intercept);
+        assert!(formula.terms.is_empty());
+        assert_eq!(vec!["Age"], terms(&formula.removed));
+
+        let formula = parse_formula("y ~ 0 + x + z - z");
+        assert_eq!(vec!["x"], terms(&formula.terms));
+        assert!(!formula.intercept);
+    }
+
+    #[test]
+    fn keeps_protected_arithmetic_and_offsets() {
+        let formula = parse_formula("y ~ I(x^2) + log(z) + offset(log(n))");
+        assert_eq!(vec!["I(x ^ 2)", "log(z)"], terms(&formula.terms));
+        assert_eq!(
+            vec!["log(n)".to_string()],
+            formula
+                .offsets
+                .iter()
+                .map(|offset| offset.to_string())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn separates_random_effects() {
+        let formula = parse_formula("Speed ~ Layout * Posture + (1 | Subject) + (0 + Day || Item)");
+        assert_eq!(
+            vec!["Layout", "Posture", "Layout:Posture"],
+            terms(&formula.terms)
+        );
+        let subject = &formula.random_effects[0];
+        assert_eq!("Subject", subject.group.to_string());
+        assert!(subject.intercept && subject.terms.is_empty() && subject.correlated);
+        let item = &formula.random_effects[1];
+        assert_eq!("Item", item.group.to_string());
+        assert_eq!(vec!["Day"], terms(&item.terms));
+        assert!(!item.intercept && !item.correlated);
+    }
+
+    #[test]
+    fn parses_one_sided_formulae() {
+        let formula = parse_formula("~ Layout + Posture");
+        assert_eq!(None, formula.response);
+        assert_eq!(vec!["Layout", "Posture"], terms(&formula.terms));
+    }
+}
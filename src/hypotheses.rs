@@ -1,8 +1,8 @@
 use std::collections::BTreeSet;
 use std::iter::FromIterator;
-use std::ops::Deref;
 
-use crate::parser::{Expression, RIdentifier};
+use crate::formula::Formula;
+use crate::parser::{desugar_magrittr, desugar_pipe, Expression, ExpressionKind, RIdentifier};
 
 pub type Hypothesis = String;
 
@@ -12,12 +12,14 @@ pub type Hypotheses = BTreeSet<Hypothesis>;
 ///
 /// Requires the expression to have all dependencies inlined, or hypothesis behind variables may not be detected.
 pub fn detect_hypotheses(expression: &Expression) -> Hypotheses {
-    use Expression::*;
-    match expression {
-        TwoSidedFormula(left, right) => BTreeSet::from_iter(vec![format!("{} ~ {}", left, right)]),
+    use ExpressionKind::*;
+    match &expression.kind {
+        TwoSidedFormula(_, _) => Formula::from_expression(expression)
+            .map(|formula| detect_hypotheses_in_formula(&formula))
+            .unwrap_or_default(),
 
         // Because of the Box, we cannot (in current Rust) use one general pattern, but have to go step by step with some referencing magic.
-        Column(left, dependent) => match &**left {
+        Column(left, dependent) => match &left.kind {
             Index(_variable, inner) => {
                 // variable[variable$independent == "level",]$dependent
                 // |----------------left--------------------|
@@ -27,16 +29,14 @@ pub fn detect_hypotheses(expression: &Expression) -> Hypotheses {
 
                 //if let [Some(Infix(operator, inner_left, _, _)), None] = inner.as_slice() {
                 if inner.len() == 2 && inner[1].is_none() {
-                    if let Some(infix) = &inner[0] {
-                        if let Infix(_, inner_left, _) = &*infix {
-                            if let Column(_, independent) = &**inner_left {
-                                if let Variable(_) = &**independent {
-                                    if let Variable(_) = &**dependent {
-                                        return BTreeSet::from_iter(vec![format!(
-                                            "{} ~ {}",
-                                            dependent, independent
-                                        )]);
-                                    }
+                    if let Some(Infix(_, inner_left, _)) = inner[0].as_ref().map(|exp| &exp.kind) {
+                        if let Column(_, independent) = &inner_left.kind {
+                            if let Variable(_) = &independent.kind {
+                                if let Variable(_) = &dependent.kind {
+                                    return BTreeSet::from_iter(vec![format!(
+                                        "{} ~ {}",
+                                        dependent, independent
+                                    )]);
                                 }
                             }
                         }
@@ -46,17 +46,15 @@ pub fn detect_hypotheses(expression: &Expression) -> Hypotheses {
             }
             // subset(data, independent == "level")$dependent
             Call(fun, args) => {
-                if let Variable(fun_name) = fun.deref() {
-                    if fun_name == "subset" {
-                        if let Some(right) = args.get(1) {
-                            if let Infix(_, independent, _) = &right.1 {
-                                if let Variable(_) = &**independent {
-                                    if let Variable(_) = &**dependent {
-                                        return BTreeSet::from_iter(vec![format!(
-                                            "{} ~ {}",
-                                            dependent, independent
-                                        )]);
-                                    }
+                if let Some("subset") = fun.function_name() {
+                    if let Some(right) = args.get(1) {
+                        if let Infix(_, independent, _) = &right.1.kind {
+                            if let Variable(_) = &independent.kind {
+                                if let Variable(_) = &dependent.kind {
+                                    return BTreeSet::from_iter(vec![format!(
+                                        "{} ~ {}",
+                                        dependent, independent
+                                    )]);
                                 }
                             }
                         }
@@ -65,19 +63,75 @@ pub fn detect_hypotheses(expression: &Expression) -> Hypotheses {
                 detect_hypotheses_in_args(args)
             }
 
-            left => detect_hypotheses(left),
+            _ => detect_hypotheses(left),
         },
 
+        // The aesthetics of a plot, e. g. `ggplot(kbd, aes(x = Layout, y = Speed))`, which map columns to the axes.
+        Call(fun, args) if fun.function_name() == Some("aes") => {
+            let x = aesthetic(args, "x", 0);
+            let y = aesthetic(args, "y", 1);
+            match (x.map(|x| &x.kind), y.map(|y| &y.kind)) {
+                (Some(Variable(x)), Some(Variable(y))) => {
+                    BTreeSet::from_iter(vec![format!("{} ~ {}", y, x)])
+                }
+                _ => BTreeSet::new(),
+            }
+        }
         Call(_, args) => detect_hypotheses_in_args(args),
+        // The layers of a plot are added with `+`.
+        Infix(op, left, right) if op == "+" => detect_hypotheses(left)
+            .into_iter()
+            .chain(detect_hypotheses(right))
+            .collect(),
+        Paren(exp) => detect_hypotheses(exp),
+        Pipe(left, right) => detect_hypotheses(&desugar_pipe(left, right)),
+        Infix(op, left, right) => desugar_magrittr(op, left, right)
+            .map(|call| detect_hypotheses(&call))
+            .unwrap_or_default(),
         _ => BTreeSet::new(),
     }
 }
 
+/// Each fixed-effect term of a model formula is a hypothesis about the response.
+///
+/// The intercept and random effects are not hypotheses. A `.` stands for all remaining columns, so it is kept together with the removed terms.
+fn detect_hypotheses_in_formula(formula: &Formula) -> Hypotheses {
+    let response = match &formula.response {
+        Some(response) => response,
+        None => return BTreeSet::new(),
+    };
+    let mut hypotheses: Hypotheses = formula
+        .terms
+        .iter()
+        .map(|term| format!("{} ~ {}", response, term))
+        .collect();
+    if formula.dot {
+        let removed: String = formula
+            .removed
+            .iter()
+            .map(|term| format!(" - {}", term))
+            .collect();
+        hypotheses.insert(format!("{} ~ .{}", response, removed));
+    }
+    hypotheses
+}
+
+/// Returns the argument of `aes()` for the aesthetic with the `name`, which is otherwise the unnamed argument at `position`.
+fn aesthetic<'a>(
+    args: &'a [(Option<RIdentifier>, Expression)],
+    name: &str,
+    position: usize,
+) -> Option<&'a Expression> {
+    args.iter()
+        .find(|(arg, _)| arg.as_deref() == Some(name))
+        .or_else(|| args.iter().filter(|(arg, _)| arg.is_none()).nth(position))
+        .map(|(_, exp)| exp)
+}
+
 /// Helper function for extracting and merging the hypotheses out of function arguments.
 fn detect_hypotheses_in_args(args: &[(Option<RIdentifier>, Expression)]) -> Hypotheses {
     args.iter()
-        .map(|(_, exp)| detect_hypotheses(exp))
-        .flatten()
+        .flat_map(|(_, exp)| detect_hypotheses(exp))
         .collect()
 }
 
@@ -99,7 +153,28 @@ mod tests {
     #[test]
     fn parses_formula_notation_in_call() {
         let code = r#"test(speed ~ layout + age)"#;
-        let expected = BTreeSet::from_iter(vec!["speed ~ layout + age".to_string()]);
+        let expected = BTreeSet::from_iter(vec![
+            "speed ~ age".to_string(),
+            "speed ~ layout".to_string(),
+        ]);
+        test_hypothesis(expected, code);
+    }
+
+    #[test]
+    fn parses_formula_terms() {
+        let code = r#"lmer(Speed ~ Layout * Posture + (1|Subject))"#;
+        let expected = BTreeSet::from_iter(vec![
+            "Speed ~ Layout".to_string(),
+            "Speed ~ Posture".to_string(),
+            "Speed ~ Layout:Posture".to_string(),
+        ]);
+        test_hypothesis(expected, code);
+    }
+
+    #[test]
+    fn parses_formula_with_dot() {
+        let code = r#"lm(Speed ~ . - Age, data = kbd)"#;
+        let expected = BTreeSet::from_iter(vec!["Speed ~ . - Age".to_string()]);
         test_hypothesis(expected, code);
     }
 
@@ -131,13 +206,29 @@ mod tests {
         test_hypothesis(expected, code);
     }
 
+    #[test]
+    fn parses_hypothesis_through_native_pipe() {
+        let code = r#"kbd[kbd$Layout == "QWERTY",]$Speed |> hist()"#;
+        let expected = BTreeSet::from_iter(vec!["Speed ~ Layout".to_string()]);
+        test_hypothesis(expected, code);
+    }
+
+    #[test]
+    fn parses_plot_aesthetics() {
+        let code = r#"ggplot(kbd, aes(x = Layout, y = Speed, fill = Posture)) + geom_boxplot()"#;
+        let expected = BTreeSet::from_iter(vec!["Speed ~ Layout".to_string()]);
+        test_hypothesis(expected, code);
+        let code = r#"ggplot(kbd, aes(Layout, Speed))"#;
+        let expected = BTreeSet::from_iter(vec!["Speed ~ Layout".to_string()]);
+        test_hypothesis(expected, code);
+    }
+
     fn test_hypothesis(expected: BTreeSet<Hypothesis>, code: &'static str) {
         let parsed = parser::parse_statements(code).unwrap();
         let stmt = parsed.into_iter().next().unwrap().0;
         let exp = stmt.expression().unwrap();
-        let result = detect_hypotheses(&exp);
+        let result = detect_hypotheses(exp);
 
         assert_eq!(expected, result);
     }
-
 }
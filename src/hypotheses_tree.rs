@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::dependency_graph;
 use crate::hypotheses::{detect_hypotheses, Hypotheses, Hypothesis};
-use crate::parser::{Statement, StatementId, Statements};
+use crate::parser::{StatementId, StatementKind, Statements};
 use dependency_graph::DependencyGraph;
 
 /// A tree grouping `Statement`s by their hypotheses.
@@ -14,6 +14,8 @@ pub struct HypothesisTree<T> {
     root: Branches<T, BlockId>,
     hypotheses: BTreeMap<HypothesesId, Hypotheses>,
     blocks: Vec<Vec<StatementId>>,
+    /// The titles of blocks that start a section, e. g. a chunk of an R Markdown document.
+    titles: BTreeMap<BlockId, String>,
 }
 
 /// The branches of a `HypothesisTree`, grouped by hypotheses.
@@ -21,10 +23,6 @@ pub struct HypothesisTree<T> {
 pub struct Branches<C, H>(pub BTreeMap<HypothesesId, Vec<Node<C, H>>>);
 
 impl<C, H> Branches<C, H> {
-    fn new() -> Self {
-        Branches(BTreeMap::new())
-    }
-
     /// Consume the `Branches` and return them with mapping applied to all `Node` contents.
     fn into_map<N, F>(self, mapping: &mut F) -> Branches<N, H>
     where
@@ -63,13 +61,6 @@ pub enum Node<C, H> {
 }
 
 impl<C, H> Node<C, H> {
-    fn new(content: C) -> Self {
-        Node::Single {
-            content,
-            children: Branches::new(),
-        }
-    }
-
     fn into_map<N, F>(self, mapping: &mut F) -> Node<N, H>
     where
         F: FnMut(C) -> N,
@@ -89,7 +80,7 @@ impl<C, H> Node<C, H> {
 }
 
 #[derive(Default)]
-pub struct HypothesesMap(Vec<(Hypotheses)>);
+pub struct HypothesesMap(Vec<Hypotheses>);
 
 impl HypothesesMap {
     pub fn new() -> Self {
@@ -141,6 +132,17 @@ impl HypothesisTree<StatementId> {
     ///
     /// Requires all elements of `stmts` to be tracked in the `dependency_graph`.
     pub fn new<T>(stmts: &Statements<T>, dependency_graph: &DependencyGraph) -> Self {
+        Self::with_sections(stmts, dependency_graph, &BTreeMap::new())
+    }
+
+    /// Creates a new `HypothesisTree` whose blocks also start at the `sections`, which map their first statement to their title.
+    ///
+    /// Otherwise, blocks start at comments that precede code. A block with a title is not ended by empty lines, but only by the next block.
+    pub fn with_sections<T>(
+        stmts: &Statements<T>,
+        dependency_graph: &DependencyGraph,
+        sections: &BTreeMap<StatementId, String>,
+    ) -> Self {
         let mut roots: HashMap<HypothesesId, Vec<RefNode>> = HashMap::new();
         let mut hypotheses_map: HypothesesMap = HypothesesMap::new();
         let mut node_map: NodeMap = HashMap::new();
@@ -148,29 +150,41 @@ impl HypothesisTree<StatementId> {
         let mut blocks: Vec<Vec<StatementId>> = Vec::new();
         let mut next_block_comment: Option<Vec<StatementId>> = None;
         let mut block_index: Option<BlockId> = None;
+        let mut titles = BTreeMap::new();
+        let mut next_title: Option<String> = None;
 
         for (stmt_id, stmt, _) in stmts.iter() {
-            if let Statement::Comment(_) = stmt {
+            if let Some(title) = sections.get(&stmt_id) {
+                // Comments at the end of the previous section do not belong to any code.
+                next_block_comment = None;
+                next_title = Some(title.clone());
+                block_index = None;
+            }
+            if let StatementKind::Comment(_) = stmt.kind {
                 next_block_comment
                     .get_or_insert_with(Vec::new)
                     .push(stmt_id);
-            } else if let Statement::Empty = stmt {
+            } else if let StatementKind::Empty = stmt.kind {
                 match &mut next_block_comment {
                     Some(block) => block.push(stmt_id),
+                    None if block_index.is_some_and(|index| titles.contains_key(&index)) => {}
                     None => block_index = None, // Prevent new statements from being added to block.
                 }
-            } else if let Some(_expression) = stmt.expression() {
-                if let Some(next) = next_block_comment.take() {
-                    blocks.push(next);
+            } else if !stmt.expressions().is_empty() {
+                if next_block_comment.is_some() || next_title.is_some() {
+                    blocks.push(next_block_comment.take().unwrap_or_default());
                     block_index = Some(blocks.len() - 1);
+                    if let Some(title) = next_title.take() {
+                        titles.insert(blocks.len() - 1, title);
+                    }
                 }
 
                 let hyp_id = Self::collect_hypotheses(
                     stmt_id,
                     &node_map,
                     &mut hypotheses_map,
-                    &dependency_graph,
-                    &stmts,
+                    dependency_graph,
+                    stmts,
                 );
                 node_map.insert(stmt_id, (hyp_id, HashMap::new(), block_index));
 
@@ -179,7 +193,7 @@ impl HypothesisTree<StatementId> {
                 match parents.last() {
                     Some(parent_id) => {
                         let (_, parent_children, parent_block) =
-                            &mut node_map.get_mut(&parent_id).unwrap(); // Parent has already been analyzed and is thus in node map.
+                            &mut node_map.get_mut(parent_id).unwrap(); // Parent has already been analyzed and is thus in node map.
 
                         let siblings = parent_children.entry(hyp_id).or_insert_with(Vec::new);
                         if parent_block != &block_index {
@@ -221,7 +235,7 @@ impl HypothesisTree<StatementId> {
                             }
                             None => RefNode::Statement(stmt_id),
                         };
-                        roots.entry(hyp_id).or_insert_with(Vec::new).push(node_id);
+                        roots.entry(hyp_id).or_default().push(node_id);
                     }
                 }
             }
@@ -242,6 +256,7 @@ impl HypothesisTree<StatementId> {
                 .collect(),
             hypotheses: hypotheses_map.into_map(),
             blocks,
+            titles,
         }
     }
 
@@ -255,8 +270,8 @@ impl HypothesisTree<StatementId> {
         let inherited_hypotheses: Vec<Hypothesis> = dependency_graph
             .parents(id)
             .iter()
-            .map(|id| {
-                let hypotheses_id = node_map[&id].0; // Parents are already analyzed and thus in the node map.
+            .flat_map(|id| {
+                let hypotheses_id = node_map[id].0; // Parents are already analyzed and thus in the node map.
                 hypotheses_map
                     .get(hypotheses_id)
                     .unwrap()
@@ -264,11 +279,13 @@ impl HypothesisTree<StatementId> {
                     .cloned()
                     .collect::<Vec<Hypothesis>>()
             })
-            .flatten()
             .collect();
 
-        let inlined_exp = dependency_graph.inline_id(id, stmts).unwrap();
-        let mut hypotheses = detect_hypotheses(&inlined_exp);
+        let mut hypotheses: Hypotheses = dependency_graph
+            .inline_expressions(id, stmts)
+            .iter()
+            .flat_map(detect_hypotheses)
+            .collect();
         for hyp in inherited_hypotheses {
             hypotheses.insert(hyp);
         }
@@ -316,6 +333,7 @@ impl<T> HypothesisTree<T> {
             root: self.root.into_map(&mut mapping),
             hypotheses: self.hypotheses,
             blocks: self.blocks,
+            titles: self.titles,
         }
     }
 }
@@ -334,13 +352,16 @@ mod tests {
     #[test]
     fn simple_hypothesis_tree() {
         let input = Statements::from_iter(vec![
-            assignment!(variable!("kbd"), vec![], constant!("data frame")),
+            assignment!(variable!("kbd"), vec![], constant!("\"data frame\"")),
             assignment!(
-                column!(variable!("kbd"), constant!("ParticipantID")),
+                column!(variable!("kbd"), constant!("\"ParticipantID\"")),
                 vec![],
                 call!(
                     variable!("factor"),
-                    vec![(None, column!(variable!("kbd"), constant!("ParticipantID")))]
+                    vec![(
+                        None,
+                        column!(variable!("kbd"), constant!("\"ParticipantID\""))
+                    )]
                 )
             ),
             expression!(call!(
@@ -429,6 +450,121 @@ mod tests {
         assert_eq!(expected, tree.root);
     }
 
+    #[test]
+    fn loop_hypotheses() {
+        let input = crate::parser::parse_statements(
+            "\
+kbd <- read.csv(\"kbd.csv\")
+res <- list()
+for (l in levels(kbd$Layout)) {
+    res[[l]] <- t.test(Speed ~ Posture, data = kbd[kbd$Layout == l, ])
+}
+print(res)",
+        )
+        .unwrap();
+        let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+        let dependency_graph = DependencyGraph::from_input(&input);
+        let tree = HypothesisTree::new(&input, &dependency_graph);
+
+        let print = Node::Single {
+            content: ids[3],
+            children: Branches::from_iter(vec![]),
+        };
+        let for_loop = Node::Single {
+            content: ids[2],
+            children: Branches::from_iter(vec![(
+                find_hyp(&["Speed ~ Posture"], &tree),
+                vec![print],
+            )]),
+        };
+        let kbd = Node::Single {
+            content: ids[0],
+            children: Branches::from_iter(vec![]),
+        };
+        // The loop changes `res`, which makes it the more recent parent.
+        let res = Node::Single {
+            content: ids[1],
+            children: Branches::from_iter(vec![(
+                find_hyp(&["Speed ~ Posture"], &tree),
+                vec![for_loop],
+            )]),
+        };
+        assert_eq!(
+            Branches::from_iter(vec![(find_hyp(&[], &tree), vec![kbd, res])]),
+            tree.root
+        );
+    }
+
+    #[test]
+    fn function_hypotheses() {
+        let input = crate::parser::parse_statements(
+            "\
+kbd <- read.csv(\"kbd.csv\")
+analyze <- function(d) t.test(Speed ~ Layout, data = d)
+analyze(kbd)",
+        )
+        .unwrap();
+        let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+        let dependency_graph = DependencyGraph::from_input(&input);
+        let tree = HypothesisTree::new(&input, &dependency_graph);
+
+        let kbd = Node::Single {
+            content: ids[0],
+            children: Branches::from_iter(vec![]),
+        };
+        let call = Node::Single {
+            content: ids[2],
+            children: Branches::from_iter(vec![]),
+        };
+        let analyze = Node::Single {
+            content: ids[1],
+            children: Branches::from_iter(vec![(find_hyp(&["Speed ~ Layout"], &tree), vec![call])]),
+        };
+        assert_eq!(
+            Branches::from_iter(vec![(find_hyp(&[], &tree), vec![kbd, analyze])]),
+            tree.root
+        );
+    }
+
+    #[test]
+    fn pipe_hypotheses() {
+        let input = crate::parser::parse_statements(
+            "\
+kbd <- read.csv(\"kbd.csv\")
+kbd %<>% filter(Speed > 0)
+kbd %>% t.test(Speed ~ Layout, data = .)
+kbd %$% t.test(Errors ~ Layout)",
+        )
+        .unwrap();
+        let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+        let dependency_graph = DependencyGraph::from_input(&input);
+        let tree = HypothesisTree::new(&input, &dependency_graph);
+
+        let speed = Node::Single {
+            content: ids[2],
+            children: Branches::from_iter(vec![]),
+        };
+        let errors = Node::Single {
+            content: ids[3],
+            children: Branches::from_iter(vec![]),
+        };
+        let filter = Node::Single {
+            content: ids[1],
+            children: Branches::from_iter(vec![
+                (find_hyp(&["Speed ~ Layout"], &tree), vec![speed]),
+                (find_hyp(&["Errors ~ Layout"], &tree), vec![errors]),
+            ]),
+        };
+        let kbd = Node::Single {
+            content: ids[0],
+            children: Branches::from_iter(vec![(find_hyp(&[], &tree), vec![filter])]),
+        };
+        assert_eq!(
+            Branches::from_iter(vec![(find_hyp(&[], &tree), vec![kbd])]),
+            tree.root
+        );
+    }
+
     fn find_hyp(hyp: &[&'static str], tree: &HypothesisTree<StatementId>) -> HypothesesId {
         let hypotheses = hyp.iter().map(|h| h.to_string()).collect::<Hypotheses>();
         *tree
@@ -439,5 +575,53 @@ mod tests {
             .0
     }
 
+    #[test]
+    fn titles_sections() {
+        let input = crate::parser::parse_statements(
+            "\
+a <- 1
+
+b <- a
+# Plot
+plot(b)",
+        )
+        .unwrap();
+        let ids: Vec<StatementId> = input.iter().map(|(id, _, _)| id).collect();
+        let sections = BTreeMap::from_iter(vec![(ids[0], "Setup (load)".to_string())]);
+        let dependency_graph = DependencyGraph::from_input(&input);
+        let tree = HypothesisTree::with_sections(&input, &dependency_graph, &sections);
+
+        // Empty lines do not end a section, but comments start a new block.
+        assert_eq!(vec![Vec::new(), vec![ids[3]]], tree.blocks);
+        assert_eq!(
+            BTreeMap::from_iter(vec![(0, "Setup (load)".to_string())]),
+            tree.titles
+        );
+        let b = Node::Group {
+            header: 1,
+            elements: vec![Node::Single {
+                content: ids[4],
+                children: Branches::from_iter(vec![]),
+            }],
+        };
+        let a = Node::Group {
+            header: 0,
+            elements: vec![Node::Single {
+                content: ids[0],
+                children: Branches::from_iter(vec![(
+                    find_hyp(&[], &tree),
+                    vec![Node::Single {
+                        content: ids[2],
+                        children: Branches::from_iter(vec![(find_hyp(&[], &tree), vec![b])]),
+                    }],
+                )]),
+            }],
+        };
+        assert_eq!(
+            Branches::from_iter(vec![(find_hyp(&[], &tree), vec![a])]),
+            tree.root
+        );
+    }
+
     // TODO: Test blocks/groups
 }
@@ -1,32 +1,53 @@
 #[macro_use]
 extern crate pest_derive;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
+pub mod data_masking;
 pub mod dependency_graph;
+pub mod document;
+pub mod formatter;
+pub mod formula;
 pub mod hypotheses;
 pub mod hypotheses_tree;
+pub mod lint;
 pub mod parser;
+pub mod slice;
 
 pub use crate::dependency_graph::DependencyGraph;
 pub use crate::hypotheses_tree::HypothesisTree;
-pub use crate::parser::{Expression, LineSpan, Parsed, RIdentifier, Statement, StatementId};
+pub use crate::parser::{
+    AssignmentDirection, Diagnostic, Expression, ExpressionKind, Incomplete, LineSpan, Literal,
+    Parsed, Position, RIdentifier, Span, Statement, StatementId, StatementKind,
+};
 
 #[derive(Serialize, Deserialize, Default)]
 pub struct Tractus {
     parsed: Parsed<(LineSpan, serde_json::Value)>,
     dependency_graph: DependencyGraph,
+    /// The titles of the document chunks, by the first statement of each chunk.
+    #[serde(default)]
+    sections: BTreeMap<StatementId, String>,
+    /// The files that were parsed, including the sourced files that could not be read.
+    #[serde(default)]
+    files: Vec<PathBuf>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct StatementMeta {
     statement: String,
+    /// The statement as printed by the `formatter` in the default style.
+    formatted: String,
     ast: serde_json::Value,
     expression: Option<String>,
     span: LineSpan,
+    /// The exact location of the statement. The nodes in the `ast` carry their own locations.
+    location: Span,
     assignment: Option<(Vec<RIdentifier>, String)>,
     function_call: Option<(String, Vec<RIdentifier>)>,
     meta: serde_json::Value,
@@ -36,12 +57,14 @@ impl StatementMeta {
     fn with(stmt: &Statement, span: LineSpan, meta: serde_json::Value) -> Self {
         let assignment = break_down_assignment(stmt);
         let expression = stmt.expression();
-        let function_call = expression.and_then(|exp| extract_function_name(exp));
+        let function_call = expression.and_then(extract_function_name);
         StatementMeta {
             expression: expression.map(|exp| format!("{}", exp)),
             ast: serde_json::to_value(stmt).unwrap(),
             span,
-            statement: format!("{}", stmt),
+            location: stmt.span,
+            statement: stmt.source().into_owned(),
+            formatted: formatter::format_statement(stmt, &formatter::Style::default()),
             assignment,
             function_call,
             meta,
@@ -50,43 +73,144 @@ impl StatementMeta {
 }
 
 fn break_down_assignment(stmt: &Statement) -> Option<(Vec<RIdentifier>, String)> {
-    use Statement::*;
-    match stmt {
-        Assignment(left, add, expression) => {
+    use StatementKind::*;
+    match &stmt.kind {
+        Assignment(left, add, expression, _) => {
             let mut vs = vec![left
-                .extract_variable_name()
+                .assigned_variable_name()
                 .unwrap_or_else(|| left.to_string())];
             let mut addition: Vec<String> = add
                 .iter()
-                .map(|v| v.extract_variable_name().unwrap_or_else(|| v.to_string()))
+                .map(|v| v.assigned_variable_name().unwrap_or_else(|| v.to_string()))
                 .collect();
             vs.append(&mut addition);
             Some((vs, expression.to_string()))
         }
+        // The assignment pipe `kbd %<>% filter(Speed > 0)` assigns the whole pipe to `kbd`.
+        Expression(expression) => parser::pipe_assignment_target(expression).map(|target| {
+            let variable = target
+                .extract_variable_name()
+                .unwrap_or_else(|| target.to_string());
+            (vec![variable], expression.to_string())
+        }),
         TailComment(inner, _) => break_down_assignment(inner),
         Empty
         | Comment(_)
         | If(_, _, _)
         | While(_, _)
         | For(_, _, _)
+        | Repeat(_)
+        | Break
+        | Next
+        | Return(_)
         | Library(_)
-        | Expression(_) => None,
+        | Unparsed(_) => None,
     }
 }
 
 fn extract_function_name(expression: &parser::Expression) -> Option<(String, Vec<RIdentifier>)> {
-    use parser::Expression::*;
-    match expression {
-        Call(name, args) => name.extract_variable_name().map(|name| {
-            let arg_vars = args.iter().flat_map(|(_, exp)| exp.extract_variable_name()).collect();
-            (name, arg_vars)
+    use parser::ExpressionKind::*;
+    match &expression.kind {
+        Call(name, args) => name.function_name().map(|name| {
+            let arg_vars = args
+                .iter()
+                .flat_map(|(_, exp)| exp.extract_variable_name())
+                .collect();
+            (name.to_string(), arg_vars)
         }),
-        Column(left, _) => extract_function_name(&left),
-        Index(left, _) => extract_function_name(&left),
+        Column(left, _) => extract_function_name(left),
+        Slot(left, _) => extract_function_name(left),
+        Index(left, _) => extract_function_name(left),
+        Paren(exp) => extract_function_name(exp),
+        Pipe(left, right) => extract_function_name(&parser::desugar_pipe(left, right)),
+        Infix(op, left, right) => {
+            extract_function_name(&parser::desugar_magrittr(op, left, right)?)
+        }
+        _ => None,
+    }
+}
+
+/// If the statement runs another script with a literal path, e. g. `source("load.R")`, returns that path.
+fn sourced_file(stmt: &Statement) -> Option<String> {
+    use parser::ExpressionKind::*;
+    let (function, args) = match &stmt.expression()?.kind {
+        Call(function, args) => (function, args),
+        _ => return None,
+    };
+    if function.function_name() != Some("source") {
+        return None;
+    }
+    let file = args
+        .iter()
+        .find(|(name, _)| name.as_deref() == Some("file"))
+        .or_else(|| args.iter().find(|(name, _)| name.is_none()))?;
+    match &file.1.kind {
+        Constant(literal) => literal.string_value(),
         _ => None,
     }
 }
 
+/// The statements of a file and the titles of its sections by their first statement.
+type Script = (
+    Parsed<(LineSpan, serde_json::Value)>,
+    BTreeMap<StatementId, String>,
+);
+
+/// Parses the `code` of the file at `path`, which is an R script, an R Markdown or Quarto document, or a Jupyter notebook.
+///
+/// Only the R chunks of a document that are run are parsed, and their statements keep their lines in the document.
+/// The cells of a notebook are parsed in the order they were run, and their statements carry the cell's output as their `result`
+/// and keep their lines in the cell, like the notebook shows them.
+fn parse_script<F>(path: &Path, code: &str, clean: &mut F) -> std::io::Result<Script>
+where
+    F: FnMut(Vec<String>) -> Vec<String>,
+{
+    let mut parsed = Parsed::new();
+    let mut sections = BTreeMap::new();
+    let mut without_meta = |_: &Statement, span| (span, serde_json::Value::Null);
+    if document::is_notebook(path) {
+        let cells = document::notebook_cells(code)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        for cell in cells {
+            let meta = json!({
+                "result": cell.output,
+                "cell": cell.index,
+                "execution_count": cell.execution_count,
+            });
+            let mut with_meta = |_: &Statement, span| (span, meta.clone());
+            parsed.restart();
+            let lines = clean(cell.code.lines().map(|line| line.to_string()).collect());
+            let mut ids = parsed.append_with_meta(lines, &mut with_meta);
+            ids.append(&mut parsed.finish_with_meta(&mut with_meta));
+            if let Some(first) = ids.first() {
+                sections.insert(*first, cell.title());
+            }
+        }
+        return Ok((parsed, sections));
+    }
+    let lines = clean(code.lines().map(|line| line.to_string()).collect());
+    if !document::is_rmarkdown(path) {
+        parsed.append_with_meta(lines, &mut without_meta);
+        parsed.finish_with_meta(&mut without_meta);
+        return Ok((parsed, sections));
+    }
+    let mut next_line = 0;
+    for chunk in document::rmarkdown_chunks(&lines.join("\n")) {
+        if !chunk.is_evaluated_r() {
+            continue;
+        }
+        parsed.skip(&lines[next_line..chunk.lines.start]);
+        let mut ids =
+            parsed.append_with_meta(lines[chunk.lines.clone()].to_vec(), &mut without_meta);
+        ids.append(&mut parsed.finish_with_meta(&mut without_meta)); // Code cannot continue in the next chunk.
+        if let (Some(first), Some(title)) = (ids.first(), chunk.title()) {
+            sections.insert(*first, title);
+        }
+        next_line = chunk.lines.end;
+    }
+    Ok((parsed, sections))
+}
+
 impl Tractus {
     pub fn new() -> Self {
         let parsed = Parsed::new();
@@ -94,6 +218,8 @@ impl Tractus {
         Tractus {
             parsed,
             dependency_graph,
+            sections: BTreeMap::new(),
+            files: Vec::new(),
         }
     }
 
@@ -115,16 +241,156 @@ impl Tractus {
         Ok(())
     }
 
+    /// Parses the R script at `path` along with the scripts it runs with `source()`,
+    /// such that the dependencies and hypotheses span the whole project.
+    /// R Markdown and Quarto documents as well as Jupyter notebooks are recognized by their extension,
+    /// and their chunks and cells become blocks of the hypotheses tree.
+    ///
+    /// Only calls with a literal path are followed, and the path is resolved relative to the directory of the script.
+    /// The sourced statements follow the `source()` call in the order they are run, and their spans carry their file.
+    /// Sourced files that cannot be read, or that would be sourced recursively, are reported as diagnostics.
+    pub fn parse_file(&mut self, path: &Path) -> std::io::Result<()> {
+        self.parse_file_cleaned(path, &mut |lines| lines)
+    }
+
+    /// Like `parse_file`, but cleans the lines of every file with `clean` before parsing them.
+    pub fn parse_file_cleaned<F>(&mut self, path: &Path, clean: &mut F) -> std::io::Result<()>
+    where
+        F: FnMut(Vec<String>) -> Vec<String>,
+    {
+        let code = std::fs::read_to_string(path)?;
+        self.source_file(path, &code, clean, &mut Vec::new())
+    }
+
+    /// Parses the `code` from `path` statement by statement, descending into sourced files.
+    ///
+    /// `sourcing` contains the files that are currently being sourced, which are skipped to avoid infinite recursion.
+    fn source_file<F>(
+        &mut self,
+        path: &Path,
+        code: &str,
+        clean: &mut F,
+        sourcing: &mut Vec<PathBuf>,
+    ) -> std::io::Result<()>
+    where
+        F: FnMut(Vec<String>) -> Vec<String>,
+    {
+        if !self.files.iter().any(|file| file == path) {
+            self.files.push(path.to_path_buf());
+        }
+        let (file, sections) = parse_script(path, code, clean)?;
+        for diagnostic in file.diagnostics() {
+            self.parsed.push_diagnostic(Diagnostic {
+                file: Some(path.to_path_buf()),
+                ..diagnostic.clone()
+            });
+        }
+        sourcing.push(path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
+        for (file_id, stmt, (span, meta)) in file.statements().iter() {
+            let sourced = sourced_file(stmt).map(|file| match path.parent() {
+                Some(directory) => directory.join(file),
+                None => PathBuf::from(file),
+            });
+            let location = stmt.span;
+            let id = self
+                .parsed
+                .push(stmt.clone(), (span.clone().in_file(path), meta.clone()));
+            if let Some(title) = sections.get(&file_id) {
+                self.sections.insert(id, title.clone());
+            }
+            self.dependency_graph
+                .batch_insert(std::iter::once(id), self.parsed.statements());
+
+            let sourced = match sourced {
+                Some(sourced) => sourced,
+                None => continue,
+            };
+            let problem = match std::fs::read_to_string(&sourced) {
+                Ok(_) if sourcing.contains(&sourced.canonicalize().unwrap_or_default()) => {
+                    format!("{} is already being sourced", sourced.display())
+                }
+                Ok(code) => {
+                    debug!("Sourcing {}.", sourced.display());
+                    match self.source_file(&sourced, &code, clean, sourcing) {
+                        Ok(()) => continue,
+                        Err(e) => format!("{} could not be sourced: {}", sourced.display(), e),
+                    }
+                }
+                Err(e) => {
+                    self.files.push(sourced.clone()); // It might be created later.
+                    format!("{} could not be sourced: {}", sourced.display(), e)
+                }
+            };
+            warn!("{}", problem);
+            self.parsed.push_diagnostic(Diagnostic {
+                message: problem,
+                span: location,
+                expected: Vec::new(),
+                file: Some(path.to_path_buf()),
+            });
+        }
+        sourcing.pop();
+        Ok(())
+    }
+
+    /// Returns the files that were parsed, starting with the main script, and the sourced files that could not be read.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Reports dead assignments, shadowed redefinitions and uses of undefined variables, see `lint::lint`,
+    /// together with the lines of the statements they are about.
+    pub fn lint(&self) -> Vec<(LineSpan, lint::Lint)> {
+        let stmts = self.parsed.statements();
+        lint::lint(stmts, &self.dependency_graph)
+            .into_iter()
+            .map(|lint| (stmts[lint.statement].1 .0.clone(), lint))
+            .collect()
+    }
+
+    /// Returns a script that runs the statements of the `target` on their own, see `slice::slice`,
+    /// or `None` if the target does not refer to any statement.
+    ///
+    /// Statements from sourced files are inlined, so the `source()` calls that were followed are left out unless they are targets themselves.
+    pub fn slice(&self, target: &slice::Target) -> Option<String> {
+        let stmts = self.parsed.statements();
+        let targets = slice::find_targets(target, stmts, &self.dependency_graph);
+        if targets.is_empty() {
+            return None;
+        }
+        // A followed `source()` call is directly followed by the first statement of the sourced file.
+        let followed: Vec<StatementId> = stmts
+            .iter()
+            .zip(stmts.iter().skip(1))
+            .filter(|((_, stmt, (span, _)), (_, _, (next, _)))| {
+                sourced_file(stmt).is_some() && next.file() != span.file()
+            })
+            .map(|((id, _, _), _)| id)
+            .collect();
+        let sliced: Vec<StatementId> = slice::slice(&targets, stmts, &self.dependency_graph)
+            .into_iter()
+            .filter(|id| targets.contains(id) || !followed.contains(id))
+            .collect();
+        Some(slice::script(&sliced, stmts))
+    }
+
     pub fn hypotheses_tree(&self) -> HypothesisTree<StatementId> {
-        HypothesisTree::new(self.parsed.statements(), &self.dependency_graph)
+        HypothesisTree::with_sections(
+            self.parsed.statements(),
+            &self.dependency_graph,
+            &self.sections,
+        )
     }
 
     pub fn serialize(&self) -> serde_json::Value {
         json!({
             "statements": self.parsed.statements().as_map(
-                    &mut |id, stmt, (span, meta)| (id, StatementMeta::with(&stmt, span.clone(), meta.clone()))
+                    &mut |id, stmt, (span, meta)| (id, StatementMeta::with(stmt, span.clone(), meta.clone()))
                 ).into_iter().collect::<HashMap<StatementId, StatementMeta>>(),
             "dependencies": self.dependency_graph.as_json(),
+            "setup": slice::setup_statements(self.parsed.statements()),
+            "diagnostics": self.parsed.diagnostics(),
+            "waiting": self.parsed.incomplete().map(|reason| reason.to_string()),
             "hypothesis_tree": self.hypotheses_tree()
         })
     }
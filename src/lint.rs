@@ -0,0 +1,275 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::dependency_graph::DependencyGraph;
+use crate::parser::{
+    pipe_assignment_target, walk_expression, Expression, ExpressionKind, RIdentifier, Statement,
+    StatementId, StatementKind, Statements, Visit,
+};
+
+/// Objects of base R that are available without being defined, which are not reported as undefined.
+///
+/// Functions are not listed, since calls are never reported.
+const BASE_OBJECTS: &[&str] = &[
+    "T",
+    "F",
+    "pi",
+    "LETTERS",
+    "letters",
+    "month.abb",
+    "month.name",
+    ".Machine",
+    ".GlobalEnv",
+    "R.version",
+];
+
+/// A finding about how the code uses its variables.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Lint {
+    #[serde(flatten)]
+    pub kind: LintKind,
+    /// The statement the finding is about, i. e. the assignment or the use.
+    pub statement: StatementId,
+    pub variable: RIdentifier,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LintKind {
+    /// The value assigned to the variable is never used.
+    DeadAssignment,
+    /// The variable is assigned again `by` another statement before its value is used.
+    ShadowedRedefinition { by: StatementId },
+    /// The variable is used, but no statement before defines it.
+    UndefinedVariable,
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            LintKind::DeadAssignment => {
+                write!(f, "dead assignment: `{}` is never used", self.variable)
+            }
+            LintKind::ShadowedRedefinition { .. } => write!(
+                f,
+                "shadowed redefinition: `{}` is assigned again before it is used",
+                self.variable
+            ),
+            LintKind::UndefinedVariable => write!(
+                f,
+                "undefined variable: `{}` is not defined before it is used",
+                self.variable
+            ),
+        }
+    }
+}
+
+/// Reports dead assignments, shadowed redefinitions and uses of undefined variables, ordered by statement.
+///
+/// Only assignments are checked for unused values, since the variables defined in the bodies of control flow
+/// might be used within those bodies, which the `dependency_graph` does not track.
+/// Calls of undefined functions are not reported, because most functions come from packages,
+/// and neither are the variables in formulas, which usually refer to the columns of the data.
+/// For the same reason, variables from packages other than base R are reported as undefined, e. g. datasets loaded with `library()`.
+pub fn lint<M>(stmts: &Statements<M>, dependency_graph: &DependencyGraph) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    let mut used = HashSet::new();
+    for (id, stmt, _) in stmts.iter() {
+        let mut formulas = FormulaVariables::default();
+        stmt.expressions()
+            .into_iter()
+            .for_each(|exp| formulas.visit_expression(exp));
+        for variable in dependency_graph.uses(id) {
+            if variable.definitions.is_empty()
+                && !variable.called
+                && !BASE_OBJECTS.contains(&variable.variable.as_str())
+                && !formulas.variables.contains(&variable.variable)
+            {
+                lints.push(Lint {
+                    kind: LintKind::UndefinedVariable,
+                    statement: id,
+                    variable: variable.variable.clone(),
+                });
+            }
+            for definition in variable.definitions {
+                used.insert((definition, variable.variable.clone()));
+            }
+        }
+    }
+    for (id, stmt, _) in stmts.iter() {
+        if !is_assignment(stmt) {
+            continue;
+        }
+        for variable in dependency_graph.definitions(id) {
+            if used.contains(&(id, variable.clone())) {
+                continue;
+            }
+            // The value is lost if the next statement that always defines the variable does not use it.
+            let shadowing = dependency_graph
+                .defining_statements(variable)
+                .iter()
+                .filter(|other| **other > id)
+                .find(|other| dependency_graph.definitions(**other).contains(&variable));
+            let kind = match shadowing {
+                Some(by) => LintKind::ShadowedRedefinition { by: *by },
+                None => LintKind::DeadAssignment,
+            };
+            lints.push(Lint {
+                kind,
+                statement: id,
+                variable: variable.clone(),
+            });
+        }
+    }
+    lints.sort_by_key(|lint| lint.statement);
+    lints
+}
+
+/// Whether the statement assigns to a variable, including with the assignment pipe `x %<>% f()`.
+fn is_assignment(stmt: &Statement) -> bool {
+    match &stmt.kind {
+        StatementKind::Assignment(_, _, _, _) => true,
+        StatementKind::Expression(expression) => pipe_assignment_target(expression).is_some(),
+        StatementKind::TailComment(stmt, _) => is_assignment(stmt),
+        _ => false,
+    }
+}
+
+/// Collects the variables in the formulas of the visited expressions, e. g. `Speed` and `Layout` in `lm(Speed ~ Layout, kbd)`.
+#[derive(Default)]
+struct FormulaVariables {
+    variables: HashSet<RIdentifier>,
+    in_formula: bool,
+}
+
+impl Visit for FormulaVariables {
+    fn visit_expression(&mut self, exp: &Expression) {
+        match &exp.kind {
+            ExpressionKind::Variable(name) if self.in_formula => {
+                self.variables.insert(name.clone());
+            }
+            ExpressionKind::OneSidedFormula(_) | ExpressionKind::TwoSidedFormula(_, _) => {
+                let outer = std::mem::replace(&mut self.in_formula, true);
+                walk_expression(self, exp);
+                self.in_formula = outer;
+            }
+            _ => walk_expression(self, exp),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::parser::parse_statements;
+
+    /// Lints the `code` and describes each finding by its kind, the index of its statement and its variable.
+    fn lint_code(code: &str) -> Vec<(String, usize, String)> {
+        let stmts = parse_statements(code).unwrap();
+        let ids: Vec<StatementId> = stmts.iter().map(|(id, _, _)| id).collect();
+        let dependency_graph = DependencyGraph::from_input(&stmts);
+        let index = |id: StatementId| ids.iter().position(|other| *other == id).unwrap();
+        lint(&stmts, &dependency_graph)
+            .into_iter()
+            .map(|lint| {
+                let kind = match lint.kind {
+                    LintKind::DeadAssignment => "dead".to_string(),
+                    LintKind::ShadowedRedefinition { by } => format!("shadowed by {}", index(by)),
+                    LintKind::UndefinedVariable => "undefined".to_string(),
+                };
+                (kind, index(lint.statement), lint.variable)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_dead_and_shadowed_assignments() {
+        let lints = lint_code(
+            "\
+kbd <- read.csv(\"kbd.csv\")
+threshold <- 100
+threshold <- 200
+fast <- head(kbd, threshold)
+unused <- nrow(kbd)",
+        );
+        assert_eq!(
+            vec![
+                ("shadowed by 2".to_string(), 1, "threshold".to_string()),
+                ("dead".to_string(), 3, "fast".to_string()),
+                ("dead".to_string(), 4, "unused".to_string()),
+            ],
+            lints
+        );
+    }
+
+    #[test]
+    fn reports_undefined_variables() {
+        let lints = lint_code(
+            "\
+model <- lm(Speed ~ Layout, data = kbd)
+summary(model, digits = precision)
+print(pi)",
+        );
+        assert_eq!(
+            vec![
+                ("undefined".to_string(), 0, "kbd".to_string()),
+                ("undefined".to_string(), 1, "precision".to_string()),
+            ],
+            lints
+        );
+    }
+
+    #[test]
+    fn uses_variables_in_data_masked_arguments() {
+        let lints = lint_code(
+            "\
+kbd <- read.csv(\"kbd.csv\")
+threshold <- 100
+fast <- subset(kbd, Speed > threshold)
+summary(fast)",
+        );
+        assert!(lints.is_empty(), "{:?}", lints);
+    }
+
+    #[test]
+    fn reports_shadowed_variables_in_data_masked_arguments() {
+        let lints = lint_code(
+            "\
+library(dplyr)
+kbd <- read.csv(\"kbd.csv\")
+threshold <- 100
+threshold <- 120
+fast <- subset(kbd, Speed > threshold)
+slow <- filter(kbd, Speed < threshold)
+scaled <- mutate(fast, Speed = Speed / threshold)
+print(scaled)",
+        );
+        assert_eq!(
+            vec![
+                ("shadowed by 3".to_string(), 2, "threshold".to_string()),
+                ("dead".to_string(), 5, "slow".to_string()),
+            ],
+            lints
+        );
+    }
+
+    #[test]
+    fn ignores_variables_used_in_control_flow() {
+        let lints = lint_code(
+            "\
+total <- 0
+for (speed in speeds) {
+  total <- total + speed
+}
+print(total)",
+        );
+        assert_eq!(
+            vec![("undefined".to_string(), 1, "speeds".to_string())],
+            lints
+        );
+    }
+}
@@ -1,15 +1,14 @@
 extern crate tractus;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::io;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::time::Duration;
 
-use env_logger;
 use failure::Error;
 use log::{debug, info, trace, warn};
 use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
@@ -18,6 +17,8 @@ use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 use websocket::{sync::Server, Message, OwnedMessage};
 
+use tractus::formatter::{self, AssignmentOperator, Style};
+use tractus::slice;
 use tractus::Tractus;
 
 #[derive(StructOpt)]
@@ -43,6 +44,24 @@ enum Subcommand {
         #[structopt(flatten)]
         opts: ServeOpts,
     },
+    #[structopt(name = "fmt")]
+    /// Formats R code
+    Fmt {
+        #[structopt(flatten)]
+        opts: FmtOpts,
+    },
+    #[structopt(name = "lint")]
+    /// Reports unused assignments and undefined variables
+    Lint {
+        #[structopt(flatten)]
+        opts: LintOpts,
+    },
+    #[structopt(name = "slice")]
+    /// Writes a minimal script that reproduces a statement
+    Slice {
+        #[structopt(flatten)]
+        opts: SliceOpts,
+    },
 }
 
 #[derive(StructOpt)]
@@ -74,6 +93,92 @@ struct ServeOpts {
     store: Option<PathBuf>,
 }
 
+#[derive(StructOpt)]
+struct FmtOpts {
+    #[structopt(short, long, parse(from_os_str))]
+    /// Input file, stdin if not present
+    input: Option<PathBuf>,
+    #[structopt(short, long, parse(from_os_str))]
+    /// Output file, stdout if not present
+    output: Option<PathBuf>,
+    #[structopt(short, long)]
+    /// Forces overwriting the output without prompting
+    force: bool,
+    #[structopt(long, default_value = "2")]
+    /// Number of spaces per indentation level
+    indent: usize,
+    #[structopt(name = "line-width", long, default_value = "80")]
+    /// Width after which argument lists and operator chains are broken into multiple lines
+    line_width: usize,
+    #[structopt(long, default_value = "arrow", possible_values = &["arrow", "equals"])]
+    /// Operator for assignments, either `<-` (arrow) or `=` (equals)
+    ///
+    /// Superassignments with `<<-` and assignments to the right are kept.
+    assignment: AssignmentOperator,
+    #[structopt(name = "tight-operators", long)]
+    /// Omits the spaces around binary operators
+    tight_operators: bool,
+}
+
+#[derive(StructOpt)]
+struct LintOpts {
+    #[structopt(short, long, parse(from_os_str))]
+    /// Input file, stdin if not present
+    ///
+    /// Files run with `source()` are linted as well.
+    input: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+struct SliceOpts {
+    #[structopt(short, long, parse(from_os_str))]
+    /// Input file
+    ///
+    /// Statements from files run with `source()` are included in place of the call.
+    input: PathBuf,
+    #[structopt(short, long, parse(from_os_str))]
+    /// Output file, stdout if not present
+    output: Option<PathBuf>,
+    #[structopt(short, long)]
+    /// Forces overwriting the output without prompting
+    force: bool,
+    #[structopt(short, long)]
+    /// Slices for the statements on this line of the input file
+    line: Option<usize>,
+    #[structopt(long)]
+    /// Slices for the statement with this id, as in the output of `run`
+    statement: Option<usize>,
+    #[structopt(long)]
+    /// Slices for the last statement that defines this variable
+    variable: Option<String>,
+    #[structopt(long)]
+    /// Slices for the statements that test this hypothesis, e. g. `Speed ~ Layout`
+    hypothesis: Option<String>,
+}
+
+impl TryFrom<&SliceOpts> for slice::Target {
+    type Error = ArgumentError;
+
+    /// Attempt to convert cli options into the target to slice for.
+    fn try_from(other: &SliceOpts) -> Result<Self, Self::Error> {
+        let mut targets = other
+            .line
+            .map(slice::Target::Line)
+            .into_iter()
+            .chain(
+                other
+                    .statement
+                    .map(|id| slice::Target::Statement(id.into())),
+            )
+            .chain(other.variable.clone().map(slice::Target::Variable))
+            .chain(other.hypothesis.clone().map(slice::Target::Hypothesis));
+        match (targets.next(), targets.next()) {
+            (Some(target), None) => Ok(target),
+            _ => Err(ArgumentError::SliceTargetCount),
+        }
+    }
+}
+
 #[derive(StructOpt)]
 struct ProcessingOpts {
     #[structopt(name = "append-only", short, long)]
@@ -157,6 +262,9 @@ fn execute(cmd: Subcommand) -> Res {
             let config = ServeConfig::try_from(opts)?;
             serve(config)?;
         }
+        Fmt { opts } => fmt(opts)?,
+        Lint { opts } => lint(opts)?,
+        Slice { opts } => slice(opts)?,
     }
 
     Ok(())
@@ -170,15 +278,16 @@ fn run(conf: RunConfig) -> Res {
     match input {
         RunInput::SingleRun(input) => {
             let mut process = get_process(input.clone(), clean);
-            let mut run_once = || -> Res {
-                let result = process()?;
-                write_result(&mut output, &result)
+            let mut run_once = || -> Result<Vec<PathBuf>, Error> {
+                let (result, files) = process()?;
+                write_result(&mut output, &result)?;
+                Ok(files)
             };
 
-            run_once()?;
+            let files = run_once()?;
 
-            if let Some(path) = &input {
-                watch(path, run_once)?;
+            if input.is_some() {
+                watch(files, run_once)?;
             }
         }
         RunInput::AppendOnly(path) => {
@@ -189,27 +298,105 @@ fn run(conf: RunConfig) -> Res {
             let mut tractus = Tractus::new();
 
             let mut clean_lines = get_cleaner(clean);
-            let mut run_once = || -> Res {
+            let mut run_once = || -> Result<Vec<PathBuf>, Error> {
                 reader.seek(io::SeekFrom::Start(offset))?;
                 let lines = (&mut reader)
                     .lines()
                     .collect::<Result<Vec<String>, io::Error>>()?;
                 let lines = clean_lines(lines);
-                offset = reader.seek(io::SeekFrom::Current(0))?; // Update offset for next run.
+                offset = reader.stream_position()?; // Update offset for next run.
 
                 tractus.parse_lines(lines)?;
                 let result = serde_json::to_string(&tractus.serialize())?;
-                write_result(&mut output, &result)
+                write_result(&mut output, &result)?;
+                Ok(vec![path.clone()])
             };
 
-            run_once()?;
+            let files = run_once()?;
 
-            watch(&path, run_once)?;
+            watch(files, run_once)?;
         }
     }
     Ok(())
 }
 
+/// Executes the `fmt` subcommand.
+fn fmt(opts: FmtOpts) -> Res {
+    let code = match &opts.input {
+        Some(path) => std::fs::read_to_string(path)?,
+        None => {
+            let mut code = String::new();
+            io::stdin().read_to_string(&mut code)?;
+            code
+        }
+    };
+    let style = Style {
+        indent: opts.indent,
+        line_width: opts.line_width,
+        assignment: opts.assignment,
+        spaces_around_operators: !opts.tight_operators,
+    };
+    let formatted = formatter::format_code(&code, &style);
+    match opts.output {
+        Some(path) => {
+            let mut output = OutputPath {
+                path,
+                force: opts.force,
+            };
+            output.write_confirmed(&formatted)?;
+        }
+        None => io::stdout().lock().write_all(formatted.as_bytes())?,
+    }
+    Ok(())
+}
+
+/// Executes the `lint` subcommand.
+///
+/// Each finding is printed on its own line, prefixed with its file and line, e. g. `analysis.R:3: dead assignment: ...`.
+fn lint(opts: LintOpts) -> Res {
+    let mut tractus = Tractus::new();
+    match &opts.input {
+        Some(path) => tractus.parse_file(path)?,
+        None => {
+            let lines = io::stdin()
+                .lock()
+                .lines()
+                .collect::<Result<Vec<String>, io::Error>>()?;
+            tractus.parse_lines(lines)?;
+        }
+    }
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    for (span, lint) in tractus.lint() {
+        let file = span
+            .file()
+            .map_or_else(|| "<stdin>".to_string(), |file| file.display().to_string());
+        writeln!(handle, "{}:{}: {}", file, span.first_line(), lint)?;
+    }
+    Ok(())
+}
+
+/// Executes the `slice` subcommand.
+fn slice(opts: SliceOpts) -> Res {
+    let target = slice::Target::try_from(&opts)?;
+    let mut tractus = Tractus::new();
+    tractus.parse_file(&opts.input)?;
+    let script = tractus
+        .slice(&target)
+        .ok_or(ArgumentError::SliceTargetNotFound)?;
+    match opts.output {
+        Some(path) => {
+            let mut output = OutputPath {
+                path,
+                force: opts.force,
+            };
+            output.write_confirmed(&script)?;
+        }
+        None => io::stdout().lock().write_all(script.as_bytes())?,
+    }
+    Ok(())
+}
+
 /// Configuration for the `run` subcommand.
 struct RunConfig {
     input: RunInput,
@@ -247,43 +434,86 @@ impl TryFrom<RunOpts> for RunConfig {
     }
 }
 
-/// Watch the file path and execute a closure on changes.
-fn watch<F>(path: &PathBuf, mut execute: F) -> Res
+/// Watch the files and execute a closure on changes to any of them.
+/// The closure returns the files to watch from then on, e. g. a script and the scripts it sources.
+///
+/// The directories of the files are watched, since editors like Jupyter save by replacing the file, which would end a watch on the file itself.
+fn watch<F>(files: Vec<PathBuf>, mut execute: F) -> Res
 where
-    F: FnMut() -> Res,
+    F: FnMut() -> Result<Vec<PathBuf>, Error>,
 {
     let (sender, receiver) = std::sync::mpsc::channel();
     let mut watcher: RecommendedWatcher = Watcher::new(sender, Duration::from_millis(500))?;
-    watcher.watch(&path, RecursiveMode::NonRecursive)?;
+    let mut directories = HashSet::new();
+    let mut watched = watch_directories(&mut watcher, &mut directories, &files)?;
 
-    println!("Watching file {}...", &path.display());
+    if let Some(path) = watched.first() {
+        println!("Watching file {}...", path.display());
+    }
     for event in receiver {
         trace!("Received new file event.");
-        if let DebouncedEvent::Write(_) = event {
-            println!("File {} changed. Updating.", path.display());
-            execute()?;
+        let changed = match &event {
+            DebouncedEvent::Write(changed)
+            | DebouncedEvent::Create(changed)
+            | DebouncedEvent::Rename(_, changed) => changed,
+            _ => continue,
+        };
+        if watched.contains(changed) {
+            println!("File {} changed. Updating.", changed.display());
+            let files = execute()?;
+            watched = watch_directories(&mut watcher, &mut directories, &files)?;
         }
     }
 
     Ok(())
 }
 
+/// Watches the directories of the `files` that are not in `directories` yet, and returns the canonical paths of the files.
+///
+/// Files that do not exist yet are watched through their directory, such that creating them counts as a change.
+fn watch_directories(
+    watcher: &mut RecommendedWatcher,
+    directories: &mut HashSet<PathBuf>,
+    files: &[PathBuf],
+) -> Result<Vec<PathBuf>, Error> {
+    let mut canonical = Vec::new();
+    for file in files {
+        let name = match file.file_name() {
+            Some(name) => name,
+            None => continue,
+        };
+        let directory = match file.parent() {
+            Some(directory) if directory != Path::new("") => directory,
+            _ => Path::new("."),
+        };
+        let directory = match directory.canonicalize() {
+            Ok(directory) => directory,
+            Err(_) => continue, // Without its directory, the file cannot be created either.
+        };
+        if directories.insert(directory.clone()) {
+            watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+        }
+        canonical.push(directory.join(name));
+    }
+    Ok(canonical)
+}
+
 /// Execute the `serve` subcommand.
 fn serve(conf: ServeConfig) -> Res {
     match conf.input {
         ServeInput::File { path, append_only } => {
             debug!("Serving from file.");
-            let mut run_once: Box<dyn FnMut() -> Res> = if !append_only {
+            let mut run_once: Box<dyn FnMut() -> Result<Vec<PathBuf>, Error>> = if !append_only {
                 debug!("Append-only inactive, reparsing whole file on changes.");
 
                 let mut update_and_broadcast = init_server(|_, _| {})?;
                 let mut process = get_process(Some(path.clone()), conf.clean);
 
-                Box::new(move || -> Res {
-                    let result = process()?;
+                Box::new(move || {
+                    let (result, files) = process()?;
                     update_and_broadcast(result);
 
-                    Ok(())
+                    Ok(files)
                 })
             } else {
                 debug!("Only considering appends.");
@@ -307,17 +537,18 @@ fn serve(conf: ServeConfig) -> Res {
                     Ok(result)
                 };
                 let mut update_and_broadcast = init_server(|_, _| {})?;
+                let watched = vec![path.clone()];
                 Box::new(move || {
                     let result = process()?;
                     update_and_broadcast(result);
 
-                    Ok(())
+                    Ok(watched.clone())
                 })
             };
 
-            run_once()?;
+            let files = run_once()?;
 
-            watch(&path, run_once)?;
+            watch(files, run_once)?;
         }
         ServeInput::Websocket { store } => {
             let mut tractus = if let Some(path) = &store {
@@ -533,6 +764,8 @@ enum ArgumentError {
     HistoryConflict,
     AppendWithoutPath,
     StoreWithPath,
+    SliceTargetCount,
+    SliceTargetNotFound,
 }
 
 impl std::fmt::Display for ArgumentError {
@@ -542,40 +775,38 @@ impl std::fmt::Display for ArgumentError {
         match self {
             HistoryConflict => write!(f, "You cannot use --history-desktop along with --append or --clean, since it would overwrite your settings."),
             AppendWithoutPath=> write!(f, "You cannot use --append when reading from stdin. Please specify a file to read from with --input."),
-            StoreWithPath => write!(f, "You cannot use --store with --input. The input file is already persistent.")
+            StoreWithPath => write!(f, "You cannot use --store with --input. The input file is already persistent."),
+            SliceTargetCount => write!(f, "Please specify exactly one of --line, --statement, --variable and --hypothesis to slice for."),
+            SliceTargetNotFound => write!(f, "No statement matches the target to slice for."),
         }
     }
 }
 
 impl std::error::Error for ArgumentError {}
 
-/// Construct a closure that processes the input file with the provided config and returns the serialized result.
+/// Construct a closure that processes the input file with the provided config and returns the serialized result along with the files that were read.
+///
+/// An input file is parsed along with the files it runs with `source()`.
 fn get_process(
     input: Option<PathBuf>,
     clean: Option<Regex>,
-) -> Box<dyn FnMut() -> Result<String, Error>> {
-    let mut get_reader: Box<dyn FnMut() -> Result<Box<dyn BufRead>, Error>> = match input {
-        None => Box::new(|| {
-            let stdin = io::stdin();
-            Ok(Box::new(io::BufReader::new(stdin)))
-        }),
-        Some(path) => Box::new(move || {
-            let file = std::fs::File::open(&path)?;
-            Ok(Box::new(io::BufReader::new(file)))
-        }),
-    };
+) -> Box<dyn FnMut() -> Result<Processed, Error>> {
     let mut clean_lines = get_cleaner(clean);
 
     Box::new(move || {
-        let mut reader = get_reader()?;
-        let lines = (&mut reader)
-            .lines()
-            .collect::<Result<Vec<String>, io::Error>>()?;
-        let lines = clean_lines(lines);
         let mut tractus = Tractus::new();
-        tractus.parse_lines(lines)?;
+        match &input {
+            Some(path) => tractus.parse_file_cleaned(path, &mut clean_lines)?,
+            None => {
+                let lines = io::stdin()
+                    .lock()
+                    .lines()
+                    .collect::<Result<Vec<String>, io::Error>>()?;
+                tractus.parse_lines(clean_lines(lines))?;
+            }
+        }
         let result = serde_json::to_string(&tractus.serialize())?;
-        Ok(result)
+        Ok((result, tractus.files().to_vec()))
     })
 }
 
@@ -586,7 +817,7 @@ fn get_cleaner(clean: Option<Regex>) -> Box<dyn FnMut(Vec<String>) -> Vec<String
         Some(regex) => Box::new(move |lines| {
             lines
                 .iter()
-                .map(|line| regex.replace_all(&line, "").to_string())
+                .map(|line| regex.replace_all(line, "").to_string())
                 .collect()
         }),
     }
@@ -658,3 +889,6 @@ impl OutputPath {
 }
 
 type Res = Result<(), Error>;
+
+/// The serialized result of processing the input along with the files that were read.
+type Processed = (String, Vec<PathBuf>);
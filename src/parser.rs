@@ -1,7 +1,8 @@
-use std::borrow::Borrow;
+use std::borrow::{Borrow, Cow};
 use std::fmt::{Display, Write};
-use std::iter::FromIterator;
+use std::iter::{FromIterator, Peekable};
 use std::ops::Index;
+use std::path::{Path, PathBuf};
 
 use itertools::Itertools;
 use log::{debug, trace};
@@ -12,53 +13,178 @@ use serde::{Deserialize, Serialize};
 #[grammar = "r.pest"]
 struct RParser;
 
-/// An AST statement.
+/// Helper macro for use instead of `unreachable!()` that outputs more information.
+macro_rules! unexpected_rule {
+    ( $rule:ident, $pair:ident) => {
+        panic!(
+            "Encountered unexpected rule {:?} for input {:#?}.",
+            $rule,
+            $pair.as_str()
+        )
+    };
+}
+
+/// An AST statement together with its location in the source code.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Statement {
+    #[serde(flatten)]
+    pub kind: StatementKind,
+    pub span: Span,
+    /// The code exactly as it was parsed, including whitespace, comments and the original operators.
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+/// The different kinds of statements.
 #[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
-pub enum Statement {
+pub enum StatementKind {
     Empty,
     Comment(String),
     TailComment(Box<Statement>, String),
-    Assignment(Expression, Vec<Expression>, Expression),
+    /// The targets, i. e. the first and the additional ones, the assigned value, and the operator of each target.
+    Assignment(
+        Expression,
+        Vec<Expression>,
+        Expression,
+        Vec<AssignmentDirection>,
+    ),
     If(Expression, Vec<Statement>, Option<Vec<Statement>>),
     While(Expression, Vec<Statement>),
     For(Expression, Expression, Vec<Statement>),
+    Repeat(Vec<Statement>),
+    Break,
+    Next,
+    Return(Option<Expression>),
     Library(RIdentifier),
     Expression(Expression),
+    /// Code that could not be parsed, see the accompanying `Diagnostic` for the reason.
+    Unparsed(String),
 }
 
 impl Statement {
+    pub fn new(kind: StatementKind, span: Span) -> Self {
+        Statement {
+            kind,
+            span,
+            source: None,
+        }
+    }
+
+    /// Creates a statement that was parsed from the `pair`.
+    fn parsed(kind: StatementKind, pair: &pest::iterators::Pair<Rule>) -> Self {
+        // The whitespace before a tail comment is not part of the statement.
+        let source = pair.as_str().trim_end_matches([' ', '\t']);
+        let span = pair.as_span().get(..source.len()).unwrap(); // The trimmed code is always a prefix.
+        Statement {
+            kind,
+            span: Span::from(span),
+            source: Some(source.to_string()),
+        }
+    }
+
+    /// Returns the code of this statement as it was written.
+    /// Statements that were not parsed, but constructed in code, are displayed instead.
+    pub fn source(&self) -> Cow<'_, str> {
+        match &self.source {
+            Some(source) => Cow::Borrowed(source),
+            None => Cow::Owned(self.to_string()),
+        }
+    }
+
     /// Returns the expression contained in the statement, if it exists.
     pub fn expression(&self) -> Option<&Expression> {
-        use Statement::*;
-        match self {
-            Assignment(_, _, expression) => Some(expression),
+        use StatementKind::*;
+        match &self.kind {
+            Assignment(_, _, expression, _) => Some(expression),
             Expression(expression) => Some(expression),
             TailComment(statement, _) => statement.expression(),
-            // TODO: Check how to handle if, while, and for.
+            Return(expression) => expression.as_ref(),
+            // Control flow evaluates multiple expressions, see `expressions`.
             If(_, _, _) => None,
             For(_, _, _) => None,
             While(_, _) => None,
+            Repeat(_) => None,
+            Break => None,
+            Next => None,
             Empty => None,
             Comment(_) => None,
             Library(_) => None,
+            Unparsed(_) => None,
         }
     }
+
+    /// Returns the expressions evaluated by this statement,
+    /// including the conditions, ranges and bodies of `if`, `for`, `while` and `repeat`, but not the bodies of function definitions.
+    pub fn expressions(&self) -> Vec<&Expression> {
+        use StatementKind::*;
+        fn body(stmts: &[Statement]) -> impl Iterator<Item = &self::Expression> {
+            stmts.iter().flat_map(Statement::expressions)
+        }
+        match &self.kind {
+            If(condition, then, otherwise) => std::iter::once(condition)
+                .chain(body(then))
+                .chain(body(otherwise.as_deref().unwrap_or_default()))
+                .collect(),
+            While(condition, stmts) => std::iter::once(condition).chain(body(stmts)).collect(),
+            For(_, range, stmts) => std::iter::once(range).chain(body(stmts)).collect(),
+            Repeat(stmts) => body(stmts).collect(),
+            TailComment(statement, _) => statement.expressions(),
+            _ => self.expression().into_iter().collect(),
+        }
+    }
+
+    /// Returns this statement moved to code that starts at `start`, see `shift`.
+    fn shifted(mut self, start: Position) -> Self {
+        self.shift(start);
+        self
+    }
+
+    /// Moves the spans of this statement and all nested nodes to code that starts at `start`.
+    fn shift(&mut self, start: Position) {
+        Shift(start).visit_statement_mut(self)
+    }
+}
+
+/// Statements are compared by their structure only, so the same code at different locations is equal.
+impl PartialEq for Statement {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl Eq for Statement {}
+
+/// Creates a statement without a known location.
+impl From<StatementKind> for Statement {
+    fn from(kind: StatementKind) -> Self {
+        Statement::new(kind, Span::default())
+    }
 }
 
 impl Display for Statement {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        use Statement::*;
-        match self {
+        use StatementKind::*;
+        match &self.kind {
             Empty => writeln!(f),
             Comment(text) => write!(f, "{}", text),
             TailComment(expression, text) => write!(f, "{} {}", expression, text),
-            Assignment(left, additional, right) => {
-                let mut assigned = vec![left];
-                assigned.append(&mut additional.iter().collect());
-                for variable in assigned.iter() {
-                    write!(f, "{} <- ", variable)?
+            Assignment(left, additional, right, directions) => {
+                let assigned: Vec<_> = std::iter::once(left)
+                    .chain(additional)
+                    .zip(directions)
+                    .collect();
+                if directions.iter().any(|direction| direction.is_right()) {
+                    write!(f, "{}", right)?;
+                    for (variable, direction) in assigned.into_iter().rev() {
+                        write!(f, " {} {}", direction.operator(), variable)?
+                    }
+                    Ok(())
+                } else {
+                    for (variable, direction) in assigned {
+                        write!(f, "{} {} ", variable, direction.operator())?
+                    }
+                    write!(f, "{}", right)
                 }
-                write!(f, "{}", right)
             }
             If(condition, body, maybe_else_body) => {
                 write!(f, "if ({}) {{\n{}\n}}", condition, display_lines(body))?;
@@ -77,8 +203,72 @@ impl Display for Statement {
                 range,
                 display_lines(body)
             ),
-            Library(name) => write!(f, "library({})", name),
+            Repeat(body) => write!(f, "repeat {{\n{}\n}}", display_lines(body)),
+            Break => write!(f, "break"),
+            Next => write!(f, "next"),
+            Return(maybe_value) => match maybe_value {
+                Some(value) => write!(f, "return({})", value),
+                None => write!(f, "return()"),
+            },
+            Library(name) => write!(f, "library({})", quote_identifier(name)),
             Expression(exp) => write!(f, "{}", exp),
+            Unparsed(code) => write!(f, "{}", code),
+        }
+    }
+}
+
+/// The direction in which the assignment to a target was written.
+///
+/// `a <- b <- 1` and `1 -> b -> a` both assign to `a` and `b`,
+/// so both are stored with the same targets and differ only in their directions.
+/// Each target keeps its own operator, since `a <- b <<- 1` only assigns `b` in an enclosing environment.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum AssignmentDirection {
+    /// Assignment with `<-` or `=`.
+    Left,
+    /// Assignment with `->`.
+    Right,
+    /// Assignment to an enclosing environment with `<<-`.
+    SuperLeft,
+    /// Assignment to an enclosing environment with `->>`.
+    SuperRight,
+}
+
+impl AssignmentDirection {
+    /// Whether the assigned value is written on the left.
+    pub fn is_right(self) -> bool {
+        match self {
+            AssignmentDirection::Left | AssignmentDirection::SuperLeft => false,
+            AssignmentDirection::Right | AssignmentDirection::SuperRight => true,
+        }
+    }
+
+    /// The canonical operator for this direction.
+    pub fn operator(self) -> &'static str {
+        match self {
+            AssignmentDirection::Left => "<-",
+            AssignmentDirection::Right => "->",
+            AssignmentDirection::SuperLeft => "<<-",
+            AssignmentDirection::SuperRight => "->>",
+        }
+    }
+}
+
+/// The syntax a function definition was written in.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum FunctionSyntax {
+    /// `function(x) x + 1`
+    Keyword,
+    /// The shorthand `\\(x) x + 1`.
+    Lambda,
+}
+
+impl FunctionSyntax {
+    /// The token that starts the definition.
+    pub fn keyword(self) -> &'static str {
+        match self {
+            FunctionSyntax::Keyword => "function",
+            FunctionSyntax::Lambda => "\\",
         }
     }
 }
@@ -88,10 +278,18 @@ fn display_lines(lines: &[Statement]) -> String {
     lines.iter().map(|line| line.to_string()).join("\n")
 }
 
-/// An AST expression.
+/// An AST expression together with its location in the source code.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Expression {
+    #[serde(flatten)]
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+/// The different kinds of expressions.
 #[derive(PartialEq, Debug, Eq, Clone, Serialize, Deserialize)]
-pub enum Expression {
-    Constant(String),
+pub enum ExpressionKind {
+    Constant(Literal),
     Variable(RIdentifier),
     Call(Box<Expression>, Vec<(Option<RIdentifier>, Expression)>),
     Column(Box<Expression>, Box<Expression>),
@@ -99,47 +297,103 @@ pub enum Expression {
     ListIndex(Box<Expression>, Vec<Option<Expression>>),
     OneSidedFormula(Box<Expression>),
     TwoSidedFormula(Box<Expression>, Box<Expression>),
-    Function(Vec<(RIdentifier, Option<Expression>)>, Vec<Statement>),
+    Function(
+        Vec<(RIdentifier, Option<Expression>)>,
+        Vec<Statement>,
+        FunctionSyntax,
+    ),
+    Switch(
+        Box<Expression>,
+        Vec<(Option<RIdentifier>, Option<Expression>)>,
+    ),
     Prefix(String, Box<Expression>),
     Infix(String, Box<Expression>, Box<Expression>),
+    /// An expression in parentheses, which are kept to preserve the grouping when displaying.
+    Paren(Box<Expression>),
+    /// The native pipe `left |> right`. See `desugar_pipe` for the call it stands for.
+    Pipe(Box<Expression>, Box<Expression>),
+    /// An object from a package, e. g. `stats::lm` or the `internal` `stats:::Pillai`.
+    Namespaced {
+        package: RIdentifier,
+        name: RIdentifier,
+        internal: bool,
+    },
+    /// Access to a slot of an S4 object, e. g. `model@coef`.
+    Slot(Box<Expression>, Box<Expression>),
 }
 
 impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Self {
+        Expression { kind, span }
+    }
+
     /// If the expression contains a unique variable, return its name.
     pub fn extract_variable_name(&self) -> Option<RIdentifier> {
-        use Expression::*;
-        match self {
+        use ExpressionKind::*;
+        match &self.kind {
             Variable(name) => Some(name.to_string()),
             Column(left, _) => left.extract_variable_name(),
+            Slot(left, _) => left.extract_variable_name(),
             Index(left, _) => left.extract_variable_name(),
             ListIndex(left, _) => left.extract_variable_name(),
-            Call(_, args) => {
-                // `colnames(variable) <- c("a", "b", "c")` is valid R.
-                if args.len() == 1 {
-                    let (_, exp) = &args[0];
-                    exp.extract_variable_name()
-                } else {
-                    None
-                }
-            }
+            // Replacement functions change their first argument,
+            // e. g. `colnames(variable) <- c("a", "b", "c")` or `attr(variable, "units") <- "cm"`.
+            Call(_, args) => args
+                .first()
+                .and_then(|(_, exp)| exp.extract_variable_name()),
+            _ => None,
+        }
+    }
+
+    /// If the expression is the target of an assignment, return the variable that is assigned.
+    ///
+    /// Like `extract_variable_name`, but a string names the variable as well, e. g. `"a" <- 1`.
+    pub fn assigned_variable_name(&self) -> Option<RIdentifier> {
+        match &self.kind {
+            ExpressionKind::Constant(literal) => literal.string_value(),
+            _ => self.extract_variable_name(),
+        }
+    }
+
+    /// If the expression names a function, return the name without its package, e. g. `lm` for `stats::lm`.
+    pub fn function_name(&self) -> Option<&str> {
+        use ExpressionKind::*;
+        match &self.kind {
+            Variable(name) | Namespaced { name, .. } => Some(name),
             _ => None,
         }
     }
 }
 
+/// Expressions are compared by their structure only, so the same code at different locations is equal.
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl Eq for Expression {}
+
+/// Creates an expression without a known location.
+impl From<ExpressionKind> for Expression {
+    fn from(kind: ExpressionKind) -> Self {
+        Expression::new(kind, Span::default())
+    }
+}
+
 impl Display for Expression {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        use Expression::*;
-        match self {
+        use ExpressionKind::*;
+        match &self.kind {
             Constant(constant) => write!(f, "{}", constant),
-            Variable(name) => write!(f, "{}", name),
+            Variable(name) => write!(f, "{}", quote_identifier(name)),
             Call(name, args) => {
                 let arguments = args
                     .iter()
                     .map(|(maybe_name, expression)| {
                         let mut s = String::new();
                         if let Some(name) = maybe_name {
-                            write!(s, "{} = ", name)?;
+                            write!(s, "{} = ", quote_identifier(name))?;
                         }
                         write!(s, "{}", expression)?;
                         Ok(s)
@@ -149,6 +403,7 @@ impl Display for Expression {
                 write!(f, "{}({})", name, arguments)
             }
             Column(left, right) => write!(f, "{}${}", left, right),
+            Slot(left, right) => write!(f, "{}@{}", left, right),
             Index(left, right) => {
                 let indices = right
                     .iter()
@@ -173,12 +428,12 @@ impl Display for Expression {
             }
             OneSidedFormula(formula) => write!(f, "~ {}", formula),
             TwoSidedFormula(left, right) => write!(f, "{} ~ {}", left, right),
-            Function(params, body) => {
+            Function(params, body, syntax) => {
                 let parameters = params
                     .iter()
                     .map(|(name, maybe_default)| {
                         let mut s = String::new();
-                        write!(s, "{}", name)?;
+                        write!(s, "{}", quote_identifier(name))?;
                         if let Some(default) = maybe_default {
                             write!(s, " = {}", default)?;
                         }
@@ -186,17 +441,756 @@ impl Display for Expression {
                     })
                     .collect::<Result<Vec<String>, std::fmt::Error>>()?
                     .join(", ");
+                let keyword = match syntax {
+                    FunctionSyntax::Keyword => "function ",
+                    FunctionSyntax::Lambda => "\\",
+                };
                 write!(
                     f,
-                    "function ({}) {{\n{}\n}}",
+                    "{}({}) {{\n{}\n}}",
+                    keyword,
                     parameters,
                     display_lines(body)
                 )
             }
+            Switch(selector, arms) => {
+                let arms = arms
+                    .iter()
+                    .map(|(maybe_name, maybe_value)| {
+                        let mut s = String::new();
+                        if let Some(name) = maybe_name {
+                            write!(s, "{} =", quote_identifier(name))?;
+                            if maybe_value.is_some() {
+                                write!(s, " ")?;
+                            }
+                        }
+                        if let Some(value) = maybe_value {
+                            write!(s, "{}", value)?;
+                        }
+                        Ok(s)
+                    })
+                    .collect::<Result<Vec<String>, std::fmt::Error>>()?;
+                write!(f, "switch({}", selector)?;
+                for arm in arms {
+                    write!(f, ", {}", arm)?;
+                }
+                write!(f, ")")
+            }
             Prefix(op, exp) => write!(f, "{}{}", op, exp),
             Infix(op, left, right) => write!(f, "{} {} {}", left, op, right),
+            Paren(exp) => write!(f, "({})", exp),
+            Pipe(left, right) => write!(f, "{} |> {}", left, right),
+            Namespaced {
+                package,
+                name,
+                internal,
+            } => {
+                let operator = if *internal { ":::" } else { "::" };
+                write!(
+                    f,
+                    "{}{}{}",
+                    quote_identifier(package),
+                    operator,
+                    quote_identifier(name)
+                )
+            }
+        }
+    }
+}
+
+/// Returns the identifier as it has to be written in code, which requires backticks around non-syntactic names.
+pub fn quote_identifier(name: &str) -> Cow<'_, str> {
+    const RESERVED: [&str; 18] = [
+        "if",
+        "else",
+        "repeat",
+        "while",
+        "function",
+        "for",
+        "in",
+        "next",
+        "break",
+        "TRUE",
+        "FALSE",
+        "NULL",
+        "Inf",
+        "NaN",
+        "NA",
+        "NA_integer_",
+        "NA_real_",
+        "NA_character_",
+    ];
+    let mut chars = name.chars();
+    let syntactic = match (chars.next(), chars.next()) {
+        (Some(first), second) => {
+            (first.is_ascii_alphabetic()
+                || first == '.' && !second.is_some_and(|c| c.is_ascii_digit()))
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_')
+        }
+        (None, _) => false,
+    };
+    // Names in double quotes and the pipe placeholder are written as they are.
+    if syntactic && !RESERVED.contains(&name) || name == "_" || name.starts_with('"') {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("`{}`", name.replace('`', "\\`")))
+    }
+}
+
+/// A literal constant.
+#[derive(PartialEq, Debug, Eq, Clone, Serialize, Deserialize)]
+pub enum Literal {
+    /// A number as written in the source, e. g. `1`, `0xFF`, `1L` or `2i`.
+    Number(String, NumberKind),
+    /// A string as written in the source including its delimiters, e. g. `"a"` or `r"(C:\path)"`.
+    String(String, StringKind),
+    Logical(bool),
+    Na(NaKind),
+    Null,
+    Inf,
+    NaN,
+}
+
+/// The R type of a number literal.
+#[derive(PartialEq, Debug, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum NumberKind {
+    /// Decimal or hexadecimal numbers without suffix, e. g. `1.5` or `0xFF`.
+    Double,
+    /// Numbers with an `L` suffix, e. g. `1L`.
+    Integer,
+    /// Numbers with an `i` suffix, e. g. `2i`.
+    Complex,
+}
+
+/// The delimiters of a string literal.
+#[derive(PartialEq, Debug, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum StringKind {
+    DoubleQuoted,
+    SingleQuoted,
+    /// Raw strings like `r"(...)"` that do not process escapes.
+    Raw,
+}
+
+/// The type of a missing value.
+#[derive(PartialEq, Debug, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum NaKind {
+    /// `NA`
+    Logical,
+    /// `NA_integer_`
+    Integer,
+    /// `NA_real_`
+    Real,
+    /// `NA_character_`
+    Character,
+}
+
+impl Literal {
+    /// Classifies the text of a `constant` token.
+    fn from_pair(constant: pest::iterators::Pair<Rule>) -> Self {
+        let text = constant.as_str();
+        match constant.as_rule() {
+            Rule::number => {
+                let kind = if text.ends_with('L') {
+                    NumberKind::Integer
+                } else if text.ends_with('i') {
+                    NumberKind::Complex
+                } else {
+                    NumberKind::Double
+                };
+                Literal::Number(text.to_string(), kind)
+            }
+            Rule::raw_string => Literal::String(text.to_string(), StringKind::Raw),
+            Rule::string => {
+                let kind = if text.starts_with('"') {
+                    StringKind::DoubleQuoted
+                } else {
+                    StringKind::SingleQuoted
+                };
+                Literal::String(text.to_string(), kind)
+            }
+            Rule::reserved_constant => match text {
+                "TRUE" => Literal::Logical(true),
+                "FALSE" => Literal::Logical(false),
+                "NULL" => Literal::Null,
+                "NA" => Literal::Na(NaKind::Logical),
+                "NA_integer_" => Literal::Na(NaKind::Integer),
+                "NA_real_" => Literal::Na(NaKind::Real),
+                "NA_character_" => Literal::Na(NaKind::Character),
+                "Inf" => Literal::Inf,
+                "NaN" => Literal::NaN,
+                _ => unreachable!("Unknown reserved constant {}.", text),
+            },
+            r => unexpected_rule!(r, constant),
+        }
+    }
+
+    /// If the literal is a real number, returns its value, e. g. `255` for `0xFF` and `0.75` for `0x1.8p-1`.
+    /// Complex numbers have no real value and are left out.
+    pub fn number_value(&self) -> Option<f64> {
+        let text = match self {
+            Literal::Number(_, NumberKind::Complex) => return None,
+            Literal::Number(text, _) => text.trim_end_matches('L'),
+            _ => return None,
+        };
+        let hexadecimal = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            Some(hexadecimal) => hexadecimal,
+            None => return text.parse().ok(),
+        };
+        let (digits, exponent) = match hexadecimal.find(['p', 'P']) {
+            Some(i) => (&hexadecimal[..i], hexadecimal[i + 1..].parse::<i32>().ok()?),
+            None => (hexadecimal, 0),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let mut value = 0.0;
+        for digit in whole.chars().chain(fraction.chars()) {
+            value = value * 16.0 + f64::from(digit.to_digit(16)?);
+        }
+        let fraction_length = fraction.len() as i32;
+        Some(value * 2f64.powi(exponent - 4 * fraction_length))
+    }
+
+    /// If the literal is a string, returns its content without delimiters and with escape sequences resolved,
+    /// e. g. `C:\data` for `"C:\\data"` and `r"(C:\data)"`.
+    pub fn string_value(&self) -> Option<String> {
+        let (text, kind) = match self {
+            Literal::String(text, kind) => (text, kind),
+            _ => return None,
+        };
+        if *kind == StringKind::Raw {
+            // Strips `r"--(` and `)--"`.
+            let body = text[1..].trim_matches(|c| c == '"' || c == '\'' || c == '-');
+            return Some(body[1..body.len() - 1].to_string());
+        }
+        let mut value = String::new();
+        let mut chars = text[1..text.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                value.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('r') => value.push('\r'),
+                Some('0') => value.push('\0'),
+                Some(other) => value.push(other),
+                None => {}
+            }
+        }
+        Some(value)
+    }
+}
+
+impl std::str::FromStr for Literal {
+    type Err = Error;
+
+    /// Parses a single literal, e. g. `"1L".parse::<Literal>()`.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut pairs = RParser::parse(Rule::constant, text)?;
+        let constant = pairs.next().unwrap(); // A successful parse always contains the constant.
+        if constant.as_str().len() != text.len() {
+            let position = pest::Position::new(text, constant.as_str().len()).unwrap();
+            return Err(pest::error::Error::new_from_pos(
+                pest::error::ErrorVariant::CustomError {
+                    message: "Unexpected input after literal.".to_string(),
+                },
+                position,
+            ));
+        }
+        Ok(Literal::from_pair(constant.into_inner().next().unwrap())) // A constant always contains its kind.
+    }
+}
+
+impl Display for Literal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Literal::*;
+        match self {
+            Number(text, _) => write!(f, "{}", text),
+            String(text, _) => write!(f, "{}", text),
+            Logical(true) => write!(f, "TRUE"),
+            Logical(false) => write!(f, "FALSE"),
+            Na(NaKind::Logical) => write!(f, "NA"),
+            Na(NaKind::Integer) => write!(f, "NA_integer_"),
+            Na(NaKind::Real) => write!(f, "NA_real_"),
+            Na(NaKind::Character) => write!(f, "NA_character_"),
+            Null => write!(f, "NULL"),
+            Inf => write!(f, "Inf"),
+            NaN => write!(f, "NaN"),
+        }
+    }
+}
+
+/// Rewrites the native pipe `left |> right` into the call it stands for.
+///
+/// `x |> f(y)` becomes `f(x, y)`, and `x |> f(y, z = _)` becomes `f(y, z = x)`.
+/// R rejects anything but a call on the right-hand side, but we are lenient and treat `x |> f` like `x |> f()`.
+pub fn desugar_pipe(left: &Expression, right: &Expression) -> Expression {
+    use ExpressionKind::*;
+    let span = left.span.to(right.span);
+    match &right.kind {
+        Call(function, args) => {
+            let is_placeholder = |exp: &Expression| exp.kind == Variable("_".to_string());
+            let mut args = args.clone();
+            match args
+                .iter_mut()
+                .find(|(name, exp)| name.is_some() && is_placeholder(exp))
+            {
+                Some((_, placeholder)) => *placeholder = left.clone(),
+                None => args.insert(0, (None, left.clone())),
+            }
+            Expression::new(Call(function.clone(), args), span)
+        }
+        _ => Expression::new(
+            Call(Box::new(right.clone()), vec![(None, left.clone())]),
+            span,
+        ),
+    }
+}
+
+/// The pipes of the magrittr package, which are parsed as custom infix operators.
+pub const MAGRITTR_PIPES: [&str; 4] = ["%>%", "%<>%", "%T>%", "%$%"];
+
+/// Rewrites the magrittr pipe `left operator right` into the call it stands for,
+/// or returns `None` if the `operator` is not one of the `MAGRITTR_PIPES`.
+///
+/// `x %>% f(y)` becomes `f(x, y)`, and the placeholder `.` stands for the left side, so `x %>% f(y, data = .)` becomes `f(y, data = x)`.
+/// Like in magrittr, only a `.` that is an argument of the call itself keeps `x` from becoming the first argument,
+/// so `x %>% f(nrow(.))` becomes `f(x, nrow(x))`. A bare function `x %>% f` becomes `f(x)`.
+///
+/// `x %$% f(y)` exposes the columns of `x` to the call, which becomes `with(x, f(y))`.
+/// The assignment pipe `x %<>% f(y)` becomes `f(x, y)`, whose value is assigned to `x`, see `pipe_assignment_target`.
+/// The tee pipe `x %T>% f(y)` calls `f(x, y)` for its side effects, like a plot, and continues with `x`.
+/// On its own, it becomes `f(x, y)`, which uses the same variables,
+/// but the pipe that follows it receives `x`, so `x %T>% plot() %>% summary()` becomes `summary(x)`.
+pub fn desugar_magrittr(
+    operator: &str,
+    left: &Expression,
+    right: &Expression,
+) -> Option<Expression> {
+    use ExpressionKind::*;
+    let span = left.span.to(right.span);
+    match operator {
+        "%>%" | "%<>%" | "%T>%" => {}
+        "%$%" => {
+            let with = Expression::new(Variable("with".to_string()), span);
+            let args = vec![(None, left.clone()), (None, right.clone())];
+            return Some(Expression::new(Call(Box::new(with), args), span));
+        }
+        _ => return None,
+    }
+    let mut left = left;
+    while let Infix(op, tee_left, _) = &left.kind {
+        if op != "%T>%" {
+            break;
+        }
+        left = tee_left;
+    }
+    let call = match &right.kind {
+        Call(function, args) => {
+            let is_placeholder = |exp: &Expression| exp.kind == Variable(".".to_string());
+            let explicit = args.iter().any(|(_, arg)| is_placeholder(arg));
+            let mut args: Vec<_> = args
+                .iter()
+                .map(|(name, arg)| (name.clone(), Placeholder(left).fold_expression(arg.clone())))
+                .collect();
+            if !explicit {
+                args.insert(0, (None, left.clone()));
+            }
+            Expression::new(Call(function.clone(), args), span)
+        }
+        _ => Expression::new(
+            Call(Box::new(right.clone()), vec![(None, left.clone())]),
+            span,
+        ),
+    };
+    Some(call)
+}
+
+/// If the `expression` is a chain of magrittr pipes that starts with the assignment pipe, e. g. `x %<>% f() %>% g()`,
+/// returns the target `x`, which is assigned the value of the whole chain.
+pub fn pipe_assignment_target(expression: &Expression) -> Option<&Expression> {
+    match &expression.kind {
+        ExpressionKind::Infix(op, left, _) if op == "%<>%" => Some(left),
+        ExpressionKind::Infix(op, left, _) if MAGRITTR_PIPES.contains(&op.as_str()) => {
+            pipe_assignment_target(left)
+        }
+        _ => None,
+    }
+}
+
+/// Replaces the magrittr placeholder `.` with the left side of the pipe.
+///
+/// Functions and the right sides of nested pipes are skipped, since their `.` means something else.
+struct Placeholder<'a>(&'a Expression);
+
+impl Fold for Placeholder<'_> {
+    fn fold_expression(&mut self, exp: Expression) -> Expression {
+        use ExpressionKind::*;
+        match &exp.kind {
+            Variable(name) if name == "." => self.0.clone(),
+            Function(_, _, _) => exp,
+            Infix(op, left, right) if MAGRITTR_PIPES.contains(&op.as_str()) => {
+                let left = self.fold_expression((**left).clone());
+                Expression::new(Infix(op.clone(), Box::new(left), right.clone()), exp.span)
+            }
+            _ => fold_expression(self, exp),
+        }
+    }
+}
+
+/// Traverses the AST by reference.
+///
+/// The default methods visit every nested statement and expression, including the bodies of functions,
+/// so an implementation only needs to override the nodes it is interested in.
+/// An overriding method can call `walk_statement` or `walk_expression` to continue into the children.
+///
+/// Note that names are expressions too, e. g. the `b` in `a$b` is visited as a `Variable`.
+///
+/// ```
+/// use tractus::parser::{parse_statements, walk_expression, Expression, ExpressionKind, Visit};
+///
+/// struct Calls(Vec<String>);
+///
+/// impl Visit for Calls {
+///     fn visit_expression(&mut self, exp: &Expression) {
+///         if let ExpressionKind::Call(function, _) = &exp.kind {
+///             self.0.push(function.to_string());
+///         }
+///         walk_expression(self, exp);
+///     }
+/// }
+///
+/// let mut calls = Calls(Vec::new());
+/// for (stmt, _) in parse_statements("f <- function(x) log(x)\nf(sqrt(2))").unwrap() {
+///     calls.visit_statement(&stmt);
+/// }
+/// assert_eq!(vec!["log", "f", "sqrt"], calls.0);
+/// ```
+pub trait Visit {
+    fn visit_statement(&mut self, stmt: &Statement) {
+        walk_statement(self, stmt)
+    }
+
+    fn visit_expression(&mut self, exp: &Expression) {
+        walk_expression(self, exp)
+    }
+}
+
+/// Visits the children of `stmt` in source code order.
+pub fn walk_statement<V: Visit + ?Sized>(visitor: &mut V, stmt: &Statement) {
+    use StatementKind::*;
+    match &stmt.kind {
+        Empty | Comment(_) | Break | Next | Library(_) | Unparsed(_) => {}
+        TailComment(statement, _) => visitor.visit_statement(statement),
+        Assignment(left, additional, right, _) => {
+            visitor.visit_expression(left);
+            additional
+                .iter()
+                .for_each(|exp| visitor.visit_expression(exp));
+            visitor.visit_expression(right);
+        }
+        If(condition, body, maybe_else_body) => {
+            visitor.visit_expression(condition);
+            body.iter().for_each(|stmt| visitor.visit_statement(stmt));
+            maybe_else_body
+                .iter()
+                .flatten()
+                .for_each(|stmt| visitor.visit_statement(stmt));
+        }
+        While(condition, body) => {
+            visitor.visit_expression(condition);
+            body.iter().for_each(|stmt| visitor.visit_statement(stmt));
+        }
+        For(variable, range, body) => {
+            visitor.visit_expression(variable);
+            visitor.visit_expression(range);
+            body.iter().for_each(|stmt| visitor.visit_statement(stmt));
+        }
+        Repeat(body) => body.iter().for_each(|stmt| visitor.visit_statement(stmt)),
+        Return(maybe_value) => maybe_value
+            .iter()
+            .for_each(|value| visitor.visit_expression(value)),
+        Expression(exp) => visitor.visit_expression(exp),
+    }
+}
+
+/// Visits the children of `exp` in source code order.
+pub fn walk_expression<V: Visit + ?Sized>(visitor: &mut V, exp: &Expression) {
+    use ExpressionKind::*;
+    match &exp.kind {
+        Constant(_) | Variable(_) | Namespaced { .. } => {}
+        Call(function, args) => {
+            visitor.visit_expression(function);
+            args.iter()
+                .for_each(|(_, exp)| visitor.visit_expression(exp));
+        }
+        Index(left, right) | ListIndex(left, right) => {
+            visitor.visit_expression(left);
+            right
+                .iter()
+                .flatten()
+                .for_each(|exp| visitor.visit_expression(exp));
+        }
+        OneSidedFormula(exp) | Prefix(_, exp) | Paren(exp) => visitor.visit_expression(exp),
+        Column(left, right)
+        | Slot(left, right)
+        | TwoSidedFormula(left, right)
+        | Infix(_, left, right)
+        | Pipe(left, right) => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        }
+        Function(params, body, _) => {
+            params
+                .iter()
+                .filter_map(|(_, maybe_default)| maybe_default.as_ref())
+                .for_each(|default| visitor.visit_expression(default));
+            body.iter().for_each(|stmt| visitor.visit_statement(stmt));
+        }
+        Switch(selector, arms) => {
+            visitor.visit_expression(selector);
+            arms.iter()
+                .filter_map(|(_, maybe_value)| maybe_value.as_ref())
+                .for_each(|value| visitor.visit_expression(value));
+        }
+    }
+}
+
+/// Traverses the AST by mutable reference to change it in place.
+///
+/// Like `Visit`, the default methods visit every nested node, and `walk_statement_mut` and `walk_expression_mut`
+/// continue into the children from an overriding method.
+pub trait VisitMut {
+    fn visit_statement_mut(&mut self, stmt: &mut Statement) {
+        walk_statement_mut(self, stmt)
+    }
+
+    fn visit_expression_mut(&mut self, exp: &mut Expression) {
+        walk_expression_mut(self, exp)
+    }
+}
+
+/// Visits the children of `stmt` mutably in source code order.
+pub fn walk_statement_mut<V: VisitMut + ?Sized>(visitor: &mut V, stmt: &mut Statement) {
+    use StatementKind::*;
+    match &mut stmt.kind {
+        Empty | Comment(_) | Break | Next | Library(_) | Unparsed(_) => {}
+        TailComment(statement, _) => visitor.visit_statement_mut(statement),
+        Assignment(left, additional, right, _) => {
+            visitor.visit_expression_mut(left);
+            additional
+                .iter_mut()
+                .for_each(|exp| visitor.visit_expression_mut(exp));
+            visitor.visit_expression_mut(right);
+        }
+        If(condition, body, maybe_else_body) => {
+            visitor.visit_expression_mut(condition);
+            body.iter_mut()
+                .for_each(|stmt| visitor.visit_statement_mut(stmt));
+            maybe_else_body
+                .iter_mut()
+                .flatten()
+                .for_each(|stmt| visitor.visit_statement_mut(stmt));
+        }
+        While(condition, body) => {
+            visitor.visit_expression_mut(condition);
+            body.iter_mut()
+                .for_each(|stmt| visitor.visit_statement_mut(stmt));
+        }
+        For(variable, range, body) => {
+            visitor.visit_expression_mut(variable);
+            visitor.visit_expression_mut(range);
+            body.iter_mut()
+                .for_each(|stmt| visitor.visit_statement_mut(stmt));
+        }
+        Repeat(body) => body
+            .iter_mut()
+            .for_each(|stmt| visitor.visit_statement_mut(stmt)),
+        Return(maybe_value) => maybe_value
+            .iter_mut()
+            .for_each(|value| visitor.visit_expression_mut(value)),
+        Expression(exp) => visitor.visit_expression_mut(exp),
+    }
+}
+
+/// Visits the children of `exp` mutably in source code order.
+pub fn walk_expression_mut<V: VisitMut + ?Sized>(visitor: &mut V, exp: &mut Expression) {
+    use ExpressionKind::*;
+    match &mut exp.kind {
+        Constant(_) | Variable(_) | Namespaced { .. } => {}
+        Call(function, args) => {
+            visitor.visit_expression_mut(function);
+            args.iter_mut()
+                .for_each(|(_, exp)| visitor.visit_expression_mut(exp));
+        }
+        Index(left, right) | ListIndex(left, right) => {
+            visitor.visit_expression_mut(left);
+            right
+                .iter_mut()
+                .flatten()
+                .for_each(|exp| visitor.visit_expression_mut(exp));
+        }
+        OneSidedFormula(exp) | Prefix(_, exp) | Paren(exp) => visitor.visit_expression_mut(exp),
+        Column(left, right)
+        | Slot(left, right)
+        | TwoSidedFormula(left, right)
+        | Infix(_, left, right)
+        | Pipe(left, right) => {
+            visitor.visit_expression_mut(left);
+            visitor.visit_expression_mut(right);
+        }
+        Function(params, body, _) => {
+            params
+                .iter_mut()
+                .filter_map(|(_, maybe_default)| maybe_default.as_mut())
+                .for_each(|default| visitor.visit_expression_mut(default));
+            body.iter_mut()
+                .for_each(|stmt| visitor.visit_statement_mut(stmt));
+        }
+        Switch(selector, arms) => {
+            visitor.visit_expression_mut(selector);
+            arms.iter_mut()
+                .filter_map(|(_, maybe_value)| maybe_value.as_mut())
+                .for_each(|value| visitor.visit_expression_mut(value));
+        }
+    }
+}
+
+/// Transforms the AST by consuming it and building a new one.
+///
+/// The default methods rebuild every node from its folded children and keep its span.
+/// A statement also keeps its `source`, so set it to `None` when a folded statement should be displayed from its AST.
+/// `fold_statement` and `fold_expression` continue into the children from an overriding method.
+pub trait Fold {
+    fn fold_statement(&mut self, stmt: Statement) -> Statement {
+        fold_statement(self, stmt)
+    }
+
+    fn fold_expression(&mut self, exp: Expression) -> Expression {
+        fold_expression(self, exp)
+    }
+}
+
+/// Rebuilds `stmt` from its folded children.
+pub fn fold_statement<F: Fold + ?Sized>(folder: &mut F, stmt: Statement) -> Statement {
+    use StatementKind::*;
+    let Statement { kind, span, source } = stmt;
+    let kind = match kind {
+        kind @ (Empty | Comment(_) | Break | Next | Library(_) | Unparsed(_)) => kind,
+        TailComment(statement, comment) => {
+            TailComment(Box::new(folder.fold_statement(*statement)), comment)
+        }
+        Assignment(left, additional, right, directions) => Assignment(
+            folder.fold_expression(left),
+            additional
+                .into_iter()
+                .map(|exp| folder.fold_expression(exp))
+                .collect(),
+            folder.fold_expression(right),
+            directions,
+        ),
+        If(condition, body, maybe_else_body) => If(
+            folder.fold_expression(condition),
+            fold_body(folder, body),
+            maybe_else_body.map(|else_body| fold_body(folder, else_body)),
+        ),
+        While(condition, body) => While(folder.fold_expression(condition), fold_body(folder, body)),
+        For(variable, range, body) => For(
+            folder.fold_expression(variable),
+            folder.fold_expression(range),
+            fold_body(folder, body),
+        ),
+        Repeat(body) => Repeat(fold_body(folder, body)),
+        Return(maybe_value) => Return(maybe_value.map(|value| folder.fold_expression(value))),
+        Expression(exp) => Expression(folder.fold_expression(exp)),
+    };
+    Statement { kind, span, source }
+}
+
+/// Rebuilds `exp` from its folded children.
+pub fn fold_expression<F: Fold + ?Sized>(folder: &mut F, exp: Expression) -> Expression {
+    use ExpressionKind::*;
+    let mut fold_box = |exp: Box<Expression>| Box::new(folder.fold_expression(*exp));
+    let kind = match exp.kind {
+        kind @ (Constant(_) | Variable(_) | Namespaced { .. }) => kind,
+        Call(function, args) => {
+            let function = fold_box(function);
+            Call(
+                function,
+                args.into_iter()
+                    .map(|(name, exp)| (name, folder.fold_expression(exp)))
+                    .collect(),
+            )
+        }
+        Column(left, right) => Column(fold_box(left), fold_box(right)),
+        Slot(left, right) => Slot(fold_box(left), fold_box(right)),
+        Index(left, right) => Index(fold_box(left), fold_indices(folder, right)),
+        ListIndex(left, right) => ListIndex(fold_box(left), fold_indices(folder, right)),
+        OneSidedFormula(exp) => OneSidedFormula(fold_box(exp)),
+        TwoSidedFormula(left, right) => TwoSidedFormula(fold_box(left), fold_box(right)),
+        Function(params, body, syntax) => Function(
+            params
+                .into_iter()
+                .map(|(name, maybe_default)| {
+                    (
+                        name,
+                        maybe_default.map(|default| folder.fold_expression(default)),
+                    )
+                })
+                .collect(),
+            fold_body(folder, body),
+            syntax,
+        ),
+        Switch(selector, arms) => {
+            let selector = fold_box(selector);
+            Switch(
+                selector,
+                arms.into_iter()
+                    .map(|(name, maybe_value)| {
+                        (name, maybe_value.map(|value| folder.fold_expression(value)))
+                    })
+                    .collect(),
+            )
         }
+        Prefix(operator, exp) => Prefix(operator, fold_box(exp)),
+        Infix(operator, left, right) => Infix(operator, fold_box(left), fold_box(right)),
+        Paren(exp) => Paren(fold_box(exp)),
+        Pipe(left, right) => Pipe(fold_box(left), fold_box(right)),
+    };
+    Expression::new(kind, exp.span)
+}
+
+/// Moves the spans of all visited nodes to code that starts at the position.
+struct Shift(Position);
+
+impl VisitMut for Shift {
+    fn visit_statement_mut(&mut self, stmt: &mut Statement) {
+        stmt.span = stmt.span.shifted(self.0);
+        walk_statement_mut(self, stmt)
     }
+
+    fn visit_expression_mut(&mut self, exp: &mut Expression) {
+        exp.span = exp.span.shifted(self.0);
+        walk_expression_mut(self, exp)
+    }
+}
+
+fn fold_body<F: Fold + ?Sized>(folder: &mut F, body: Vec<Statement>) -> Vec<Statement> {
+    body.into_iter()
+        .map(|stmt| folder.fold_statement(stmt))
+        .collect()
+}
+
+fn fold_indices<F: Fold + ?Sized>(
+    folder: &mut F,
+    indices: Vec<Option<Expression>>,
+) -> Vec<Option<Expression>> {
+    indices
+        .into_iter()
+        .map(|maybe_exp| maybe_exp.map(|exp| folder.fold_expression(exp)))
+        .collect()
 }
 
 pub type RIdentifier = String;
@@ -238,6 +1232,13 @@ impl<M> IntoIterator for Statements<M> {
 )]
 pub struct StatementId(usize);
 
+/// Refers to the statement with the number, as in the serialized dependencies.
+impl From<usize> for StatementId {
+    fn from(id: usize) -> Self {
+        StatementId(id)
+    }
+}
+
 impl<S, M> Index<S> for Statements<M>
 where
     S: Borrow<StatementId>,
@@ -255,6 +1256,16 @@ impl<M> Statements<M> {
         Self { stmts: Vec::new() }
     }
 
+    /// Returns the statement that was appended last.
+    fn last(&self) -> Option<&(Statement, M)> {
+        self.stmts.last()
+    }
+
+    /// Removes the statement that was appended last, such that its id is used again by the next statement.
+    fn pop(&mut self) -> Option<(Statement, M)> {
+        self.stmts.pop()
+    }
+
     /// Append a statement with its meta-data to the collection.
     pub fn append(&mut self, stmt: Statement, meta: M) -> StatementId {
         self.concat(Statements::from_iter(vec![(stmt, meta)]))[0]
@@ -312,7 +1323,10 @@ impl<M> Statements<M> {
 pub struct Parsed<M = LineSpan> {
     statements: Statements<M>,
     unparsed: Vec<String>,
+    scanner: Scanner,
     line_count: usize,
+    byte_count: usize,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl Parsed<LineSpan> {
@@ -323,6 +1337,11 @@ impl Parsed<LineSpan> {
     {
         self.append_with_meta(lines, &mut |_, m| m)
     }
+
+    /// Convenience function, see `finish_with_meta`.
+    pub fn finish(&mut self) -> Vec<StatementId> {
+        self.finish_with_meta(&mut |_, m| m)
+    }
 }
 
 impl<M: std::fmt::Debug> Parsed<M> {
@@ -330,7 +1349,10 @@ impl<M: std::fmt::Debug> Parsed<M> {
         Parsed {
             statements: Statements::new(),
             unparsed: Vec::new(),
+            scanner: Scanner::default(),
             line_count: 0,
+            byte_count: 0,
+            diagnostics: Vec::new(),
         }
     }
 
@@ -343,45 +1365,155 @@ impl<M: std::fmt::Debug> Parsed<M> {
     {
         let mut added_ids = Vec::new();
         for line in lines.iter() {
+            let line = line.as_ref();
             self.line_count += 1;
-            self.unparsed.push(line.as_ref().to_string()); // Push to unparsed, such that all currently unparsed lines are treated together.
+            let line_start = Position {
+                offset: self.byte_count,
+                line: self.line_count,
+                column: 1,
+            };
+            self.byte_count += line.len() + 1; // Lines are separated by a newline.
+            self.unparsed.push(line.to_string()); // Push to unparsed, such that all currently unparsed lines are treated together.
 
-            let to_parse = &self.unparsed.join("\n");
-            let parse_result = parse_statements(to_parse);
+            // Only code that can be complete is parsed, which keeps the total parsing time linear.
+            self.scanner.scan(line, line_start);
+            if let Some(incomplete) = self.scanner.incomplete() {
+                debug!("Waiting for more input: {}.", incomplete);
+                continue;
+            }
+
+            let mut new_ids = self.parse_unparsed(mapping);
+            new_ids.retain(|id| !added_ids.contains(id)); // The id of a merged if statement might have been added before.
+            added_ids.append(&mut new_ids);
+        }
 
-            match parse_result {
+        added_ids
+    }
+
+    /// Parses the lines waiting for more input as they are, e. g. at the end of a file or a chunk of a document,
+    /// and returns the ids of the statements added.
+    /// Code that is not complete is added as unparsed statements.
+    pub fn finish_with_meta<F>(&mut self, mapping: &mut F) -> Vec<StatementId>
+    where
+        F: FnMut(&Statement, LineSpan) -> M,
+    {
+        if self.unparsed.is_empty() {
+            return Vec::new();
+        }
+        self.scanner = Scanner::default();
+        self.parse_unparsed(mapping)
+    }
+
+    /// Skips `lines` that are not code, e. g. the text between the chunks of a document,
+    /// such that the positions of the lines appended afterwards are counted from the start of the document.
+    ///
+    /// The lines waiting for more input need to be parsed with `finish_with_meta` first.
+    pub fn skip<S: AsRef<str>>(&mut self, lines: &[S]) {
+        debug_assert!(
+            self.unparsed.is_empty(),
+            "Skipped lines would end up in the middle of code."
+        );
+        for line in lines {
+            self.line_count += 1;
+            self.byte_count += line.as_ref().len() + 1;
+        }
+    }
+
+    /// Counts the positions of the lines appended afterwards from the start again, e. g. for each cell of a notebook.
+    ///
+    /// The lines waiting for more input need to be parsed with `finish_with_meta` first.
+    pub fn restart(&mut self) {
+        debug_assert!(
+            self.unparsed.is_empty(),
+            "Restarted lines would end up in the middle of code."
+        );
+        self.line_count = 0;
+        self.byte_count = 0;
+    }
+
+    /// Parses all lines in `unparsed`, which end at the current position, and appends the statements.
+    fn parse_unparsed<F>(&mut self, mapping: &mut F) -> Vec<StatementId>
+    where
+        F: FnMut(&Statement, LineSpan) -> M,
+    {
+        let to_parse = &self.unparsed.join("\n");
+        let mut start = Position {
+            offset: self.byte_count - to_parse.len() - 1, // -1 for the newline after the current line.
+            line: self.line_count - self.unparsed.len() + 1, // +1 because unparsed ends with the current line.
+            column: 1,
+        };
+
+        // An `else` on a new line belongs to the `if` statement before it, which is parsed again together with the `else`.
+        let mut merged = None;
+        if let Some((with_if, if_start)) = self.prepend_if(to_parse, start) {
+            match parse_statements(&with_if) {
                 Ok(stmts) => {
-                    let stmts = stmts.into_map(&mut |stmt, span| {
-                        let first_unparsed_line = self.line_count - self.unparsed.len() + 1; // +1 because unparsed always contains the current line.
-                        let shifted_span = span.shifted(first_unparsed_line);
-                        mapping(&stmt, shifted_span)
-                    });
-                    let mut new_ids = self.statements.concat(stmts);
-                    self.unparsed.clear(); // We have parsed everything successfully.
-                    added_ids.append(&mut new_ids);
+                    debug!("Attaching else to the preceding if statement.");
+                    self.statements.pop(); // The merged statement takes over the id of the if statement.
+                    start = if_start;
+                    merged = Some((stmts, Vec::new()));
                 }
+                Err(e) => trace!("Could not attach else:\n{}", e),
+            }
+        }
+
+        let (stmts, diagnostics) = match merged {
+            Some(merged) => merged,
+            None => match parse_statements(to_parse) {
+                Ok(stmts) => (stmts, Vec::new()),
                 Err(e) => {
-                    // If the parsing error occurred at the very last symbol,
-                    // we assume that it is simply incomplete and will try again when we have more input.
                     trace!("Encountered error while parsing {}:\n{}", to_parse, e);
-                    if let pest::error::InputLocation::Pos(pos) = e.location {
-                        trace!(
-                            "Error position is {}, last position is {}.",
-                            pos,
-                            to_parse.len()
-                        );
-                        if pos == to_parse.len() {
-                            debug!("Will retry with more input.");
-                            continue; // Current line is already pushed to self.unparsed, so it will be retried on next iteration.
-                        }
-                    }
-                    debug!("Skipping this input.");
-                    self.unparsed.clear(); // We determined that there is an error in the currently unparsed code.
+                    debug!("Recovering from the error.");
+                    parse_statements_recovering(to_parse)
                 }
+            },
+        };
+
+        let mut unassignable = UnassignableTargets::default();
+        let stmts = stmts
+            .into_iter()
+            .map(|(mut stmt, _)| {
+                stmt.shift(start);
+                unassignable.visit_statement(&stmt);
+                let line_span = LineSpan::from(&stmt.span);
+                let meta = mapping(&stmt, line_span);
+                (stmt, meta)
+            })
+            .collect();
+        let new_ids = self.statements.concat(stmts);
+        self.diagnostics.extend(
+            diagnostics
+                .into_iter()
+                .map(|diagnostic| diagnostic.shifted(start)),
+        );
+        self.diagnostics.extend(unassignable.0);
+        self.unparsed.clear(); // Everything was either parsed or recorded as unparsed.
+        new_ids
+    }
+
+    /// If `code` starts with an `else` that continues the last statement, an `if` on the line before,
+    /// returns the code of the `if` statement followed by `code` and the start of the `if` statement.
+    fn prepend_if(&self, code: &str, start: Position) -> Option<(String, Position)> {
+        let rest = code.trim_start().strip_prefix("else")?;
+        if rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '.' || c == '_') {
+            return None; // A variable like `elsewhere`.
+        }
+        let (last, _) = self.statements.last()?;
+        let is_if = match &last.kind {
+            StatementKind::If(_, _, _) => true,
+            StatementKind::TailComment(inner, _) => {
+                matches!(inner.kind, StatementKind::If(_, _, _))
             }
+            _ => false,
+        };
+        if !is_if || last.span.end.line + 1 != start.line {
+            return None;
         }
-
-        added_ids
+        let source = last.source.as_ref()?;
+        // The trailing whitespace of the `if` line is trimmed from the source, but is needed for correct offsets.
+        let trailing = (start.offset - last.span.end.offset).saturating_sub(1);
+        let with_if = format!("{}{}\n{}", source, " ".repeat(trailing), code);
+        Some((with_if, last.span.start))
     }
 
     /// Returns this collection's `Statements` by reference.
@@ -389,12 +1521,273 @@ impl<M: std::fmt::Debug> Parsed<M> {
         &self.statements
     }
 
+    /// Returns why the lines added last are waiting for more input before they are parsed,
+    /// or `None` if all lines have been parsed.
+    pub fn incomplete(&self) -> Option<Incomplete> {
+        self.scanner.incomplete()
+    }
+
+    /// Appends a statement that was parsed separately, e. g. from another file, and returns its id.
+    pub fn push(&mut self, stmt: Statement, meta: M) -> StatementId {
+        self.statements.append(stmt, meta)
+    }
+
+    /// Records a problem that was encountered outside of this collection, e. g. in another file.
+    pub fn push_diagnostic(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Returns the problems encountered while parsing.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
     /// Consumes this collection and returns its `Statements`.
     pub fn into_statements(self) -> Statements<M> {
         self.statements
     }
 }
 
+/// The reason why the lines added to a `Parsed` cannot form complete statements yet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Incomplete {
+    /// A bracket like `(` or `{` that is not closed yet.
+    Bracket { bracket: String, position: Position },
+    /// A string or a name in backticks that is not closed yet.
+    String { quote: String, position: Position },
+    /// A line ending with an operator like `+` or `<-`, which is continued on the next line.
+    Operator {
+        operator: String,
+        position: Position,
+    },
+    /// A statement like `if (x)`, `function(x)` or `else` without its body.
+    Body { keyword: String, position: Position },
+}
+
+impl Incomplete {
+    /// Where the construct that is not complete starts.
+    pub fn position(&self) -> Position {
+        match self {
+            Incomplete::Bracket { position, .. }
+            | Incomplete::String { position, .. }
+            | Incomplete::Operator { position, .. }
+            | Incomplete::Body { position, .. } => *position,
+        }
+    }
+}
+
+impl Display for Incomplete {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let line = self.position().line;
+        match self {
+            Incomplete::Bracket { bracket, .. } => {
+                write!(f, "unclosed `{}` at line {}", bracket, line)
+            }
+            // Backticks are quoted with double backticks like in Markdown.
+            Incomplete::String { quote, .. } if quote.contains('`') => {
+                write!(f, "unterminated `` {} `` at line {}", quote, line)
+            }
+            Incomplete::String { quote, .. } => {
+                write!(f, "unterminated `{}` at line {}", quote, line)
+            }
+            Incomplete::Operator { operator, .. } => {
+                write!(f, "operand of `{}` missing at line {}", operator, line)
+            }
+            Incomplete::Body { keyword, .. } => {
+                write!(f, "body of `{}` missing at line {}", keyword, line)
+            }
+        }
+    }
+}
+
+/// Keeps track of the brackets, strings and continued lines in the lines added so far,
+/// such that code is only parsed once it can be complete.
+///
+/// The scanner only knows as much of R's syntax as is needed for this,
+/// anything else, like mismatched brackets, is left to the parser to report.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct Scanner {
+    /// The open brackets, along with the keyword whose header a `(` starts, e. g. `if` in `if (`.
+    brackets: Vec<(String, Option<String>, Position)>,
+    /// The open string with the text that closes it, e. g. `)"` for the raw string `r"(`.
+    string: Option<(String, String, Position)>,
+    /// The reason why the last token on the last line needs another line.
+    continuation: Option<Incomplete>,
+}
+
+impl Scanner {
+    /// Why the code scanned so far cannot be complete, if it cannot.
+    fn incomplete(&self) -> Option<Incomplete> {
+        if let Some((quote, _, position)) = &self.string {
+            return Some(Incomplete::String {
+                quote: quote.clone(),
+                position: *position,
+            });
+        }
+        if let Some((bracket, _, position)) = self.brackets.last() {
+            return Some(Incomplete::Bracket {
+                bracket: bracket.clone(),
+                position: *position,
+            });
+        }
+        self.continuation.clone()
+    }
+
+    /// Scans the `line`, which starts at `start`.
+    fn scan(&mut self, line: &str, start: Position) {
+        let chars: Vec<(usize, char)> = line.char_indices().collect();
+        let position = |i: usize| Position {
+            offset: start.offset + chars.get(i).map_or(line.len(), |(offset, _)| *offset),
+            line: start.line,
+            column: i + 1,
+        };
+        let text =
+            |from: usize, to: usize| chars[from..to].iter().map(|(_, c)| c).collect::<String>();
+        let is_name = |c: char| c.is_alphanumeric() || c == '.' || c == '_';
+        let mut i = 0;
+        // The keyword that precedes a `(`, e. g. `function`.
+        let mut keyword = None;
+        while i < chars.len() {
+            if let Some((_, closing, _)) = &self.string {
+                match chars[i].1 {
+                    '\\' if closing.len() == 1 => i += 2,
+                    _ if text(i, (i + closing.chars().count()).min(chars.len())) == *closing => {
+                        i += closing.chars().count();
+                        self.string = None;
+                        self.continuation = None;
+                    }
+                    _ => i += 1,
+                }
+                continue;
+            }
+            let c = chars[i].1;
+            let next = chars.get(i + 1).map(|(_, c)| *c);
+            if c.is_whitespace() {
+                i += 1;
+                continue;
+            }
+            if c == '#' {
+                break;
+            }
+            let token_start = i;
+            let header = keyword.take();
+            match c {
+                '"' | '\'' | '`' => {
+                    self.string = Some((c.to_string(), c.to_string(), position(i)));
+                    i += 1;
+                    continue;
+                }
+                // Raw strings like `r"(...)"` or `R'--[...]--'`.
+                'r' | 'R'
+                    if matches!(next, Some('"' | '\'')) && (i == 0 || !is_name(chars[i - 1].1)) =>
+                {
+                    let quote = next.unwrap();
+                    let dashes = chars[i + 2..].iter().take_while(|(_, c)| *c == '-').count();
+                    let bracket = chars.get(i + 2 + dashes).map(|(_, c)| *c);
+                    let closing = match bracket {
+                        Some('(') => ')',
+                        Some('[') => ']',
+                        Some('{') => '}',
+                        _ => {
+                            i += 1; // Not a raw string, so `r` is a name followed by a string.
+                            continue;
+                        }
+                    };
+                    let closing = format!("{}{}{}", closing, "-".repeat(dashes), quote);
+                    self.string = Some((text(i, i + 3 + dashes), closing, position(i)));
+                    i += 3 + dashes;
+                    continue;
+                }
+                '(' | '[' | '{' => {
+                    let bracket = if c == '[' && next == Some('[') {
+                        "[["
+                    } else {
+                        &line[chars[i].0..chars[i].0 + 1]
+                    };
+                    let header = if c == '(' { header } else { None };
+                    self.brackets
+                        .push((bracket.to_string(), header, position(i)));
+                    self.continuation = None;
+                    i += bracket.len();
+                    continue;
+                }
+                ')' | ']' | '}' => {
+                    let bracket = match self.brackets.last() {
+                        Some((open, _, _)) if open == "[[" && c == ']' && next == Some(']') => "]]",
+                        _ => &line[chars[i].0..chars[i].0 + 1],
+                    };
+                    let matches = |open: &str| {
+                        matches!(
+                            (open, bracket),
+                            ("(", ")") | ("[", "]") | ("[[", "]]") | ("{", "}")
+                        )
+                    };
+                    match self.brackets.pop() {
+                        Some((open, keyword, position)) if matches(&open) => {
+                            self.continuation =
+                                keyword.map(|keyword| Incomplete::Body { keyword, position });
+                        }
+                        // A syntax error, which is left for the parser to report instead of waiting for more lines.
+                        _ => {
+                            self.brackets.clear();
+                            self.continuation = None;
+                        }
+                    }
+                    i += bracket.len();
+                    continue;
+                }
+                _ if is_name(c) => {
+                    while i < chars.len() && is_name(chars[i].1) {
+                        i += 1;
+                    }
+                    let word = text(token_start, i);
+                    self.continuation = match word.as_str() {
+                        "repeat" | "else" => Some(Incomplete::Body {
+                            keyword: word,
+                            position: position(token_start),
+                        }),
+                        "if" | "for" | "while" | "function" => {
+                            keyword = Some(word);
+                            None
+                        }
+                        _ => None,
+                    };
+                    continue;
+                }
+                // The shorthand `\(x)` for `function(x)`.
+                '\\' => {
+                    keyword = Some("\\".to_string());
+                    i += 1;
+                    continue;
+                }
+                '%' => {
+                    i += 1;
+                    while i < chars.len() && chars[i].1 != '%' {
+                        i += 1;
+                    }
+                    i = (i + 1).min(chars.len());
+                }
+                _ if "+-*/^<>=!&|~:$@?".contains(c) => {
+                    while i < chars.len() && "+-*/^<>=!&|~:$@?".contains(chars[i].1) {
+                        i += 1;
+                    }
+                }
+                // Commas, semicolons and anything unknown.
+                _ => {
+                    self.continuation = None;
+                    i += 1;
+                    continue;
+                }
+            }
+            self.continuation = Some(Incomplete::Operator {
+                operator: text(token_start, i),
+                position: position(token_start),
+            });
+        }
+    }
+}
+
 /// Parses `code` into a collection of `Statements` associated with their `LineSpan` information.
 /// If the parser fails, the parsing error is returned instead.
 pub fn parse_statements(code: &str) -> Result<Statements<LineSpan>, Error> {
@@ -405,8 +1798,9 @@ pub fn parse_statements(code: &str) -> Result<Statements<LineSpan>, Error> {
             Rule::EOI => None,
             _ => {
                 // parse_line handles all other cases.
-                let line_span = LineSpan::from(token.as_span());
-                Some((parse_line(token), line_span))
+                let statement = parse_line(token);
+                let line_span = LineSpan::from(&statement.span);
+                Some((statement, line_span))
             }
         })
         .collect();
@@ -414,48 +1808,289 @@ pub fn parse_statements(code: &str) -> Result<Statements<LineSpan>, Error> {
     Ok(Statements::from_iter(new_statements))
 }
 
+/// Parses `code` into a collection of `Statements` like `parse_statements`, but continues after errors.
+///
+/// Statements that cannot be parsed are kept as `StatementKind::Unparsed` and described by a `Diagnostic`.
+/// An unparsed statement reaches from the start of the line where the faulty statement begins to the end of the line with the error.
+pub fn parse_statements_recovering(code: &str) -> (Statements<LineSpan>, Vec<Diagnostic>) {
+    let mut statements = Vec::new();
+    let mut diagnostics = Vec::new();
+    let mut start = 0; // Always the start of a line.
+    while start <= code.len() {
+        let rest = &code[start..];
+        let offset = Position::from(pest::Position::new(code, start).unwrap()); // Start is always at a character boundary.
+        let error = match parse_statements(rest) {
+            Ok(stmts) => {
+                statements.extend(stmts.into_iter().map(|(stmt, _)| stmt.shifted(offset)));
+                break;
+            }
+            Err(error) => error,
+        };
+        let diagnostic = Diagnostic::from_error(&error, rest);
+        let error_position = diagnostic.span.start.offset;
+
+        // The faulty statement begins at the latest line start before the error, up to which everything parses.
+        let (statement_start, preceding) = rest[..error_position]
+            .match_indices('\n')
+            .map(|(newline, _)| newline + 1)
+            .rev()
+            .chain(std::iter::once(0))
+            .find_map(|line_start| {
+                parse_statements(&rest[..line_start])
+                    .ok()
+                    .map(|stmts| (line_start, stmts))
+            })
+            .unwrap(); // The empty code always parses.
+        statements.extend(preceding.into_iter().map(|(stmt, _)| stmt.shifted(offset)));
+
+        let statement_end = rest[error_position..]
+            .find('\n')
+            .map(|newline| error_position + newline)
+            .unwrap_or_else(|| rest.len());
+        let span = Span {
+            start: Position::from(pest::Position::new(rest, statement_start).unwrap()),
+            end: Position::from(pest::Position::new(rest, statement_end).unwrap()),
+        };
+        let code = rest[statement_start..statement_end].to_string();
+        let unparsed = Statement {
+            kind: StatementKind::Unparsed(code.clone()),
+            span,
+            source: Some(code),
+        };
+        statements.push(unparsed.shifted(offset));
+        diagnostics.push(diagnostic.shifted(offset));
+
+        start += statement_end + 1; // Skip the newline.
+    }
+
+    let statements = statements
+        .into_iter()
+        .map(|stmt| {
+            let line_span = LineSpan::from(&stmt.span);
+            (stmt, line_span)
+        })
+        .collect();
+    (statements, diagnostics)
+}
+
+/// A problem encountered while parsing.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    /// The tokens that would have been valid at the location of the problem.
+    pub expected: Vec<String>,
+    /// The file that contains the problem, if the code was read from a file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
+impl Diagnostic {
+    /// Describes the parsing `error` that occurred in `code`.
+    fn from_error(error: &Error, code: &str) -> Self {
+        let (start, end) = match error.location {
+            pest::error::InputLocation::Pos(pos) => (pos, pos),
+            pest::error::InputLocation::Span(span) => span,
+        };
+        let expected = match &error.variant {
+            pest::error::ErrorVariant::ParsingError { positives, .. } => {
+                positives.iter().map(|rule| format!("{:?}", rule)).collect()
+            }
+            pest::error::ErrorVariant::CustomError { .. } => Vec::new(),
+        };
+        Diagnostic {
+            message: error.variant.message().into_owned(),
+            span: Span {
+                start: Position::from(pest::Position::new(code, start).unwrap()), // Errors always point into the code.
+                end: Position::from(pest::Position::new(code, end).unwrap()),
+            },
+            expected,
+            file: None,
+        }
+    }
+
+    /// Moves this diagnostic to code that starts at `start`.
+    fn shifted(self, start: Position) -> Self {
+        Diagnostic {
+            span: self.span.shifted(start),
+            ..self
+        }
+    }
+}
+
+/// Describes the targets of assignments that do not name a variable, e. g. `f() <- 1`, which R refuses to run.
+#[derive(Default)]
+struct UnassignableTargets(Vec<Diagnostic>);
+
+impl Visit for UnassignableTargets {
+    fn visit_statement(&mut self, stmt: &Statement) {
+        if let StatementKind::Assignment(left, additional, _, _) = &stmt.kind {
+            for target in std::iter::once(left).chain(additional) {
+                if target.assigned_variable_name().is_none() {
+                    self.0.push(Diagnostic {
+                        message: format!("Could not find a variable in the target {}.", target),
+                        span: target.span,
+                        expected: Vec::new(),
+                        file: None,
+                    });
+                }
+            }
+        }
+        walk_statement(self, stmt)
+    }
+}
+
 /// Information on which source code lines a statement spans.
-#[derive(Debug, Serialize, PartialEq, Eq, Default, Deserialize, Clone)]
+#[derive(Serialize, PartialEq, Eq, Default, Deserialize, Clone)]
 pub struct LineSpan {
     /// First line number the statement occupies.
     from: usize,
     /// Last line number the statement occupies.
     to: usize,
+    /// The file the statement was read from, if it was read from a file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file: Option<PathBuf>,
 }
 
 impl LineSpan {
-    /// Shift this span's line numbers such that it starts at the `new_start`.
-    pub fn shifted(self, new_start: usize) -> Self {
-        let offset = new_start - 1; // Minus one, because line count starts at one.
-        Self {
-            from: self.from + offset,
-            to: self.to + offset,
+    /// Returns these lines as lines of the `file`.
+    pub fn in_file(self, file: impl Into<PathBuf>) -> Self {
+        LineSpan {
+            file: Some(file.into()),
+            ..self
+        }
+    }
+
+    /// The file the statement was read from, if it was read from a file.
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// The number of the first line the statement occupies, starting at 1.
+    pub fn first_line(&self) -> usize {
+        self.from
+    }
+
+    /// Whether the statement occupies the `line`, starting at 1.
+    pub fn contains_line(&self, line: usize) -> bool {
+        (self.from..=self.to).contains(&line)
+    }
+}
+
+/// Leaves out a missing file, since most code is not read from files.
+impl std::fmt::Debug for LineSpan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("LineSpan");
+        debug.field("from", &self.from).field("to", &self.to);
+        if let Some(file) = &self.file {
+            debug.field("file", file);
         }
+        debug.finish()
     }
 }
 
-impl<'a, S> From<S> for LineSpan
+impl<S> From<S> for LineSpan
 where
-    S: Borrow<pest::Span<'a>>,
+    S: Borrow<Span>,
 {
     fn from(other: S) -> Self {
         let span = other.borrow();
         Self {
-            from: span.start_pos().line_col().0,
-            to: span.end_pos().line_col().0,
+            from: span.start.line,
+            to: span.end.line,
+            file: None,
         }
     }
 }
 
-/// Helper macro for use instead of `unreachable!()` that outputs more information.
-macro_rules! unexpected_rule {
-    ( $rule:ident, $pair:ident) => {
-        panic!(
-            "Encountered unexpected rule {:?} for input {:#?}.",
-            $rule,
-            $pair.as_str()
+/// The region of source code that a node was parsed from.
+///
+/// Nodes that were not parsed, but constructed in code, have the default span with line number zero.
+#[derive(Serialize, PartialEq, Eq, Default, Deserialize, Clone, Copy)]
+pub struct Span {
+    /// Position of the first character.
+    pub start: Position,
+    /// Position right after the last character.
+    pub end: Position,
+}
+
+impl Span {
+    /// Returns the span from the start of this span to the end of the `other` span.
+    pub fn to(self, other: Span) -> Self {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+
+    /// Moves this span, which was parsed from code starting at the beginning of a line, to code that starts at `start`.
+    pub fn shifted(self, start: Position) -> Self {
+        Span {
+            start: self.start.shifted(start),
+            end: self.end.shifted(start),
+        }
+    }
+}
+
+/// Displays the span compactly as `line:column-line:column (start..end)`.
+impl std::fmt::Debug for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}-{}:{} ({}..{})",
+            self.start.line,
+            self.start.column,
+            self.end.line,
+            self.end.column,
+            self.start.offset,
+            self.end.offset
         )
-    };
+    }
+}
+
+impl<'a> From<pest::Span<'a>> for Span {
+    fn from(other: pest::Span<'a>) -> Self {
+        Span {
+            start: Position::from(other.start_pos()),
+            end: Position::from(other.end_pos()),
+        }
+    }
+}
+
+/// A location in source code.
+#[derive(Debug, Serialize, PartialEq, Eq, Default, Deserialize, Clone, Copy)]
+pub struct Position {
+    /// Byte offset from the start of the code.
+    pub offset: usize,
+    /// Line number, starting at one.
+    pub line: usize,
+    /// Column number in characters, starting at one.
+    pub column: usize,
+}
+
+impl Position {
+    fn shifted(self, start: Position) -> Self {
+        Position {
+            offset: self.offset + start.offset,
+            line: self.line + start.line - 1, // Minus one, because line count starts at one.
+            column: if self.line == 1 {
+                self.column + start.column - 1
+            } else {
+                self.column
+            },
+        }
+    }
+}
+
+impl<'a> From<pest::Position<'a>> for Position {
+    fn from(other: pest::Position<'a>) -> Self {
+        let (line, column) = other.line_col();
+        Position {
+            offset: other.pos(),
+            line,
+            column,
+        }
+    }
 }
 
 /// Parses a token representing a single line of code.
@@ -464,29 +2099,52 @@ macro_rules! unexpected_rule {
 ///
 /// This function panics if the token does not represent a line.
 fn parse_line(line_pair: pest::iterators::Pair<Rule>) -> Statement {
+    let line_token = line_pair.clone();
     match line_pair.as_rule() {
-        Rule::empty => Statement::Empty,
+        Rule::empty => Statement::parsed(StatementKind::Empty, &line_token),
         Rule::line => {
             let mut line = line_pair.into_inner();
             let first_pair = line.next().unwrap(); // A line always contains at least a statement or a comment.
-            let first: Statement = match first_pair.as_rule() {
+            let first_token = first_pair.clone();
+            let first = match first_pair.as_rule() {
                 Rule::statement => {
                     let statement = first_pair.into_inner().next().unwrap(); // Take statement out of line.
                     match statement.as_rule() {
-                        Rule::expression => Statement::Expression(parse_expression(statement)),
+                        Rule::expression => StatementKind::Expression(parse_expression(statement)),
                         Rule::assignment => {
                             // Can be multiple assignment, e. g. a=b=c=1. We want to extract the right-most expression,
                             // which is assigned to all others.
-                            let mut elements: Vec<Expression> =
-                                statement.into_inner().map(parse_expression).collect();
+                            // The operator next to each target is the operator of that target.
+                            let mut directions = Vec::new();
+                            let mut elements: Vec<Expression> = statement
+                                .into_inner()
+                                .filter_map(|element| match element.as_rule() {
+                                    Rule::left_assignment_operator
+                                    | Rule::right_assignment_operator => {
+                                        directions.push(match element.as_str() {
+                                            "<<-" => AssignmentDirection::SuperLeft,
+                                            "->>" => AssignmentDirection::SuperRight,
+                                            "->" => AssignmentDirection::Right,
+                                            _ => AssignmentDirection::Left,
+                                        });
+                                        None
+                                    }
+                                    _ => Some(parse_expression(element)),
+                                })
+                                .collect();
+                            if directions.iter().any(|direction| direction.is_right()) {
+                                // 1 -> b -> a is stored like a <- b <- 1.
+                                elements.reverse();
+                                directions.reverse();
+                            }
                             let error = "Assignment did not have enough elements.";
                             let right = elements.pop().expect(error);
                             if elements.is_empty() {
-                                panic!(error);
+                                panic!("{}", error);
                             }
                             let left = elements.remove(0);
                             let additional = elements;
-                            Statement::Assignment(left, additional, right)
+                            StatementKind::Assignment(left, additional, right, directions)
                         }
                         Rule::if_statement => {
                             let mut elements = statement.into_inner();
@@ -505,7 +2163,7 @@ fn parse_line(line_pair: pest::iterators::Pair<Rule>) -> Statement {
                                     .collect::<Vec<Statement>>()
                             });
 
-                            Statement::If(condition, body, else_body)
+                            StatementKind::If(condition, body, else_body)
                         }
                         Rule::while_statement => {
                             let mut elements = statement.into_inner();
@@ -516,7 +2174,7 @@ fn parse_line(line_pair: pest::iterators::Pair<Rule>) -> Statement {
                             };
                             let body = elements.next().unwrap().into_inner(); // For statement always has a body.
                             let body: Vec<Statement> = body.map(parse_line).collect();
-                            Statement::While(condition, body)
+                            StatementKind::While(condition, body)
                         }
                         Rule::for_statement => {
                             let mut elements = statement.into_inner();
@@ -529,23 +2187,38 @@ fn parse_line(line_pair: pest::iterators::Pair<Rule>) -> Statement {
                             };
                             let body = elements.next().unwrap().into_inner(); // For statement always has a body.
                             let body: Vec<Statement> = body.map(parse_line).collect();
-                            Statement::For(pattern, range, body)
+                            StatementKind::For(pattern, range, body)
+                        }
+                        Rule::repeat_statement => {
+                            let body = statement.into_inner().next().unwrap().into_inner(); // Repeat statement always has a body.
+                            let body: Vec<Statement> = body.map(parse_line).collect();
+                            StatementKind::Repeat(body)
+                        }
+                        Rule::break_statement => StatementKind::Break,
+                        Rule::next_statement => StatementKind::Next,
+                        Rule::return_statement => {
+                            let value = statement.into_inner().next().map(parse_expression);
+                            StatementKind::Return(value)
                         }
                         Rule::library => {
                             let name = statement.into_inner().next().unwrap(); // Library name always exists.
-                            Statement::Library(name.as_str().into())
+                            StatementKind::Library(parse_identifier(&name))
                         }
                         r => unexpected_rule!(r, statement),
                     }
                 }
-                Rule::comment => Statement::Comment(first_pair.as_str().to_string()),
+                Rule::comment => StatementKind::Comment(first_pair.as_str().to_string()),
                 r => unexpected_rule!(r, first_pair),
             };
+            let first = Statement::parsed(first, &first_token);
 
             let maybe_comment = line.next();
             if let Some(comment) = maybe_comment {
                 // Only two-part structure is a tail comment.
-                Statement::TailComment(Box::new(first), comment.as_str().to_string())
+                Statement::parsed(
+                    StatementKind::TailComment(Box::new(first), comment.as_str().to_string()),
+                    &line_token,
+                )
             } else {
                 first
             }
@@ -556,36 +2229,141 @@ fn parse_line(line_pair: pest::iterators::Pair<Rule>) -> Statement {
 
 /// Parses a token representing an expression.
 ///
+/// The token holds a flat sequence of operands and operators, which is arranged into a tree according to R's operator precedence.
+///
 /// # Panics
 ///
 /// This function panics if the token does not represent an expression.
 fn parse_expression(expression_pair: pest::iterators::Pair<Rule>) -> Expression {
-    let mut whole_expression = expression_pair.into_inner();
-    let expression = whole_expression.next().unwrap(); // Expression is always non-empty.
-    let mut rexp: Expression = match expression.as_rule() {
-        Rule::constant => Expression::Constant(expression.as_str().to_string()),
-        Rule::identifier => Expression::Variable(expression.as_str().to_string()),
-        Rule::prefix => {
-            let mut prefix_expression = expression.into_inner();
-            let operator = prefix_expression.next().unwrap(); // Prefix always has operator.
-            let exp = prefix_expression.next().unwrap(); // Prefix always has expression.
-            Expression::Prefix(
-                operator.as_str().to_string(),
-                Box::new(parse_expression(exp)),
-            )
+    let mut parts = expression_pair.into_inner().peekable();
+    parse_operation(&mut parts, 0)
+}
+
+/// Parses operands and operators until reaching an infix operator that binds less tightly than `min_power`.
+///
+/// This is a Pratt parser, see `infix_binding_power` for how the operators bind.
+fn parse_operation(
+    parts: &mut Peekable<pest::iterators::Pairs<Rule>>,
+    min_power: u8,
+) -> Expression {
+    let first = parts.next().unwrap(); // Operators are always followed by an operand.
+    let mut left = match first.as_rule() {
+        Rule::prefix_operator => {
+            let operator = first.as_str();
+            let operand = parse_operation(parts, prefix_binding_power(operator));
+            let span = Span::from(first.as_span()).to(operand.span);
+            let kind = match operator {
+                "~" => ExpressionKind::OneSidedFormula(Box::new(operand)),
+                operator => ExpressionKind::Prefix(operator.to_string(), Box::new(operand)),
+            };
+            Expression::new(kind, span)
+        }
+        _ => parse_operand(first, parts),
+    };
+
+    while let Some(infix) = parts.peek() {
+        let operator = infix.as_str();
+        let (left_power, right_power) = infix_binding_power(operator);
+        if left_power < min_power {
+            break;
+        }
+        let operator = operator.to_string();
+        parts.next();
+        let right = parse_operation(parts, right_power);
+        let span = left.span.to(right.span);
+        let kind = match operator.as_str() {
+            "~" => ExpressionKind::TwoSidedFormula(Box::new(left), Box::new(right)),
+            "|>" => ExpressionKind::Pipe(Box::new(left), Box::new(right)),
+            _ => ExpressionKind::Infix(operator, Box::new(left), Box::new(right)),
+        };
+        left = Expression::new(kind, span);
+    }
+
+    left
+}
+
+/// The precedence levels of R's operators, from loosest to tightest.
+///
+/// See `?Syntax` in R.
+fn precedence(operator: &str, unary: bool) -> u8 {
+    match (operator, unary) {
+        ("~", _) => 1,
+        ("|", false) | ("||", false) => 2,
+        ("&", false) | ("&&", false) => 3,
+        ("!", true) => 4,
+        ("<", false)
+        | (">", false)
+        | ("<=", false)
+        | (">=", false)
+        | ("==", false)
+        | ("!=", false) => 5,
+        ("+", false) | ("-", false) => 6,
+        ("*", false) | ("/", false) => 7,
+        ("|>", false) => 8,
+        (":", false) => 9,
+        ("+", true) | ("-", true) => 10,
+        ("^", false) => 11,
+        (operator, false) if operator.starts_with('%') => 8,
+        (operator, _) => unreachable!("Unknown operator {}.", operator),
+    }
+}
+
+/// How strongly an infix operator binds to its left and right operand.
+///
+/// The stronger side wins, so left-associative operators bind more strongly to the right and vice versa.
+fn infix_binding_power(operator: &str) -> (u8, u8) {
+    let level = 2 * precedence(operator, false);
+    match operator {
+        "^" => (level + 1, level),
+        _ => (level, level + 1),
+    }
+}
+
+/// How strongly a prefix operator binds to its operand.
+fn prefix_binding_power(operator: &str) -> u8 {
+    2 * precedence(operator, true)
+}
+
+/// Parses an operand together with all indices, columns and calls that directly follow it.
+fn parse_operand(
+    primary: pest::iterators::Pair<Rule>,
+    parts: &mut Peekable<pest::iterators::Pairs<Rule>>,
+) -> Expression {
+    let span = Span::from(primary.as_span());
+    let kind = match primary.as_rule() {
+        Rule::constant => {
+            let literal = primary.into_inner().next().unwrap(); // A constant always contains its kind.
+            ExpressionKind::Constant(Literal::from_pair(literal))
+        }
+        Rule::identifier => ExpressionKind::Variable(parse_identifier(&primary)),
+        Rule::namespaced => {
+            let (package, operator, name) = primary.into_inner().next_tuple().unwrap(); // Namespaced always has package, operator and name.
+            ExpressionKind::Namespaced {
+                package: parse_identifier(&package),
+                name: parse_identifier(&name),
+                internal: operator.as_str() == ":::",
+            }
+        }
+        Rule::parenthesized => {
+            let inner = primary.into_inner().next().unwrap(); // Parentheses always contain an expression.
+            ExpressionKind::Paren(Box::new(parse_expression(inner)))
         }
-        Rule::formula => Expression::OneSidedFormula(Box::new(parse_expression(expression))),
         Rule::function_definition => {
-            let mut function = expression.into_inner();
+            let syntax = if primary.as_str().starts_with('\\') {
+                FunctionSyntax::Lambda
+            } else {
+                FunctionSyntax::Keyword
+            };
+            let mut function = primary.into_inner();
             let args = function.next().unwrap(); // Function always has (possibly empty) arguments.
             let args: Vec<(RIdentifier, Option<Expression>)> = args
                 .into_inner()
                 .map(|arg| {
                     match arg.as_rule() {
-                        Rule::required_parameter => (arg.as_str().into(), None),
+                        Rule::required_parameter => (parse_identifier(&arg), None),
                         Rule::parameter_with_default => {
                             let (arg, expression) = arg.into_inner().next_tuple().unwrap(); // Parameter with default always has name and default value.
-                            (arg.as_str().into(), Some(parse_expression(expression)))
+                            (parse_identifier(&arg), Some(parse_expression(expression)))
                         }
                         _ => unreachable!(),
                     }
@@ -593,20 +2371,62 @@ fn parse_expression(expression_pair: pest::iterators::Pair<Rule>) -> Expression
                 .collect();
             let body = function.next().unwrap().into_inner(); // Function always has a body.
             let body: Vec<Statement> = body.map(parse_line).collect();
-            Expression::Function(args, body)
+            ExpressionKind::Function(args, body, syntax)
         }
-        Rule::expression => parse_expression(expression),
-        r => unexpected_rule!(r, expression),
+        Rule::switch => {
+            let mut switch = primary.into_inner();
+            let selector = switch.next().unwrap(); // Switch always has a selector.
+            let arms = switch
+                .map(|arm| match arm.as_rule() {
+                    Rule::named_switch_arm => {
+                        let mut arm = arm.into_inner();
+                        let name = arm.next().unwrap(); // Named arm always has a name.
+                        let value = arm.next().map(parse_expression); // Value is missing when falling through.
+                        (Some(parse_identifier(&name)), value)
+                    }
+                    Rule::unnamed_switch_arm => {
+                        let value = arm.into_inner().next().unwrap(); // Unnamed arm always has a value.
+                        (None, Some(parse_expression(value)))
+                    }
+                    r => unexpected_rule!(r, arm),
+                })
+                .collect();
+            ExpressionKind::Switch(Box::new(parse_expression(selector)), arms)
+        }
+        r => unexpected_rule!(r, primary),
     };
+    let mut rexp = Expression::new(kind, span);
 
-    // Process all binary operators that follow.
-    // This process is due to the workaround for preventing left-recursion for these binary operators in the parser.
-    for infix in whole_expression {
-        rexp = match infix.as_rule() {
-            Rule::function_call => parse_function_expression(rexp, infix),
-            Rule::column => Expression::Column(Box::new(rexp), Box::new(parse_expression(infix))),
+    // Process all postfix operators that follow.
+    while let Some(postfix) = parts.peek() {
+        if postfix.as_rule() == Rule::infix_operator {
+            break;
+        }
+        let postfix = parts.next().unwrap();
+        let span = rexp.span.to(Span::from(postfix.as_span()));
+        let kind = match postfix.as_rule() {
+            Rule::function_call => {
+                rexp = parse_function_expression(rexp, postfix);
+                continue;
+            }
+            Rule::column => {
+                let column = postfix.into_inner().next().unwrap(); // Column always has a name.
+                let column = Expression::new(
+                    ExpressionKind::Variable(parse_identifier(&column)),
+                    Span::from(column.as_span()),
+                );
+                ExpressionKind::Column(Box::new(rexp), Box::new(column))
+            }
+            Rule::slot => {
+                let slot = postfix.into_inner().next().unwrap(); // Slot always has a name.
+                let slot = Expression::new(
+                    ExpressionKind::Variable(parse_identifier(&slot)),
+                    Span::from(slot.as_span()),
+                );
+                ExpressionKind::Slot(Box::new(rexp), Box::new(slot))
+            }
             Rule::index => {
-                let indices = infix
+                let indices = postfix
                     .into_inner()
                     .map(|maybe_expression| match maybe_expression.as_rule() {
                         Rule::expression => Some(parse_expression(maybe_expression)),
@@ -614,10 +2434,10 @@ fn parse_expression(expression_pair: pest::iterators::Pair<Rule>) -> Expression
                         _ => unreachable!(),
                     })
                     .collect();
-                Expression::Index(Box::new(rexp), indices)
+                ExpressionKind::Index(Box::new(rexp), indices)
             }
             Rule::list_index => {
-                let indices = infix
+                let indices = postfix
                     .into_inner()
                     .map(|maybe_expression| match maybe_expression.as_rule() {
                         Rule::expression => Some(parse_expression(maybe_expression)),
@@ -625,28 +2445,28 @@ fn parse_expression(expression_pair: pest::iterators::Pair<Rule>) -> Expression
                         _ => unreachable!(),
                     })
                     .collect();
-                Expression::ListIndex(Box::new(rexp), indices)
-            }
-            Rule::infix => {
-                let mut infix_operator = infix.into_inner();
-                let operator = infix_operator.next().unwrap(); // Operator is always present.
-                let right = infix_operator.next().unwrap(); // Infix operator always has right-hand side.
-                Expression::Infix(
-                    operator.as_str().into(),
-                    Box::new(rexp),
-                    Box::new(parse_expression(right)),
-                )
-            }
-            Rule::formula => {
-                Expression::TwoSidedFormula(Box::new(rexp), Box::new(parse_expression(infix)))
+                ExpressionKind::ListIndex(Box::new(rexp), indices)
             }
-            r => unexpected_rule!(r, infix),
+            r => unexpected_rule!(r, postfix),
         };
+        rexp = Expression::new(kind, span);
     }
 
     rexp
 }
 
+/// Parses a token representing an identifier, removing the backticks around quoted names.
+fn parse_identifier(identifier: &pest::iterators::Pair<Rule>) -> RIdentifier {
+    let text = identifier.as_str();
+    match text
+        .strip_prefix('`')
+        .and_then(|text| text.strip_suffix('`'))
+    {
+        Some(quoted) => quoted.replace("\\`", "`"),
+        None => text.to_string(),
+    }
+}
+
 /// Parse a token representing a function expression.
 ///
 /// # Panics
@@ -656,6 +2476,7 @@ fn parse_function_expression(
     expression: Expression,
     function_pair: pest::iterators::Pair<Rule>,
 ) -> Expression {
+    let span = expression.span.to(Span::from(function_pair.as_span()));
     let mut function = function_pair.into_inner();
     let maybe_arguments = function.next();
     let args: Vec<(Option<RIdentifier>, Expression)> = match maybe_arguments {
@@ -667,12 +2488,12 @@ fn parse_function_expression(
                             let mut argument = arg.into_inner();
                             let key = argument.next().unwrap(); // Key always exists.
                             let value = argument.next().unwrap(); // Value always exists.
-                            let value = parse_expression(value);
-                            (Some(key.as_str().to_string()), value)
+                            let value = parse_argument_value(value);
+                            (Some(parse_identifier(&key)), value)
                         }
                         Rule::unnamed_argument => {
                             let value = arg.into_inner().next().unwrap(); // Argument's value always exists.
-                            let value = parse_expression(value);
+                            let value = parse_argument_value(value);
                             (None, value)
                         }
                         r => unexpected_rule!(r, arg),
@@ -682,7 +2503,25 @@ fn parse_function_expression(
         }
         None => vec![],
     };
-    Expression::Call(Box::new(expression), args)
+    Expression::new(ExpressionKind::Call(Box::new(expression), args), span)
+}
+
+/// Parses the value of an argument, which is an `Infix` expression if it assigns a variable, e. g. `f(x -> y)`.
+fn parse_argument_value(value_pair: pest::iterators::Pair<Rule>) -> Expression {
+    match value_pair.as_rule() {
+        Rule::expression => parse_expression(value_pair),
+        Rule::argument_assignment => {
+            let span = Span::from(value_pair.as_span());
+            let (left, operator, right) = value_pair.into_inner().next_tuple().unwrap(); // Assignment always has both sides and an operator.
+            let kind = ExpressionKind::Infix(
+                operator.as_str().to_string(),
+                Box::new(parse_expression(left)),
+                Box::new(parse_argument_value(right)),
+            );
+            Expression::new(kind, span)
+        }
+        r => unexpected_rule!(r, value_pair),
+    }
 }
 
 #[cfg(test)]
@@ -695,140 +2534,268 @@ mod tests {
     #[macro_export]
     macro_rules! empty {
         () => {
-            Statement::Empty
+            Statement::from($crate::parser::StatementKind::Empty)
         };
     }
 
     #[macro_export]
     macro_rules! comment {
         ($text: literal) => {
-            Statement::Comment($text.to_string())
+            Statement::from($crate::parser::StatementKind::Comment($text.to_string()))
         };
     }
 
     #[macro_export]
     macro_rules! tail_comment {
         ($exp: expr, $text: literal) => {
-            Statement::TailComment(Box::new($exp), $text.to_string())
+            Statement::from($crate::parser::StatementKind::TailComment(
+                Box::new($exp),
+                $text.to_string(),
+            ))
         };
     }
 
     #[macro_export]
     macro_rules! assignment {
-        ($left: expr, $additional: expr, $right: expr) => {
-            Statement::Assignment($left, $additional, $right)
+        ($left: expr, $additional: expr, $right: expr) => {{
+            let additional: Vec<$crate::parser::Expression> = $additional;
+            let directions = vec![$crate::parser::AssignmentDirection::Left; additional.len() + 1];
+            Statement::from($crate::parser::StatementKind::Assignment(
+                $left, additional, $right, directions,
+            ))
+        }};
+        ($left: expr, $additional: expr, $right: expr, $directions: expr) => {
+            Statement::from($crate::parser::StatementKind::Assignment(
+                $left,
+                $additional,
+                $right,
+                $directions,
+            ))
         };
     }
 
     #[macro_export]
     macro_rules! if_stmt {
         ($cond: expr, $body: expr, $else_body: expr) => {
-            Statement::If($cond, $body, $else_body)
+            Statement::from($crate::parser::StatementKind::If($cond, $body, $else_body))
         };
     }
 
     #[macro_export]
     macro_rules! while_stmt {
         ($cond:expr, $body: expr) => {
-            Statement::While($cond, $body)
+            Statement::from($crate::parser::StatementKind::While($cond, $body))
         };
     }
 
     #[macro_export]
     macro_rules! for_stmt {
         ($pattern:expr, $range:expr, $body:expr) => {
-            Statement::For($pattern, $range, $body)
+            Statement::from($crate::parser::StatementKind::For($pattern, $range, $body))
+        };
+    }
+
+    #[macro_export]
+    macro_rules! repeat_stmt {
+        ($body:expr) => {
+            Statement::from($crate::parser::StatementKind::Repeat($body))
+        };
+    }
+
+    #[macro_export]
+    macro_rules! break_stmt {
+        () => {
+            Statement::from($crate::parser::StatementKind::Break)
+        };
+    }
+
+    #[macro_export]
+    macro_rules! next_stmt {
+        () => {
+            Statement::from($crate::parser::StatementKind::Next)
+        };
+    }
+
+    #[macro_export]
+    macro_rules! return_stmt {
+        () => {
+            Statement::from($crate::parser::StatementKind::Return(None))
+        };
+        ($value:expr) => {
+            Statement::from($crate::parser::StatementKind::Return(Some($value)))
         };
     }
 
     #[macro_export]
     macro_rules! library {
         ($name:literal) => {
-            Statement::Library($name.to_string())
+            Statement::from($crate::parser::StatementKind::Library($name.to_string()))
         };
     }
 
     #[macro_export]
     macro_rules! expression {
         ($exp: expr) => {
-            Statement::Expression($exp)
+            Statement::from($crate::parser::StatementKind::Expression($exp))
         };
     }
 
     #[macro_export]
     macro_rules! constant {
         ($value: literal) => {
-            Expression::Constant($value.to_string())
+            Expression::from($crate::parser::ExpressionKind::Constant(
+                $value
+                    .parse::<$crate::parser::Literal>()
+                    .unwrap_or_else(|e| panic!("Invalid literal {}:\n{}", $value, e)),
+            ))
         };
     }
 
     #[macro_export]
     macro_rules! variable {
         ($name: literal) => {
-            Expression::Variable($name.to_string())
+            Expression::from($crate::parser::ExpressionKind::Variable($name.to_string()))
         };
     }
 
     #[macro_export]
     macro_rules! call {
         ($exp: expr, $args: expr) => {
-            Expression::Call(Box::new($exp), $args)
+            Expression::from($crate::parser::ExpressionKind::Call(Box::new($exp), $args))
         };
     }
 
     #[macro_export]
     macro_rules! column {
         ($left:expr, $right:expr) => {
-            Expression::Column(Box::new($left), Box::new($right))
+            Expression::from($crate::parser::ExpressionKind::Column(
+                Box::new($left),
+                Box::new($right),
+            ))
+        };
+    }
+
+    #[macro_export]
+    macro_rules! slot {
+        ($left:expr, $right:expr) => {
+            Expression::from($crate::parser::ExpressionKind::Slot(
+                Box::new($left),
+                Box::new($right),
+            ))
+        };
+    }
+
+    #[macro_export]
+    macro_rules! namespaced {
+        ($package:literal, $name:literal, $internal:expr) => {
+            Expression::from($crate::parser::ExpressionKind::Namespaced {
+                package: $package.to_string(),
+                name: $name.to_string(),
+                internal: $internal,
+            })
         };
     }
 
     #[macro_export]
     macro_rules! index {
         ($left:expr,$right:expr) => {
-            Expression::Index(Box::new($left), $right)
+            Expression::from($crate::parser::ExpressionKind::Index(
+                Box::new($left),
+                $right,
+            ))
         };
     }
 
     #[macro_export]
     macro_rules! list_index {
         ($left:expr, $right:expr) => {
-            Expression::ListIndex(Box::new($left), $right)
+            Expression::from($crate::parser::ExpressionKind::ListIndex(
+                Box::new($left),
+                $right,
+            ))
         };
     }
 
     #[macro_export]
     macro_rules! one_sided_formula {
         ($exp:expr) => {
-            Expression::OneSidedFormula(Box::new($exp))
+            Expression::from($crate::parser::ExpressionKind::OneSidedFormula(Box::new(
+                $exp,
+            )))
         };
     }
 
     #[macro_export]
     macro_rules! two_sided_formula {
         ($left:expr,$right:expr) => {
-            Expression::TwoSidedFormula(Box::new($left), Box::new($right))
+            Expression::from($crate::parser::ExpressionKind::TwoSidedFormula(
+                Box::new($left),
+                Box::new($right),
+            ))
         };
     }
 
     #[macro_export]
     macro_rules! function {
         ($params:expr, $body:expr) => {
-            Expression::Function($params, $body)
+            Expression::from($crate::parser::ExpressionKind::Function(
+                $params,
+                $body,
+                $crate::parser::FunctionSyntax::Keyword,
+            ))
+        };
+        ($params:expr, $body:expr, $syntax:expr) => {
+            Expression::from($crate::parser::ExpressionKind::Function(
+                $params, $body, $syntax,
+            ))
+        };
+    }
+
+    #[macro_export]
+    macro_rules! switch {
+        ($selector:expr, $arms:expr) => {
+            Expression::from($crate::parser::ExpressionKind::Switch(
+                Box::new($selector),
+                $arms,
+            ))
         };
     }
 
     #[macro_export]
     macro_rules! prefix {
         ($op:literal,$exp:expr) => {
-            Expression::Prefix($op.to_string(), Box::new($exp))
+            Expression::from($crate::parser::ExpressionKind::Prefix(
+                $op.to_string(),
+                Box::new($exp),
+            ))
         };
     }
 
     #[macro_export]
     macro_rules! infix {
         ($op:literal, $left:expr,$right:expr) => {
-            Expression::Infix($op.to_string(), Box::new($left), Box::new($right))
+            Expression::from($crate::parser::ExpressionKind::Infix(
+                $op.to_string(),
+                Box::new($left),
+                Box::new($right),
+            ))
+        };
+    }
+
+    #[macro_export]
+    macro_rules! paren {
+        ($exp:expr) => {
+            Expression::from($crate::parser::ExpressionKind::Paren(Box::new($exp)))
+        };
+    }
+
+    #[macro_export]
+    macro_rules! pipe {
+        ($left:expr, $right:expr) => {
+            Expression::from($crate::parser::ExpressionKind::Pipe(
+                Box::new($left),
+                Box::new($right),
+            ))
         };
     }
 
@@ -852,22 +2819,11 @@ mod tests {
         assert_eq!(expected, actual_parsed, "Failed using Parsed.");
     }
 
-    /// The line-by-line nature of `Parsed` leads to a slightly different AST without empty statements and without else statements.
-    /// This functions takes a statement and returns a collection of statement that satisfies these constraints.
+    /// The line-by-line nature of `Parsed` leads to a slightly different AST without empty statements.
+    /// This functions takes a statement and returns a collection of statement that satisfies this constraint.
     fn clean_for_parsed(stmt: Statement) -> Vec<Statement> {
-        use Statement::*;
-        match stmt {
-            Empty => vec![],
-            If(condition, body, maybe_else_body) => {
-                if let Some(else_body) = maybe_else_body {
-                    let mut broken = vec![If(condition, body, None)];
-                    let mut else_body = else_body.into_iter().flat_map(clean_for_parsed).collect();
-                    broken.append(&mut else_body);
-                    broken
-                } else {
-                    vec![If(condition, body, None)]
-                }
-            }
+        match stmt.kind {
+            StatementKind::Empty => vec![],
             _ => vec![stmt],
         }
     }
@@ -921,39 +2877,156 @@ d <- 1
     }
 
     #[test]
-    fn parses_assignments() {
+    fn parses_assignments() {
+        let code = "\
+a <- 1
+line <-
+    \"break\"
+b = 2
+a=b=c=1
+colnames(something) <- c(\"R\", \"is\", \"crazy\")";
+        let expected = vec![
+            assignment!(variable!("a"), vec![], constant!("1")),
+            assignment!(variable!("line"), vec![], constant!("\"break\"")),
+            assignment!(variable!("b"), vec![], constant!("2")),
+            assignment!(
+                variable!("a"),
+                vec![variable!("b"), variable!("c")],
+                constant!("1")
+            ),
+            assignment!(
+                call!(variable!("colnames"), vec![(None, variable!("something"))]),
+                vec![],
+                call!(
+                    variable!("c"),
+                    vec![
+                        (None, constant!("\"R\"")),
+                        (None, constant!("\"is\"")),
+                        (None, constant!("\"crazy\"")),
+                    ]
+                )
+            ),
+        ];
+        assert_matches(code, expected);
+    }
+
+    #[test]
+    fn parses_superassignments() {
+        let code = "\
+counter <<- counter + 1
+a = b <<- 2";
+        let expected = vec![
+            assignment!(
+                variable!("counter"),
+                vec![],
+                infix!("+", variable!("counter"), constant!("1")),
+                vec![AssignmentDirection::SuperLeft]
+            ),
+            assignment!(
+                variable!("a"),
+                vec![variable!("b")],
+                constant!("2"),
+                vec![AssignmentDirection::Left, AssignmentDirection::SuperLeft]
+            ),
+        ];
+        assert_matches(code, expected);
+
+        // Only `b` is assigned in an enclosing environment.
+        let displayed: Vec<String> = parse_statements(code)
+            .unwrap_or_else(|e| panic!("{}", e))
+            .into_iter()
+            .map(|(stmt, _)| stmt.to_string())
+            .collect();
+        assert_eq!(vec!["counter <<- counter + 1", "a <- b <<- 2"], displayed);
+    }
+
+    #[test]
+    fn parses_right_assignments() {
+        let code = "\
+read.csv(\"x.csv\") -> kbd
+1 ->> global
+df %>% filter(x) ->
+    subset
+1 -> b -> a";
+        let expected = vec![
+            assignment!(
+                variable!("kbd"),
+                vec![],
+                call!(variable!("read.csv"), vec![(None, constant!("\"x.csv\""))]),
+                vec![AssignmentDirection::Right]
+            ),
+            assignment!(
+                variable!("global"),
+                vec![],
+                constant!("1"),
+                vec![AssignmentDirection::SuperRight]
+            ),
+            assignment!(
+                variable!("subset"),
+                vec![],
+                infix!(
+                    "%>%",
+                    variable!("df"),
+                    call!(variable!("filter"), vec![(None, variable!("x"))])
+                ),
+                vec![AssignmentDirection::Right]
+            ),
+            assignment!(
+                variable!("a"),
+                vec![variable!("b")],
+                constant!("1"),
+                vec![AssignmentDirection::Right, AssignmentDirection::Right]
+            ),
+        ];
+        assert_matches(code, expected);
+    }
+
+    #[test]
+    fn parses_assignments_in_arguments() {
         let code = "\
-a <- 1
-line <-
-    \"break\"
-b = 2
-a=b=c=1
-colnames(something) <- c(\"R\", \"is\", \"crazy\")";
+print(mean(x) -> m)
+f(n = y <- 2)";
         let expected = vec![
-            assignment!(variable!("a"), vec![], constant!("1")),
-            assignment!(variable!("line"), vec![], constant!("\"break\"")),
-            assignment!(variable!("b"), vec![], constant!("2")),
-            assignment!(
-                variable!("a"),
-                vec![variable!("b"), variable!("c")],
-                constant!("1")
-            ),
-            assignment!(
-                call!(variable!("colnames"), vec![(None, variable!("something"))]),
-                vec![],
-                call!(
-                    variable!("c"),
-                    vec![
-                        (None, constant!("\"R\"")),
-                        (None, constant!("\"is\"")),
-                        (None, constant!("\"crazy\"")),
-                    ]
-                )
-            ),
+            expression!(call!(
+                variable!("print"),
+                vec![(
+                    None,
+                    infix!(
+                        "->",
+                        call!(variable!("mean"), vec![(None, variable!("x"))]),
+                        variable!("m")
+                    )
+                )]
+            )),
+            expression!(call!(
+                variable!("f"),
+                vec![(
+                    Some("n".to_string()),
+                    infix!("<-", variable!("y"), constant!("2"))
+                )]
+            )),
         ];
         assert_matches(code, expected);
     }
 
+    #[test]
+    fn displays_assignments_in_original_direction() {
+        let code = "\
+a <- b <- 1
+1 -> b -> a
+a <- b <<- 2
+1 ->> b -> a";
+        let displayed: Vec<String> = parse_statements(code)
+            .unwrap_or_else(|e| panic!("{}", e))
+            .into_iter()
+            .map(|(stmt, _)| stmt.to_string())
+            .collect();
+        assert_eq!(
+            vec!["a <- b <- 1", "1 -> b -> a", "a <- b <<- 2", "1 ->> b -> a"],
+            displayed
+        );
+    }
+
     #[test]
     fn parses_function_calls() {
         let code = "\
@@ -991,7 +3064,7 @@ higher_order()(10)";
                 variable!("weird"),
                 vec![(Some("\"name\"".to_string()), constant!("1"))]
             )),
-            expression!(call!(variable!("name:::space"), vec![])),
+            expression!(call!(namespaced!("name", "space", true), vec![])),
             expression!(call!(
                 call!(variable!("higher_order"), vec![]),
                 vec![(None, constant!("10"))]
@@ -1000,16 +3073,95 @@ higher_order()(10)";
         assert_matches(code, expected);
     }
 
+    #[test]
+    fn extracts_string_values() {
+        let value = |code: &str| code.parse::<Literal>().unwrap().string_value();
+        assert_eq!(Some("a \"b\"\n".to_string()), value(r#""a \"b\"\n""#));
+        assert_eq!(Some(r"C:\data".to_string()), value(r"'C:\\data'"));
+        assert_eq!(Some(r"C:\(1)".to_string()), value(r#"r"-(C:\(1))-""#));
+        assert_eq!(None, value("1L"));
+    }
+
+    #[test]
+    fn extracts_number_values() {
+        let value = |code: &str| code.parse::<Literal>().unwrap().number_value();
+        assert_eq!(Some(1.5), value("1.5"));
+        assert_eq!(Some(5.0), value("5."));
+        assert_eq!(Some(16.0), value("0x10L"));
+        assert_eq!(Some(8.0), value("0x1p3"));
+        assert_eq!(Some(0.75), value("0x1.8p-1"));
+        assert_eq!(Some(255.0), value("0XFFP0"));
+        assert_eq!(None, value("2i"));
+        assert_eq!(None, value("\"1\""));
+    }
+
     #[test]
     fn parses_strings() {
         let code = "\
 'first'
-\"second\"
-`third`";
+\"second\"";
         let expected = vec![
             expression!(constant!("'first'")),
             expression!(constant!("\"second\"")),
-            expression!(constant!("`third`")),
+        ];
+        assert_matches(code, expected);
+    }
+
+    #[test]
+    fn parses_backtick_identifiers() {
+        let code = "\
+`my var` <- 1
+`x`
+df$`a column`
+`+`(1, `if` = 2)";
+        let expected = vec![
+            assignment!(variable!("my var"), vec![], constant!("1")),
+            expression!(variable!("x")),
+            expression!(column!(variable!("df"), variable!("a column"))),
+            expression!(call!(
+                variable!("+"),
+                vec![
+                    (None, constant!("1")),
+                    (Some("if".to_string()), constant!("2"))
+                ]
+            )),
+        ];
+        assert_matches(code, expected);
+    }
+
+    #[test]
+    fn displays_backticks_for_non_syntactic_names() {
+        let code = "`my var` <- `x` + `if`(.a, `_b`, ...)";
+        let display = parse_statements(code).unwrap()[StatementId(0)]
+            .0
+            .to_string();
+        assert_eq!("`my var` <- x + `if`(.a, `_b`, ...)", display);
+    }
+
+    #[test]
+    fn parses_namespaces_and_slots() {
+        let code = "\
+stats::lm(y ~ x)
+stats:::Pillai
+`my pkg`::f
+model@coef[1]
+obj@slot <- 2";
+        let expected = vec![
+            expression!(call!(
+                namespaced!("stats", "lm", false),
+                vec![(None, two_sided_formula!(variable!("y"), variable!("x")))]
+            )),
+            expression!(namespaced!("stats", "Pillai", true)),
+            expression!(namespaced!("my pkg", "f", false)),
+            expression!(index!(
+                slot!(variable!("model"), variable!("coef")),
+                vec![Some(constant!("1"))]
+            )),
+            assignment!(
+                slot!(variable!("obj"), variable!("slot")),
+                vec![],
+                constant!("2")
+            ),
         ];
         assert_matches(code, expected);
     }
@@ -1034,6 +3186,78 @@ higher_order()(10)";
         assert_matches(code, expected);
     }
 
+    #[test]
+    fn parses_typed_constants() {
+        let code = "\
+1L
+0xFF
+0x10L
+0x1p3
+0x1.8p-1
+2i
+1e5
+5.
+NA
+NA_integer_
+NA_real_
+NA_character_
+NULL
+Inf
+NaN
+TRUE
+r\"(C:\\path)\"
+R'---[brackets]\"]---'
+\"escaped \\\\\"
+NAME
+Info";
+        let number = |text: &str, kind| {
+            Expression::from(ExpressionKind::Constant(Literal::Number(text.into(), kind)))
+        };
+        let expected = vec![
+            expression!(number("1L", NumberKind::Integer)),
+            expression!(number("0xFF", NumberKind::Double)),
+            expression!(number("0x10L", NumberKind::Integer)),
+            expression!(number("0x1p3", NumberKind::Double)),
+            expression!(number("0x1.8p-1", NumberKind::Double)),
+            expression!(number("2i", NumberKind::Complex)),
+            expression!(number("1e5", NumberKind::Double)),
+            expression!(number("5.", NumberKind::Double)),
+            expression!(Expression::from(ExpressionKind::Constant(Literal::Na(
+                NaKind::Logical
+            )))),
+            expression!(Expression::from(ExpressionKind::Constant(Literal::Na(
+                NaKind::Integer
+            )))),
+            expression!(Expression::from(ExpressionKind::Constant(Literal::Na(
+                NaKind::Real
+            )))),
+            expression!(Expression::from(ExpressionKind::Constant(Literal::Na(
+                NaKind::Character
+            )))),
+            expression!(Expression::from(ExpressionKind::Constant(Literal::Null))),
+            expression!(Expression::from(ExpressionKind::Constant(Literal::Inf))),
+            expression!(Expression::from(ExpressionKind::Constant(Literal::NaN))),
+            expression!(Expression::from(ExpressionKind::Constant(
+                Literal::Logical(true)
+            ))),
+            expression!(Expression::from(ExpressionKind::Constant(Literal::String(
+                "r\"(C:\\path)\"".into(),
+                StringKind::Raw
+            )))),
+            expression!(Expression::from(ExpressionKind::Constant(Literal::String(
+                "R'---[brackets]\"]---'".into(),
+                StringKind::Raw
+            )))),
+            expression!(Expression::from(ExpressionKind::Constant(Literal::String(
+                "\"escaped \\\\\"".into(),
+                StringKind::DoubleQuoted
+            )))),
+            expression!(variable!("NAME")),
+            expression!(variable!("Info")),
+        ];
+        assert_matches(code, expected);
+    }
+
     #[test]
     fn parses_library_calls() {
         let code = "\
@@ -1099,8 +3323,8 @@ lm(y[subk]~factor(x[subk]))";
             expression!(two_sided_formula!(variable!("two"), variable!("sided"))),
             expression!(one_sided_formula!(infix!(
                 "+",
-                variable!("one"),
-                infix!("+", variable!("sided"), variable!("multiple"))
+                infix!("+", variable!("one"), variable!("sided")),
+                variable!("multiple")
             ))),
             expression!(two_sided_formula!(
                 variable!("two"),
@@ -1192,7 +3416,10 @@ y <- negate(!x)
                     vec![(None, prefix!("!", variable!("x")),)]
                 )
             ),
-            expression!(prefix!("-", infix!("+", constant!("1"), constant!("2")))),
+            expression!(prefix!(
+                "-",
+                paren!(infix!("+", constant!("1"), constant!("2")))
+            )),
         ];
         assert_matches(code, expected);
     }
@@ -1214,6 +3441,221 @@ TRUE && FALSE
         assert_matches(code, expected);
     }
 
+    #[test]
+    fn parses_native_pipes() {
+        let code = "\
+kbd |> subset(Layout == \"QWERTY\") |> summary()
+kbd |> lm(formula = Speed ~ Layout, data = _)";
+        let expected = vec![
+            expression!(pipe!(
+                pipe!(
+                    variable!("kbd"),
+                    call!(
+                        variable!("subset"),
+                        vec![(
+                            None,
+                            infix!("==", variable!("Layout"), constant!("\"QWERTY\""))
+                        )]
+                    )
+                ),
+                call!(variable!("summary"), vec![])
+            )),
+            expression!(pipe!(
+                variable!("kbd"),
+                call!(
+                    variable!("lm"),
+                    vec![
+                        (
+                            Some("formula".to_string()),
+                            two_sided_formula!(variable!("Speed"), variable!("Layout"))
+                        ),
+                        (Some("data".to_string()), variable!("_")),
+                    ]
+                )
+            )),
+        ];
+        assert_matches(code, expected);
+    }
+
+    #[test]
+    fn parses_lambdas() {
+        let code = "\
+sapply(xs, \\(x) x + 1)
+add <- \\(x, y = 1) {
+    x + y
+}";
+        let expected = vec![
+            expression!(call!(
+                variable!("sapply"),
+                vec![
+                    (None, variable!("xs")),
+                    (
+                        None,
+                        function!(
+                            vec![("x".to_string(), None)],
+                            vec![expression!(infix!("+", variable!("x"), constant!("1")))],
+                            FunctionSyntax::Lambda
+                        )
+                    ),
+                ]
+            )),
+            assignment!(
+                variable!("add"),
+                vec![],
+                function!(
+                    vec![
+                        ("x".to_string(), None),
+                        ("y".to_string(), Some(constant!("1"))),
+                    ],
+                    vec![expression!(infix!("+", variable!("x"), variable!("y")))],
+                    FunctionSyntax::Lambda
+                )
+            ),
+        ];
+        assert_matches(code, expected);
+    }
+
+    mod desugars_pipe {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[test]
+        fn into_first_argument() {
+            let result = desugar_pipe(
+                &variable!("x"),
+                &call!(variable!("f"), vec![(None, variable!("y"))]),
+            );
+            let expected = call!(
+                variable!("f"),
+                vec![(None, variable!("x")), (None, variable!("y"))]
+            );
+            assert_eq!(expected, result);
+        }
+
+        #[test]
+        fn into_named_placeholder() {
+            let result = desugar_pipe(
+                &variable!("x"),
+                &call!(
+                    variable!("f"),
+                    vec![
+                        (None, variable!("y")),
+                        (Some("data".into()), variable!("_"))
+                    ]
+                ),
+            );
+            let expected = call!(
+                variable!("f"),
+                vec![
+                    (None, variable!("y")),
+                    (Some("data".into()), variable!("x"))
+                ]
+            );
+            assert_eq!(expected, result);
+        }
+
+        #[test]
+        fn into_bare_function() {
+            let result = desugar_pipe(&variable!("x"), &variable!("f"));
+            let expected = call!(variable!("f"), vec![(None, variable!("x"))]);
+            assert_eq!(expected, result);
+        }
+    }
+
+    mod desugars_magrittr {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        fn desugar(code: &str) -> Option<Expression> {
+            let parsed = parse_statements(code).unwrap();
+            let stmt = parsed.into_iter().next().unwrap().0;
+            match &stmt.expression().unwrap().kind {
+                ExpressionKind::Infix(op, left, right) => desugar_magrittr(op, left, right),
+                _ => None,
+            }
+        }
+
+        #[test]
+        fn into_first_argument() {
+            let expected = call!(
+                variable!("f"),
+                vec![
+                    (None, variable!("x")),
+                    (None, call!(variable!("nrow"), vec![(None, variable!("x"))]))
+                ]
+            );
+            assert_eq!(Some(expected), desugar("x %>% f(nrow(.))"));
+            let expected = call!(variable!("f"), vec![(None, variable!("x"))]);
+            assert_eq!(Some(expected), desugar("x %>% f"));
+        }
+
+        #[test]
+        fn into_placeholder() {
+            let expected = call!(
+                variable!("t.test"),
+                vec![
+                    (
+                        None,
+                        two_sided_formula!(variable!("Speed"), variable!("Layout"))
+                    ),
+                    (Some("data".into()), variable!("kbd"))
+                ]
+            );
+            assert_eq!(
+                Some(expected),
+                desugar("kbd %>% t.test(Speed ~ Layout, data = .)")
+            );
+        }
+
+        #[test]
+        fn exposes_columns() {
+            let expected = call!(
+                variable!("with"),
+                vec![
+                    (None, variable!("kbd")),
+                    (
+                        None,
+                        call!(
+                            variable!("cor"),
+                            vec![(None, variable!("Speed")), (None, variable!("Errors"))]
+                        )
+                    )
+                ]
+            );
+            assert_eq!(Some(expected), desugar("kbd %$% cor(Speed, Errors)"));
+        }
+
+        #[test]
+        fn continues_after_tee() {
+            let expected = call!(variable!("plot"), vec![(None, variable!("x"))]);
+            assert_eq!(Some(expected), desugar("x %T>% plot()"));
+            let expected = call!(variable!("summary"), vec![(None, variable!("x"))]);
+            assert_eq!(
+                Some(expected.clone()),
+                desugar("x %T>% plot() %>% summary()")
+            );
+            assert_eq!(
+                Some(expected),
+                desugar("x %T>% print() %T>% plot() %>% summary()")
+            );
+        }
+
+        #[test]
+        fn ignores_other_operators() {
+            assert_eq!(None, desugar("x %in% y"));
+        }
+
+        #[test]
+        fn finds_assignment_target() {
+            let parsed = parse_statements("kbd %<>% filter(Speed > 0) %>% arrange(Speed)").unwrap();
+            let stmt = parsed.into_iter().next().unwrap().0;
+            assert_eq!(
+                Some(&variable!("kbd")),
+                pipe_assignment_target(stmt.expression().unwrap())
+            );
+        }
+    }
+
     #[test]
     fn parses_expression_in_parens() {
         let code = "\
@@ -1223,21 +3665,106 @@ TRUE && FALSE
 ((1 + 2) + 3)";
         let expected = vec![
             expression!(constant!("1")),
-            expression!(constant!("2")),
-            expression!(infix!(
+            expression!(paren!(constant!("2"))),
+            expression!(paren!(infix!(
                 "+",
                 constant!("1"),
-                infix!("+", constant!("2"), constant!("3"))
-            )),
-            expression!(infix!(
+                paren!(infix!("+", constant!("2"), constant!("3")))
+            ))),
+            expression!(paren!(infix!(
                 "+",
-                infix!("+", constant!("1"), constant!("2")),
+                paren!(infix!("+", constant!("1"), constant!("2"))),
                 constant!("3")
+            ))),
+        ];
+        assert_matches(code, expected);
+    }
+
+    #[test]
+    fn parses_operators_with_precedence() {
+        let code = "\
+a - b - c
+x == 1 & y > 2
+2^3^2
+-2^2
+-1:3
+!x == y
+a %in% b * c
+y ~ a + b | c
+a * (b + c)";
+        let expected = vec![
+            expression!(infix!(
+                "-",
+                infix!("-", variable!("a"), variable!("b")),
+                variable!("c")
+            )),
+            expression!(infix!(
+                "&",
+                infix!("==", variable!("x"), constant!("1")),
+                infix!(">", variable!("y"), constant!("2"))
+            )),
+            expression!(infix!(
+                "^",
+                constant!("2"),
+                infix!("^", constant!("3"), constant!("2"))
+            )),
+            expression!(prefix!("-", infix!("^", constant!("2"), constant!("2")))),
+            expression!(infix!(":", prefix!("-", constant!("1")), constant!("3"))),
+            expression!(prefix!("!", infix!("==", variable!("x"), variable!("y")))),
+            expression!(infix!(
+                "*",
+                infix!("%in%", variable!("a"), variable!("b")),
+                variable!("c")
+            )),
+            expression!(two_sided_formula!(
+                variable!("y"),
+                infix!(
+                    "|",
+                    infix!("+", variable!("a"), variable!("b")),
+                    variable!("c")
+                )
+            )),
+            expression!(infix!(
+                "*",
+                variable!("a"),
+                paren!(infix!("+", variable!("b"), variable!("c")))
             )),
         ];
         assert_matches(code, expected);
     }
 
+    #[test]
+    fn keeps_original_code() {
+        let code = "x = f(a,  # first\n  b)   # tail\ny <<- (1);z";
+        let stmts: Vec<Statement> = parse_statements(code)
+            .unwrap()
+            .into_iter()
+            .map(|(stmt, _)| stmt)
+            .collect();
+        assert_eq!(
+            vec!["x = f(a,  # first\n  b)   # tail", "y <<- (1)", "z"],
+            stmts.iter().map(|stmt| stmt.source()).collect::<Vec<_>>()
+        );
+        if let StatementKind::TailComment(inner, _) = &stmts[0].kind {
+            assert_eq!("x = f(a,  # first\n  b)", inner.source());
+        } else {
+            panic!("Expected a tail comment, but got {:?}.", stmts[0]);
+        }
+        assert_eq!("y <<- (1)", stmts[1].to_string());
+    }
+
+    #[test]
+    fn displays_parentheses() {
+        let code = "(a + b) * -(c - d) ^ 2";
+        let stmt = parse_statements(code)
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap()
+            .0;
+        assert_eq!(code, stmt.to_string());
+    }
+
     #[test]
     fn parses_if() {
         let code = "\
@@ -1303,6 +3830,60 @@ else (why <- 1)";
         assert_matches(code, expected);
     }
 
+    mod else_on_new_line {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[test]
+        fn merges_into_preceding_if() {
+            let mut parsed = Parsed::new();
+            let first = parsed.append(vec!["x <- 1", "if (a) {", "  1", "} else if (b) 2"]);
+            let second = parsed.append(vec!["else {", "  3", "}  ", "elsewhere"]);
+            assert_eq!(vec![StatementId(0), StatementId(1)], first);
+            assert_eq!(vec![StatementId(1), StatementId(2)], second);
+
+            let (merged, _) = &parsed.statements()[StatementId(1)];
+            assert_eq!(
+                if_stmt!(
+                    variable!("a"),
+                    vec![expression!(constant!("1"))],
+                    Some(vec![if_stmt!(
+                        variable!("b"),
+                        vec![expression!(constant!("2"))],
+                        Some(vec![expression!(constant!("3"))])
+                    )])
+                ),
+                *merged
+            );
+            assert_eq!((2, 7), (merged.span.start.line, merged.span.end.line));
+            assert_eq!(
+                Some("if (a) {\n  1\n} else if (b) 2\nelse {\n  3\n}"),
+                merged.source.as_deref()
+            );
+            assert!(parsed.diagnostics().is_empty());
+        }
+
+        #[test]
+        fn keeps_else_without_preceding_if_unparsed() {
+            let mut parsed = Parsed::new();
+            parsed.append(vec!["if (a) 1", "x", "else 2"]);
+            let stmts: Vec<Statement> = parsed
+                .statements()
+                .iter()
+                .map(|(_, stmt, _)| stmt.clone())
+                .collect();
+            assert_eq!(
+                vec![
+                    if_stmt!(variable!("a"), vec![expression!(constant!("1"))], None),
+                    expression!(variable!("x")),
+                    Statement::from(StatementKind::Unparsed("else 2".to_string())),
+                ],
+                stmts
+            );
+            assert_eq!(1, parsed.diagnostics().len());
+        }
+    }
+
     #[test]
     fn parses_for() {
         let code = "\
@@ -1379,6 +3960,435 @@ while (TRUE)
         assert_matches(code, expected);
     }
 
+    #[test]
+    fn parses_repeat() {
+        let code = "\
+repeat {
+    i <- i + 1
+    if (i > 10) break
+    next
+}
+repeat step()";
+        let expected = vec![
+            repeat_stmt!(vec![
+                assignment!(
+                    variable!("i"),
+                    vec![],
+                    infix!("+", variable!("i"), constant!("1"))
+                ),
+                if_stmt!(
+                    infix!(">", variable!("i"), constant!("10")),
+                    vec![break_stmt!()],
+                    None
+                ),
+                next_stmt!(),
+            ]),
+            repeat_stmt!(vec![expression!(call!(variable!("step"), vec![]))]),
+        ];
+        assert_matches(code, expected);
+    }
+
+    #[test]
+    fn parses_return() {
+        let code = "\
+f <- function(x) {
+    if (is.null(x)) return()
+    return(x + 1)
+}
+return_value <- 1
+next_value(return_value)";
+        let expected = vec![
+            assignment!(
+                variable!("f"),
+                vec![],
+                function!(
+                    vec![("x".to_string(), None)],
+                    vec![
+                        if_stmt!(
+                            call!(variable!("is.null"), vec![(None, variable!("x"))]),
+                            vec![return_stmt!()],
+                            None
+                        ),
+                        return_stmt!(infix!("+", variable!("x"), constant!("1"))),
+                    ]
+                )
+            ),
+            assignment!(variable!("return_value"), vec![], constant!("1")),
+            expression!(call!(
+                variable!("next_value"),
+                vec![(None, variable!("return_value"))]
+            )),
+        ];
+        assert_matches(code, expected);
+    }
+
+    #[test]
+    fn parses_switch() {
+        let code = "\
+switch(x, a = , b = \"ab\", \"other\")
+y <- switch(type,
+    mean = mean(z),
+    \"median\" = median(z),
+    stop(\"unknown\"))
+switch(x == 1, 2)";
+        let expected = vec![
+            expression!(switch!(
+                variable!("x"),
+                vec![
+                    (Some("a".to_string()), None),
+                    (Some("b".to_string()), Some(constant!("\"ab\""))),
+                    (None, Some(constant!("\"other\""))),
+                ]
+            )),
+            assignment!(
+                variable!("y"),
+                vec![],
+                switch!(
+                    variable!("type"),
+                    vec![
+                        (
+                            Some("mean".to_string()),
+                            Some(call!(variable!("mean"), vec![(None, variable!("z"))]))
+                        ),
+                        (
+                            Some("\"median\"".to_string()),
+                            Some(call!(variable!("median"), vec![(None, variable!("z"))]))
+                        ),
+                        (
+                            None,
+                            Some(call!(
+                                variable!("stop"),
+                                vec![(None, constant!("\"unknown\""))]
+                            ))
+                        ),
+                    ]
+                )
+            ),
+            expression!(switch!(
+                infix!("==", variable!("x"), constant!("1")),
+                vec![(None, Some(constant!("2")))]
+            )),
+        ];
+        assert_matches(code, expected);
+    }
+
+    #[test]
+    fn displays_control_flow() {
+        let code = "\
+switch(x, a = , b = \"ab\", \"other\")
+return(x)";
+        let displayed: Vec<String> = parse_statements(code)
+            .unwrap_or_else(|e| panic!("{}", e))
+            .into_iter()
+            .map(|(stmt, _)| stmt.to_string())
+            .collect();
+        assert_eq!(
+            vec!["switch(x, a =, b = \"ab\", \"other\")", "return(x)"],
+            displayed
+        );
+    }
+
+    mod incomplete {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        /// Appends the lines one by one and returns the reason for waiting after each of them.
+        fn reasons(code: &str) -> Vec<Option<String>> {
+            let mut parsed = Parsed::new();
+            code.lines()
+                .map(|line| {
+                    parsed.append(vec![line]);
+                    parsed.incomplete().map(|reason| reason.to_string())
+                })
+                .collect()
+        }
+
+        #[test]
+        fn waits_for_closing_brackets() {
+            let code = "\
+f <- function(x) {
+  y <- c(x,
+    x[[1]])
+  y
+}";
+            assert_eq!(
+                vec![
+                    Some("unclosed `{` at line 1".to_string()),
+                    Some("unclosed `(` at line 2".to_string()),
+                    Some("unclosed `{` at line 1".to_string()),
+                    Some("unclosed `{` at line 1".to_string()),
+                    None,
+                ],
+                reasons(code)
+            );
+        }
+
+        #[test]
+        fn waits_for_closing_quotes() {
+            let code = "\
+x <- \"first
+second (\\\" { \" # not a comment
+`my
+var` <- r\"-(\")\"
+)-\"";
+            assert_eq!(
+                vec![
+                    Some("unterminated `\"` at line 1".to_string()),
+                    None,
+                    Some("unterminated `` ` `` at line 3".to_string()),
+                    Some("unterminated `r\"-(` at line 4".to_string()),
+                    None,
+                ],
+                reasons(code)
+            );
+        }
+
+        #[test]
+        fn waits_for_continued_lines() {
+            let code = "\
+x <- a +
+  b
+if (x > 1)
+  y
+else
+  z
+g <- \\(x)
+  x |>
+  sum()";
+            assert_eq!(
+                vec![
+                    Some("operand of `+` missing at line 1".to_string()),
+                    None,
+                    Some("body of `if` missing at line 3".to_string()),
+                    None,
+                    Some("body of `else` missing at line 5".to_string()),
+                    None,
+                    Some("body of `\\` missing at line 7".to_string()),
+                    Some("operand of `|>` missing at line 8".to_string()),
+                    None,
+                ],
+                reasons(code)
+            );
+        }
+
+        #[test]
+        fn ignores_brackets_in_strings_and_comments() {
+            let code = "\
+x <- \"(\" # {
+y <- '[['";
+            assert_eq!(vec![None, None], reasons(code));
+        }
+
+        #[test]
+        fn parses_mismatched_brackets_right_away() {
+            let mut parsed = Parsed::new();
+            let ids = parsed.append(vec!["f(x]", "y <- 1"]);
+            assert_eq!(None, parsed.incomplete());
+            assert_eq!(2, ids.len());
+            assert_eq!(
+                StatementKind::Unparsed("f(x]".to_string()),
+                parsed.statements()[ids[0]].0.kind
+            );
+            assert_eq!(1, parsed.diagnostics().len());
+        }
+
+        #[test]
+        fn exposes_position_of_reason() {
+            let mut parsed = Parsed::new();
+            parsed.append(vec!["a <- 1", "  b <- list(x = 1,"]);
+            assert_eq!(
+                Some(Incomplete::Bracket {
+                    bracket: "(".to_string(),
+                    position: Position {
+                        offset: 18,
+                        line: 2,
+                        column: 12
+                    }
+                }),
+                parsed.incomplete()
+            );
+        }
+    }
+
+    mod recovery {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[test]
+        fn keeps_unparsed_statements() {
+            let code = "\
+a <- 1
+b <- )
+f(1,
+  2 +* 3)
+c <- a";
+            let (stmts, diagnostics) = parse_statements_recovering(code);
+            let stmts: Vec<Statement> = stmts.into_iter().map(|(stmt, _)| stmt).collect();
+            assert_eq!(
+                vec![
+                    assignment!(variable!("a"), vec![], constant!("1")),
+                    Statement::from(StatementKind::Unparsed("b <- )".to_string())),
+                    Statement::from(StatementKind::Unparsed("f(1,\n  2 +* 3)".to_string())),
+                    assignment!(variable!("c"), vec![], variable!("a")),
+                ],
+                stmts
+            );
+            assert_eq!(
+                vec![(2, 2), (3, 4)],
+                stmts[1..3]
+                    .iter()
+                    .map(|stmt| (stmt.span.start.line, stmt.span.end.line))
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!(
+                vec![(2, 6), (4, 6)],
+                diagnostics
+                    .iter()
+                    .map(|diagnostic| (diagnostic.span.start.line, diagnostic.span.start.column))
+                    .collect::<Vec<_>>()
+            );
+            assert!(diagnostics[0].expected.contains(&"expression".to_string()));
+        }
+
+        #[test]
+        fn appending_keeps_unparsed_statements() {
+            let code = "\
+a <- 1
+b <- )
+c <- a";
+            let mut parsed = Parsed::new();
+            parsed.append(code.lines().collect());
+            let stmts: Vec<Statement> = parsed
+                .into_statements()
+                .into_iter()
+                .map(|(stmt, _)| stmt)
+                .collect();
+            assert_eq!(
+                vec![
+                    assignment!(variable!("a"), vec![], constant!("1")),
+                    Statement::from(StatementKind::Unparsed("b <- )".to_string())),
+                    assignment!(variable!("c"), vec![], variable!("a")),
+                ],
+                stmts
+            );
+        }
+
+        #[test]
+        fn appending_reports_diagnostics() {
+            let mut parsed = Parsed::new();
+            parsed.append(vec!["a <- 1", "b <- )"]);
+            let diagnostic = &parsed.diagnostics()[0];
+            assert_eq!(
+                Position {
+                    offset: 12,
+                    line: 2,
+                    column: 6
+                },
+                diagnostic.span.start
+            );
+        }
+
+        #[test]
+        fn reports_targets_without_variables() {
+            let mut parsed = Parsed::new();
+            parsed.append(vec!["\"a\" <- 1", "for (i in 1:3) {", "  f() <- i", "}"]);
+            parsed.finish();
+            assert_eq!(2, parsed.statements().iter().count());
+            let diagnostic = &parsed.diagnostics()[0];
+            assert_eq!(1, parsed.diagnostics().len());
+            assert_eq!(
+                "Could not find a variable in the target f().",
+                diagnostic.message
+            );
+            assert_eq!(
+                (3, 3),
+                (diagnostic.span.start.line, diagnostic.span.start.column)
+            );
+        }
+    }
+
+    mod visit {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        fn parse_single(code: &str) -> Statement {
+            parse_statements(code)
+                .unwrap()
+                .into_iter()
+                .next()
+                .unwrap()
+                .0
+        }
+
+        struct Variables(Vec<RIdentifier>);
+
+        impl Visit for Variables {
+            fn visit_expression(&mut self, exp: &Expression) {
+                if let ExpressionKind::Variable(name) = &exp.kind {
+                    self.0.push(name.clone());
+                }
+                walk_expression(self, exp)
+            }
+        }
+
+        #[test]
+        fn visits_all_nodes_in_order() {
+            let stmt = parse_single(
+                "if (a) { f <- function(x, y = b) { x[c] + y@d } } else repeat switch(e, g = h) # i",
+            );
+            let mut variables = Variables(Vec::new());
+            variables.visit_statement(&stmt);
+            assert_eq!(
+                vec!["a", "f", "b", "x", "c", "y", "d", "e", "h"],
+                variables.0
+            );
+        }
+
+        struct Rename;
+
+        impl VisitMut for Rename {
+            fn visit_expression_mut(&mut self, exp: &mut Expression) {
+                if let ExpressionKind::Variable(name) = &mut exp.kind {
+                    name.make_ascii_uppercase();
+                }
+                walk_expression_mut(self, exp)
+            }
+        }
+
+        #[test]
+        fn changes_nodes_in_place() {
+            let mut stmt = parse_single("for (i in seq(n)) total <- total + i");
+            Rename.visit_statement_mut(&mut stmt);
+            assert_eq!(
+                "for (I in SEQ(N)) {\nTOTAL <- TOTAL + I\n}",
+                Statement::from(stmt.kind).to_string()
+            );
+        }
+
+        struct Unparen;
+
+        impl Fold for Unparen {
+            fn fold_expression(&mut self, exp: Expression) -> Expression {
+                match exp.kind {
+                    ExpressionKind::Paren(inner) => self.fold_expression(*inner),
+                    _ => fold_expression(self, exp),
+                }
+            }
+        }
+
+        #[test]
+        fn rebuilds_folded_nodes() {
+            let stmt = parse_single("x <- ((a))[(2)] + lapply(l, function(b = (1)) (b))");
+            let folded = Unparen.fold_statement(stmt.clone());
+            assert_eq!(
+                "x <- a[2] + lapply(l, function (b = 1) {\nb\n})",
+                Statement::from(folded.kind.clone()).to_string()
+            );
+            assert_eq!(stmt.span, folded.span);
+            assert_eq!(stmt.source, folded.source);
+        }
+    }
+
     mod ids {
         use super::*;
         use pretty_assertions::assert_eq;
@@ -1468,21 +4478,51 @@ sixth()";
             assert_matches(Some("x"), name);
         }
 
+        #[test]
+        fn from_slot() {
+            let name = slot!(variable!("x"), variable!("a"));
+            assert_matches(Some("x"), name);
+        }
+
+        #[test]
+        fn rejects_namespaced() {
+            let name = namespaced!("stats", "lm", false);
+            assert_matches(None, name);
+        }
+
         #[test]
         fn from_colnames() {
             let name = call!(variable!("colnames"), vec![(None, variable!("x"))]);
             assert_matches(Some("x"), name);
         }
 
+        #[test]
+        fn from_replacement_functions() {
+            let name = call!(
+                variable!("attr"),
+                vec![(None, variable!("x")), (None, constant!("\"units\""))]
+            );
+            assert_matches(Some("x"), name);
+        }
+
+        #[test]
+        fn from_string_targets() {
+            assert_eq!(
+                Some("a".to_string()),
+                constant!("\"a\"").assigned_variable_name()
+            );
+            assert_eq!(None, constant!("1").assigned_variable_name());
+        }
+
         #[test]
         fn rejects_constants() {
-            let name = constant!("x");
+            let name = constant!("\"x\"");
             assert_matches(None, name);
         }
 
         #[test]
         fn rejects_constant_in_column() {
-            let name = column!(constant!("x"), variable!("a"));
+            let name = column!(constant!("\"x\""), variable!("a"));
             assert_matches(None, name);
         }
     }
@@ -1491,6 +4531,14 @@ sixth()";
         use super::*;
         use pretty_assertions::assert_eq;
 
+        fn lines(from: usize, to: usize) -> LineSpan {
+            LineSpan {
+                from,
+                to,
+                file: None,
+            }
+        }
+
         #[test]
         fn associates_correct_line_number() {
             let code = "\
@@ -1502,14 +4550,7 @@ if (third)
             let stmts =
                 parse_statements(code).unwrap_or_else(|e| panic!("Failed to parse code:\n{}", e));
             let spans: Vec<LineSpan> = stmts.into_iter().map(|(_, span)| span).collect();
-            assert_eq!(
-                vec![
-                    LineSpan { from: 1, to: 1 },
-                    LineSpan { from: 2, to: 2 },
-                    LineSpan { from: 3, to: 4 }
-                ],
-                spans
-            );
+            assert_eq!(vec![lines(1, 1), lines(2, 2), lines(3, 4)], spans);
         }
 
         #[test]
@@ -1526,14 +4567,7 @@ if (third)
                 .iter()
                 .map(|id| parsed.statements()[id].1.clone())
                 .collect();
-            assert_eq!(
-                vec![
-                    LineSpan { from: 1, to: 1 },
-                    LineSpan { from: 2, to: 2 },
-                    LineSpan { from: 3, to: 4 }
-                ],
-                spans
-            );
+            assert_eq!(vec![lines(1, 1), lines(2, 2), lines(3, 4)], spans);
 
             let more_code = "\
 # Fifth
@@ -1543,9 +4577,80 @@ sixth()";
                 .iter()
                 .map(|id| parsed.statements()[id].1.clone())
                 .collect();
+            assert_eq!(vec![lines(5, 5), lines(6, 6),], spans);
+        }
+    }
+
+    mod span {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        /// Returns the code covered by the `span`.
+        fn covered(code: &str, span: Span) -> &str {
+            &code[span.start.offset..span.end.offset]
+        }
+
+        /// Returns the first argument of the call in the `statement`.
+        fn first_argument(statement: &Statement) -> &Expression {
+            match &statement.expression().unwrap().kind {
+                ExpressionKind::Call(_, args) => &args[0].1,
+                other => panic!("Expected a call, but got {:?}.", other),
+            }
+        }
+
+        #[test]
+        fn covers_nested_expressions() {
+            let code = "x <- 1\nhist(kbd$Layout == \"QWERTY\")";
+            let stmts = parse_statements(code).unwrap();
+            let (statement, _) = &stmts[StatementId(1)];
+            assert_eq!(code.lines().nth(1).unwrap(), covered(code, statement.span));
+
+            let argument = first_argument(statement);
+            assert_eq!("kbd$Layout == \"QWERTY\"", covered(code, argument.span));
+            assert_eq!(
+                Position {
+                    offset: 12,
+                    line: 2,
+                    column: 6
+                },
+                argument.span.start
+            );
+            if let ExpressionKind::Infix(_, left, _) = &argument.kind {
+                assert_eq!("kbd$Layout", covered(code, left.span));
+            } else {
+                panic!("Expected an infix operator, but got {:?}.", argument);
+            }
+        }
+
+        #[test]
+        fn successive_appends_shift_spans() {
+            let code = "\
+a <- 1
+plot(
+  a
+)
+hist(b)";
+            let mut parsed = Parsed::new();
+            let mut inserted = parsed.append(code.lines().take(2).collect());
+            inserted.append(&mut parsed.append(code.lines().skip(2).collect()));
+            let spans: Vec<Span> = inserted
+                .iter()
+                .skip(1)
+                .map(|id| first_argument(&parsed.statements()[id].0).span)
+                .collect();
+            assert_eq!(
+                vec!["a", "b"],
+                spans
+                    .iter()
+                    .map(|span| covered(code, *span))
+                    .collect::<Vec<_>>()
+            );
             assert_eq!(
-                vec![LineSpan { from: 5, to: 5 }, LineSpan { from: 6, to: 6 },],
+                vec![(3, 3), (5, 6)],
                 spans
+                    .iter()
+                    .map(|span| (span.start.line, span.start.column))
+                    .collect::<Vec<_>>()
             );
         }
     }
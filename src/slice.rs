@@ -0,0 +1,260 @@
+use std::collections::BTreeSet;
+
+use crate::dependency_graph::DependencyGraph;
+use crate::hypotheses::{detect_hypotheses, Hypothesis};
+use crate::parser::{
+    Expression, ExpressionKind, LineSpan, RIdentifier, Statement, StatementId, StatementKind,
+    Statements,
+};
+
+/// Functions whose calls prepare the session for the code that follows without defining variables,
+/// e. g. by attaching packages or seeding the random number generator.
+const SETUP_FUNCTIONS: &[&str] = &[
+    "library",
+    "require",
+    "requireNamespace",
+    "set.seed",
+    "RNGkind",
+    "options",
+    "Sys.setenv",
+    "Sys.setlocale",
+];
+
+/// Functions that are wrapped around setup calls to silence them, e. g. `suppressPackageStartupMessages(library(dplyr))`.
+const QUIET_FUNCTIONS: &[&str] = &[
+    "suppressPackageStartupMessages",
+    "suppressMessages",
+    "suppressWarnings",
+    "invisible",
+];
+
+/// What to slice the code for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// The statements on the line of the main file, starting at 1.
+    Line(usize),
+    /// The statement with the id.
+    Statement(StatementId),
+    /// The statement that defines the variable last.
+    Variable(RIdentifier),
+    /// The statements that test the hypothesis, e. g. `Speed ~ Layout`, including through the variables they use.
+    Hypothesis(Hypothesis),
+}
+
+/// Returns the statements the `target` refers to, ordered by id.
+///
+/// The main file is the file of the first statement, which is the script that sources all others.
+pub fn find_targets<M>(
+    target: &Target,
+    stmts: &Statements<(LineSpan, M)>,
+    dependency_graph: &DependencyGraph,
+) -> Vec<StatementId> {
+    match target {
+        Target::Line(line) => {
+            let main_file = stmts
+                .iter()
+                .next()
+                .and_then(|(_, _, (span, _))| span.file());
+            stmts
+                .iter()
+                .filter(|(_, _, (span, _))| span.file() == main_file && span.contains_line(*line))
+                .map(|(id, _, _)| id)
+                .collect()
+        }
+        Target::Statement(target) => stmts
+            .iter()
+            .map(|(id, _, _)| id)
+            .filter(|id| id == target)
+            .collect(),
+        Target::Variable(variable) => dependency_graph
+            .defining_statements(variable)
+            .last()
+            .into_iter()
+            .copied()
+            .collect(),
+        Target::Hypothesis(hypothesis) => {
+            let wanted = without_whitespace(hypothesis);
+            stmts
+                .iter()
+                .map(|(id, _, _)| id)
+                .filter(|id| {
+                    dependency_graph
+                        .inline_expressions(*id, stmts)
+                        .iter()
+                        .flat_map(detect_hypotheses)
+                        .any(|found| without_whitespace(&found) == wanted)
+                })
+                .collect()
+        }
+    }
+}
+
+fn without_whitespace(code: &str) -> String {
+    code.split_whitespace().collect()
+}
+
+/// Returns the statements needed to run the `targets` on their own, ordered by id.
+///
+/// Like the reproduction code copied in the viewer, these are the targets and all statements they depend on.
+/// Additionally, the setup statements that run before the last target are kept along with their dependencies,
+/// since the targets might call functions of the attached packages or draw random numbers.
+/// Note that the random numbers still differ from the full script if dropped statements draw random numbers as well.
+pub fn slice<M>(
+    targets: &[StatementId],
+    stmts: &Statements<M>,
+    dependency_graph: &DependencyGraph,
+) -> Vec<StatementId> {
+    let last = match targets.iter().max() {
+        Some(last) => *last,
+        None => return Vec::new(),
+    };
+    let setup = setup_statements(stmts).into_iter().filter(|id| *id < last);
+    let mut sliced = BTreeSet::new();
+    for id in targets.iter().copied().chain(setup) {
+        sliced.insert(id);
+        sliced.extend(dependency_graph.ancestors(id));
+    }
+    sliced.into_iter().collect()
+}
+
+/// Returns the statements that attach packages or change the state of the session, e. g. `library(dplyr)` or `set.seed(42)`.
+pub fn setup_statements<M>(stmts: &Statements<M>) -> Vec<StatementId> {
+    stmts
+        .iter()
+        .filter(|(_, stmt, _)| is_setup(stmt))
+        .map(|(id, _, _)| id)
+        .collect()
+}
+
+fn is_setup(stmt: &Statement) -> bool {
+    match &stmt.kind {
+        StatementKind::Library(_) => true,
+        StatementKind::Expression(expression) => is_setup_call(expression),
+        StatementKind::TailComment(stmt, _) => is_setup(stmt),
+        _ => false,
+    }
+}
+
+fn is_setup_call(expression: &Expression) -> bool {
+    match &expression.kind {
+        ExpressionKind::Call(function, args) => match function.function_name() {
+            Some(name) if SETUP_FUNCTIONS.contains(&name) => true,
+            Some(name) if QUIET_FUNCTIONS.contains(&name) => {
+                args.iter().any(|(_, arg)| is_setup_call(arg))
+            }
+            _ => false,
+        },
+        ExpressionKind::Paren(expression) => is_setup_call(expression),
+        _ => false,
+    }
+}
+
+/// Writes the code of the statements with `ids` as a script, one statement after another as they were written.
+pub fn script<M>(ids: &[StatementId], stmts: &Statements<M>) -> String {
+    ids.iter()
+        .map(|id| format!("{}\n", stmts[id].0.source()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::parser::parse_statements;
+
+    const ANALYSIS: &str = "\
+library(dplyr)
+set.seed(42)
+kbd <- read.csv(\"kbd.csv\")
+noise <- rnorm(10)
+fast <- filter(kbd, Speed > 100)
+model <- lm(Speed ~ Layout, data = fast)
+summary(model)
+plot(noise)";
+
+    /// Slices the `code` for the `target` and returns the script.
+    fn slice_code(code: &str, target: Target) -> String {
+        let stmts = parse_statements(code)
+            .unwrap()
+            .into_map(&mut |_, span| (span, ()));
+        let dependency_graph = DependencyGraph::from_input(&stmts);
+        let targets = find_targets(&target, &stmts, &dependency_graph);
+        script(&slice(&targets, &stmts, &dependency_graph), &stmts)
+    }
+
+    #[test]
+    fn slices_for_a_line() {
+        assert_eq!(
+            "\
+library(dplyr)
+set.seed(42)
+kbd <- read.csv(\"kbd.csv\")
+fast <- filter(kbd, Speed > 100)
+model <- lm(Speed ~ Layout, data = fast)
+summary(model)
+",
+            slice_code(ANALYSIS, Target::Line(7))
+        );
+    }
+
+    #[test]
+    fn slices_for_a_variable_or_hypothesis() {
+        let for_fast = "\
+library(dplyr)
+set.seed(42)
+kbd <- read.csv(\"kbd.csv\")
+fast <- filter(kbd, Speed > 100)
+";
+        assert_eq!(
+            for_fast,
+            slice_code(ANALYSIS, Target::Variable("fast".into()))
+        );
+        assert_eq!(
+            format!(
+                "{}model <- lm(Speed ~ Layout, data = fast)\nsummary(model)\n",
+                for_fast
+            ),
+            slice_code(ANALYSIS, Target::Hypothesis("Speed~Layout".into()))
+        );
+    }
+
+    #[test]
+    fn keeps_only_setup_before_the_target() {
+        let code = "\
+x <- 1
+suppressPackageStartupMessages(library(ggplot2))
+y <- x + 1";
+        assert_eq!("x <- 1\n", slice_code(code, Target::Statement(0.into())));
+        assert_eq!(code.to_string() + "\n", slice_code(code, Target::Line(3)));
+        assert_eq!("", slice_code(code, Target::Variable("z".into())));
+    }
+
+    #[test]
+    fn keeps_variables_in_data_masked_arguments() {
+        let code = "\
+threshold <- 100
+kbd <- read.csv(\"kbd.csv\")
+fast <- subset(kbd, Speed > threshold)";
+        assert_eq!(code.to_string() + "\n", slice_code(code, Target::Line(3)));
+    }
+
+    #[test]
+    fn keeps_definitions_used_in_a_data_masked_target() {
+        let code = "\
+library(dplyr)
+cutoff <- 100
+kbd <- read.csv(\"kbd.csv\")
+noise <- rnorm(10)
+fast <- kbd %>% filter(Speed > cutoff)";
+        assert_eq!(
+            "\
+library(dplyr)
+cutoff <- 100
+kbd <- read.csv(\"kbd.csv\")
+fast <- kbd %>% filter(Speed > cutoff)
+",
+            slice_code(code, Target::Line(5))
+        );
+    }
+}
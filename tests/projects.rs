@@ -0,0 +1,219 @@
+extern crate tractus;
+
+use std::path;
+
+use pretty_assertions::assert_eq;
+use serde_json::Value;
+
+fn project_file(project: &str, file: &str) -> path::PathBuf {
+    path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/projects")
+        .join(project)
+        .join(file)
+}
+
+fn parse_project(project: &str, main: &str) -> Value {
+    let mut tractus = tractus::Tractus::new();
+    tractus.parse_file(&project_file(project, main)).unwrap();
+    tractus.serialize()
+}
+
+/// Returns the file relative to the project, the first line and the code of each statement in order.
+fn statements(result: &Value, project: &str) -> Vec<(String, u64, String)> {
+    let statements = result["statements"].as_object().unwrap();
+    let mut statements: Vec<(usize, String, u64, String)> = statements
+        .iter()
+        .map(|(id, stmt)| {
+            let file = path::Path::new(stmt["span"]["file"].as_str().unwrap());
+            let file = file.strip_prefix(project_file(project, "")).unwrap();
+            (
+                id.parse().unwrap(),
+                file.display().to_string(),
+                stmt["span"]["from"].as_u64().unwrap(),
+                stmt["statement"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect();
+    statements.sort();
+    statements
+        .into_iter()
+        .map(|(_, file, line, stmt)| (file, line, stmt))
+        .collect()
+}
+
+#[test]
+fn runs_sourced_statements_in_order() {
+    let result = parse_project("sourcing", "main.R");
+    let statements = statements(&result, "sourcing");
+    let statements: Vec<(&str, &str)> = statements
+        .iter()
+        .map(|(file, _, stmt)| (file.as_str(), stmt.as_str()))
+        .collect();
+    assert_eq!(
+        vec![
+            ("main.R", "# Runs the whole analysis."),
+            ("main.R", "source(\"01-load.R\")"),
+            ("01-load.R", "raw <- read.csv(\"keyboard.csv\")"),
+            ("main.R", "source(file = 'scripts/02-clean.R')"),
+            ("scripts/02-clean.R", "clean <- subset(raw, !is.na(speed))"),
+            (
+                "scripts/02-clean.R",
+                "source(\"02-clean.R\") # Sourcing itself is a cycle."
+            ),
+            ("main.R", "source(\"missing.R\")"),
+            ("main.R", "model <- lm(speed ~ layout, data = clean)"),
+        ],
+        statements
+    );
+}
+
+#[test]
+fn connects_dependencies_across_files() {
+    let result = parse_project("sourcing", "main.R");
+    assert_eq!(
+        serde_json::json!([[2, 4, "raw", null], [4, 7, "clean", null]]),
+        result["dependencies"]["edges"]
+    );
+}
+
+#[test]
+fn reports_cycles_and_missing_files() {
+    let result = parse_project("sourcing", "main.R");
+    let diagnostics: Vec<(String, u64, bool)> = result["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|diagnostic| {
+            let file = path::Path::new(diagnostic["file"].as_str().unwrap());
+            (
+                file.file_name().unwrap().to_string_lossy().into_owned(),
+                diagnostic["span"]["start"]["line"].as_u64().unwrap(),
+                diagnostic["message"]
+                    .as_str()
+                    .unwrap()
+                    .contains("already being sourced"),
+            )
+        })
+        .collect();
+    assert_eq!(
+        vec![
+            ("02-clean.R".to_string(), 2, true),
+            ("main.R".to_string(), 4, false),
+        ],
+        diagnostics
+    );
+}
+
+#[test]
+fn lists_the_files_to_watch() {
+    let mut tractus = tractus::Tractus::new();
+    tractus
+        .parse_file(&project_file("sourcing", "main.R"))
+        .unwrap();
+    let files: Vec<path::PathBuf> = tractus
+        .files()
+        .iter()
+        .map(|file| {
+            file.strip_prefix(project_file("sourcing", ""))
+                .unwrap()
+                .to_path_buf()
+        })
+        .collect();
+    // The missing file is watched, too, since it might be created.
+    assert_eq!(
+        vec![
+            path::PathBuf::from("main.R"),
+            path::PathBuf::from("01-load.R"),
+            path::PathBuf::from("scripts/02-clean.R"),
+            path::PathBuf::from("missing.R"),
+        ],
+        files
+    );
+}
+
+#[test]
+fn reads_evaluated_r_chunks_of_documents() {
+    let result = parse_project("rmarkdown", "report.Rmd");
+    let statements = statements(&result, "rmarkdown");
+    let statements: Vec<(&str, u64)> = statements
+        .iter()
+        .map(|(file, line, _)| (file.as_str(), *line))
+        .collect();
+    assert_eq!(
+        vec![
+            ("report.Rmd", 9),
+            ("load.R", 1),
+            ("report.Rmd", 18),
+            ("report.Rmd", 20),
+            ("report.Rmd", 34),
+        ],
+        statements
+    );
+}
+
+#[test]
+fn titles_blocks_with_chunks() {
+    let result = parse_project("rmarkdown", "report.Rmd");
+    assert_eq!(
+        serde_json::json!({
+            "0": "Loading (load)",
+            "1": "Cleaning (clean)",
+            "2": "Models",
+        }),
+        result["hypothesis_tree"]["titles"]
+    );
+}
+
+#[test]
+fn runs_notebook_cells_in_execution_order() {
+    let result = parse_project("notebook", "analysis.ipynb");
+    let statements = statements(&result, "notebook");
+    let statements: Vec<(u64, &str)> = statements
+        .iter()
+        .map(|(_, line, stmt)| (*line, stmt.as_str()))
+        .collect();
+    assert_eq!(
+        vec![
+            (1, "raw <- read.csv(\"keyboard.csv\")"),
+            (1, "clean <- subset(raw, !is.na(speed))"),
+            (2, "nrow(clean)"),
+            (1, "model <- lm(speed ~ layout,\n            data = clean)"),
+            (3, "print(coef(model))"),
+            (4, "plot(model)"),
+        ],
+        statements
+    );
+    assert_eq!(
+        serde_json::json!([
+            [0, 1, "raw", null],
+            [1, 2, "clean", null],
+            [1, 3, "clean", null],
+            [3, 4, "model", null],
+            [3, 5, "model", null]
+        ]),
+        result["dependencies"]["edges"]
+    );
+}
+
+#[test]
+fn attaches_cell_outputs_as_results() {
+    let result = parse_project("notebook", "analysis.ipynb");
+    let meta = |id: &str| result["statements"][id]["meta"].clone();
+    assert_eq!(
+        serde_json::json!({"result": "", "cell": 2, "execution_count": 1}),
+        meta("0")
+    );
+    assert_eq!("[1] 42", meta("2")["result"]);
+    assert_eq!(
+        "Coefficients:\n(Intercept)  layoutQWERTY\nError in plot.new(): figure margins too large",
+        meta("5")["result"]
+    );
+    assert_eq!(
+        serde_json::json!({
+            "0": "Keyboards (In [1])",
+            "1": "Keyboards (In [2])",
+            "2": "Models (In [3])",
+        }),
+        result["hypothesis_tree"]["titles"]
+    );
+}
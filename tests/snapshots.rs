@@ -25,15 +25,59 @@ fn hidden_snapshots() {
         .unwrap_or_default(); // Is allowed to fail when directory is missing.
 }
 
+#[test]
+fn round_trips() {
+    let snapshot_dir = get_snapshot_dir("tests/snapshots");
+    let snapshot_files = find_snapshot_files(&snapshot_dir).unwrap();
+    for snapshot_file in snapshot_files {
+        let code = fs::read_to_string(&snapshot_file).unwrap();
+
+        let parsed = tractus::parser::parse_statements(&code).unwrap();
+        let statements = parsed.into_iter().map(|(stmt, _)| stmt);
+        assert_round_trip(&code, statements, &snapshot_file);
+
+        let mut parsed = tractus::Parsed::new();
+        parsed.append(code.lines().collect());
+        let statements = parsed.into_statements().into_iter().map(|(stmt, _)| stmt);
+        assert_round_trip(&code, statements, &snapshot_file);
+    }
+}
+
+/// Asserts that printing the `statements` in their original form, separated by the code between them, reproduces the `code`.
+fn assert_round_trip(
+    code: &str,
+    statements: impl Iterator<Item = tractus::Statement>,
+    snapshot_path: &path::Path,
+) {
+    let mut printed = String::new();
+    let mut end = 0;
+    for stmt in statements {
+        let separator = &code[end..stmt.span.start.offset];
+        assert!(
+            separator.chars().all(|c| c.is_whitespace() || c == ';'),
+            "Code {:?} in file {} is not part of any statement.",
+            separator,
+            snapshot_path.display()
+        );
+        printed.push_str(separator);
+        printed.push_str(&stmt.source());
+        end = stmt.span.end.offset;
+    }
+    printed.push_str(&code[end..]);
+    assert_eq!(
+        code,
+        printed,
+        "Failed for file {}.",
+        snapshot_path.display()
+    );
+}
+
 fn get_snapshot_dir<P: std::convert::AsRef<std::path::Path>>(relative_path: P) -> path::PathBuf {
     path::Path::new(env!("CARGO_MANIFEST_DIR")).join(relative_path)
 }
 
-fn test_snapshots_in(
-    snapshot_dir: &path::PathBuf,
-    maybe_prefix: Option<&'static str>,
-) -> Result<(), std::io::Error> {
-    let snapshot_files: Vec<path::PathBuf> = fs::read_dir(&snapshot_dir)?
+fn find_snapshot_files(snapshot_dir: &path::PathBuf) -> Result<Vec<path::PathBuf>, std::io::Error> {
+    let snapshot_files: Vec<path::PathBuf> = fs::read_dir(snapshot_dir)?
         .filter_map(|maybe_entry| {
             maybe_entry
                 .map(|entry| {
@@ -48,6 +92,14 @@ fn test_snapshots_in(
         })
         .collect();
     assert!(!snapshot_files.is_empty(), "No snapshot files were found!");
+    Ok(snapshot_files)
+}
+
+fn test_snapshots_in(
+    snapshot_dir: &path::PathBuf,
+    maybe_prefix: Option<&'static str>,
+) -> Result<(), std::io::Error> {
+    let snapshot_files = find_snapshot_files(snapshot_dir)?;
 
     let mut failures = Vec::new();
     for snapshot_file in snapshot_files {
@@ -55,7 +107,7 @@ fn test_snapshots_in(
         if result.is_err() {
             failures.push(snapshot_file.display().to_string());
         }
-        }
+    }
 
     if !failures.is_empty() {
         panic!("{} files failed:\n{}", failures.len(), failures.join(", "));
@@ -65,7 +117,7 @@ fn test_snapshots_in(
 }
 
 fn test_snapshot(snapshot_path: &path::PathBuf, maybe_prefix: Option<&'static str>) {
-    let mut file = fs::File::open(&snapshot_path).unwrap();
+    let mut file = fs::File::open(snapshot_path).unwrap();
     let mut code = String::new();
     file.read_to_string(&mut code).unwrap();
 